@@ -0,0 +1,31 @@
+use crate::AppState;
+use crate::modules::arp_watch::{IpHistory, SpoofAlert};
+use tauri::State;
+
+fn watch_unavailable() -> String {
+    "ARP spoofing detection needs the packet monitor, which is not available".to_string()
+}
+
+/// Hosts seen claiming addresses that other hosts held, most recent first
+#[tauri::command]
+pub async fn get_arp_alerts(state: State<'_, AppState>) -> Result<Vec<SpoofAlert>, String> {
+    let packet_monitor = state.packet_monitor.lock().await;
+    let monitor = packet_monitor.as_ref().ok_or_else(watch_unavailable)?;
+    Ok(monitor.arp_watch().get_alerts().await)
+}
+
+#[tauri::command]
+pub async fn clear_arp_alerts(state: State<'_, AppState>) -> Result<(), String> {
+    let packet_monitor = state.packet_monitor.lock().await;
+    let monitor = packet_monitor.as_ref().ok_or_else(watch_unavailable)?;
+    monitor.arp_watch().clear_alerts().await;
+    Ok(())
+}
+
+/// Every MAC each IP has been claimed by since startup
+#[tauri::command]
+pub async fn get_arp_history(state: State<'_, AppState>) -> Result<Vec<IpHistory>, String> {
+    let packet_monitor = state.packet_monitor.lock().await;
+    let monitor = packet_monitor.as_ref().ok_or_else(watch_unavailable)?;
+    Ok(monitor.arp_watch().get_history().await)
+}
@@ -0,0 +1,28 @@
+use crate::AppState;
+use crate::modules::database::{AuditPage, AuditVerification};
+use tauri::State;
+
+/// Control actions taken by the user, schedules and quotas, newest first
+#[tauri::command]
+pub async fn get_audit_log(
+    state: State<'_, AppState>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<AuditPage, String> {
+    let database = state.database.lock().await;
+    database.get_audit_log(limit, offset).await
+        .map_err(|e| format!("Failed to load audit log: {}", e))
+}
+
+/// Check that no audit entry has been altered or removed
+#[tauri::command]
+pub async fn verify_audit_log(state: State<'_, AppState>) -> Result<AuditVerification, String> {
+    let database = state.database.lock().await;
+    let verification = database.verify_audit_log().await
+        .map_err(|e| format!("Failed to verify audit log: {}", e))?;
+
+    if let Some(id) = verification.first_broken_id {
+        log::warn!("Audit log chain is broken at entry {}", id);
+    }
+    Ok(verification)
+}
@@ -0,0 +1,87 @@
+use crate::AppState;
+use crate::modules::database::Database;
+use std::path::PathBuf;
+use tauri::State;
+
+#[tauri::command]
+pub async fn backup_database(
+    state: State<'_, AppState>,
+    path: Option<String>,
+) -> Result<String, String> {
+    let database = state.database.lock().await;
+
+    // Default to a timestamped file in a backups folder next to the database
+    let dest = match path.filter(|p| !p.is_empty()) {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let dir = database.path()
+                .parent()
+                .map(|p| p.join("backups"))
+                .unwrap_or_else(|| PathBuf::from("backups"));
+            std::fs::create_dir_all(&dir)
+                .map_err(|e| format!("Failed to create backup folder: {}", e))?;
+            dir.join(format!("netsnip-{}.db", chrono::Local::now().format("%Y%m%d-%H%M%S")))
+        }
+    };
+
+    database.backup_to(&dest).await
+        .map_err(|e| format!("Failed to back up database: {}", e))?;
+
+    log::info!("Database backed up to {}", dest.display());
+
+    Ok(dest.to_string_lossy().into_owned())
+}
+
+/// Replace the database with a backup. The scheduler, quota enforcement and
+/// bandwidth sampler are stopped first so their in-memory state is not
+/// written over the restored data; they pick it up when NetSnip restarts.
+#[tauri::command]
+pub async fn restore_database(
+    state: State<'_, AppState>,
+    path: String,
+) -> Result<String, String> {
+    if path.is_empty() {
+        return Err("Invalid backup path".to_string());
+    }
+
+    let source = PathBuf::from(&path);
+
+    // Validate before taking the lock so a bad file fails fast
+    Database::validate_file(&source).await
+        .map_err(|e| format!("Invalid backup file: {}", e))?;
+
+    // The cut journal of the backup knows nothing of devices cut now, so
+    // they could not be recovered after the restart
+    let arp = state.arp_controller.lock().await;
+    let redirected = arp.get_cut_devices().await.len() + arp.get_forwarded_devices().await.len();
+    drop(arp);
+    if redirected > 0 {
+        return Err(format!("Failed to restore database: restore the {} cut or limited device(s) first", redirected));
+    }
+
+    state.database.lock().await.check_restore_source(&source).await
+        .map_err(|e| format!("Invalid backup file: {}", e))?;
+
+    // Anything failing from here on is unexpected, so the services are
+    // left stopped rather than restarted over a half-restored database
+    stop_services(&state).await;
+
+    state.database.lock().await.restore_from(&source).await
+        .map_err(|e| format!("Failed to restore database: {}. Restart NetSnip before continuing.", e))?;
+
+    log::info!("Database restored from {}", path);
+
+    Ok("Database restored. Restart NetSnip to load it.".to_string())
+}
+
+async fn stop_services(state: &AppState) {
+    if let Err(e) = state.scheduler.lock().await.stop().await {
+        log::warn!("Could not stop scheduler: {}", e);
+    }
+    if let Err(e) = state.quota_manager.lock().await.stop_enforcement().await {
+        log::warn!("Could not stop quota enforcement: {}", e);
+    }
+    if let Err(e) = state.bandwidth_history.lock().await.stop_sampling().await {
+        log::warn!("Could not stop bandwidth history sampling: {}", e);
+    }
+}
@@ -2,11 +2,109 @@ use crate::AppState;
 use serde::{Deserialize, Serialize};
 use tauri::State;
 use super::network::Device;
+use super::events::record_event;
+use crate::modules::audit::{self, AuditOutcome, CONFIRMATION_TTL};
+use crate::modules::bandwidth::{BandwidthLimit, BandwidthStats};
+use crate::modules::database::{AuditRecord, CutMode, EventType};
+use crate::modules::policy::{ControlAction, ControlPolicy, Refusal};
+use crate::modules::scanner::NetworkDevice;
+use crate::utils::safety::{device_id_for_mac, mac_for_device_id, normalize_mac};
+use std::net::Ipv4Addr;
+use std::time::{Duration, SystemTime};
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_NOTES_LEN: usize = 2000;
+const MAX_REASON_LEN: usize = 500;
+
+/// Longest timed cut, one week
+const MAX_CUT_MINUTES: u32 = 7 * 24 * 60;
+
+/// Largest configurable burst, 16 MB
+const MAX_BURST_KB: u32 = 16 * 1024;
+
+/// Device types the UI has icons for
+const DEVICE_ICONS: [&str; 8] = ["router", "computer", "phone", "tablet", "iot", "tv", "gaming", "unknown"];
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CutResult {
     pub success: bool,
     pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<Refusal>,  // Set when the protection policy blocked the action
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<ConfirmationRequest>,  // Set when the action waits on the user
+}
+
+impl CutResult {
+    fn ok(message: String) -> Self {
+        Self {
+            success: true,
+            message,
+            refusal: None,
+            confirmation: None,
+        }
+    }
+
+    fn refused(refusal: Refusal) -> Self {
+        Self {
+            success: false,
+            message: refusal.message.clone(),
+            refusal: Some(refusal),
+            confirmation: None,
+        }
+    }
+
+    fn needs_confirmation(request: ConfirmationRequest) -> Self {
+        Self {
+            success: false,
+            message: format!("{}. Confirm to continue.", request.reason),
+            refusal: None,
+            confirmation: Some(request),
+        }
+    }
+}
+
+/// Sent back when an action on a sensitive target needs the user's go-ahead.
+/// Repeating the action with `token` carries it out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmationRequest {
+    pub token: String,
+    pub action: String,
+    pub target: String,
+    pub reason: String,
+    pub expires_in_secs: u64,
+}
+
+/// Who asked for a control action and why, for the audit log
+#[derive(Debug, Clone)]
+pub struct ActionContext {
+    pub actor: String,
+    pub reason: Option<String>,
+    pub confirmation_token: Option<String>,
+    pub confirmed: bool,  // Already confirmed, as for members of a confirmed group action
+}
+
+impl ActionContext {
+    /// An action requested from the UI
+    pub fn user(reason: Option<String>, confirmation_token: Option<String>) -> Result<Self, String> {
+        let reason = reason.map(|r| r.trim().to_string()).filter(|r| !r.is_empty());
+        if let Some(reason) = &reason {
+            if reason.chars().count() > MAX_REASON_LEN {
+                return Err(format!("Reason too long (max {} characters)", MAX_REASON_LEN));
+            }
+            if reason.chars().any(char::is_control) {
+                return Err("Reason cannot contain control characters".to_string());
+            }
+        }
+
+        Ok(Self {
+            actor: audit::local_actor(),
+            reason,
+            confirmation_token,
+            confirmed: false,
+        })
+    }
 }
 
 #[tauri::command]
@@ -19,14 +117,87 @@ pub async fn get_devices(state: State<'_, AppState>) -> Result<Vec<Device>, Stri
 pub async fn cut_device(
     state: State<'_, AppState>,
     device_id: String,
+    duration_minutes: Option<u32>,
+    mode: Option<CutMode>,
+    reason: Option<String>,
+    confirmation_token: Option<String>,
+) -> Result<CutResult, String> {
+    let context = ActionContext::user(reason, confirmation_token)?;
+    cut_device_by_id(&state, &device_id, duration_minutes, mode.unwrap_or_default(), &context).await
+}
+
+#[tauri::command]
+pub async fn restore_device(
+    state: State<'_, AppState>,
+    device_id: String,
+    reason: Option<String>,
+) -> Result<CutResult, String> {
+    let context = ActionContext::user(reason, None)?;
+    restore_device_by_id(&state, &device_id, &context).await
+}
+
+/// Emergency stop: restore every redirected device and stop spoofing
+#[tauri::command]
+pub async fn restore_all(
+    state: State<'_, AppState>,
+    reason: Option<String>,
 ) -> Result<CutResult, String> {
+    let context = ActionContext::user(reason, None)?;
+    let outcome = apply_restore_all(&state).await;
+    audit_action(&state, &context, "restore_all", "all", &outcome, serde_json::json!({})).await;
+    outcome
+}
+
+/// Restore every redirected device as the app exits, recording the events
+/// and audit entry an emergency stop would
+pub async fn restore_all_on_exit(state: &AppState) {
+    let context = ActionContext {
+        actor: audit::local_actor(),
+        reason: Some("Application exit".to_string()),
+        confirmation_token: None,
+        confirmed: false,
+    };
+    let outcome = apply_restore_all(state).await;
+    if let Err(e) = &outcome {
+        log::warn!("Could not restore cut devices on exit: {}", e);
+    }
+    audit_action(state, &context, "restore_all", "all", &outcome, serde_json::json!({})).await;
+}
+
+/// Limit a device. `limit_mbps` applies to both directions unless
+/// `upload_mbps` or `download_mbps` override it; a direction left without a
+/// rate is unlimited.
+// Tauri maps each argument to a field of the frontend's call, so they stay flat
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn limit_bandwidth(
+    state: State<'_, AppState>,
+    device_id: String,
+    limit_mbps: Option<f64>,
+    upload_mbps: Option<f64>,
+    download_mbps: Option<f64>,
+    burst_kb: Option<u32>,
+    reason: Option<String>,
+    confirmation_token: Option<String>,
+) -> Result<CutResult, String> {
+    let context = ActionContext::user(reason, confirmation_token)?;
+    let limit = BandwidthLimit {
+        upload_mbps: upload_mbps.or(limit_mbps),
+        download_mbps: download_mbps.or(limit_mbps),
+        burst_kb,
+    };
+    limit_device_bandwidth(&state, &device_id, limit, &context).await
+}
+
+/// Look up a discovered device by its ID (MAC address with underscores)
+async fn find_device(state: &AppState, device_id: &str) -> Result<NetworkDevice, String> {
     // Validate device_id
     if device_id.is_empty() {
         return Err("Invalid device ID".to_string());
     }
 
     // Parse device_id (MAC address with underscores)
-    let mac_address = device_id.replace('_', ":");
+    let mac_address = mac_for_device_id(device_id);
 
     // Get device info from scanner
     let scanner = state.scanner.lock().await;
@@ -34,9 +205,142 @@ pub async fn cut_device(
     drop(scanner);
 
     // Find the device by MAC
-    let device = devices.iter()
+    devices.into_iter()
         .find(|d| d.mac.to_lowercase() == mac_address.to_lowercase())
-        .ok_or_else(|| format!("Device {} not found", device_id))?;
+        .ok_or_else(|| format!("Device {} not found", device_id))
+}
+
+/// The MAC a device ID stands for, for actions on an existing redirect.
+/// Redirects follow the device by MAC, so these work whether or not the
+/// last scan found it.
+fn device_mac(device_id: &str) -> Result<String, String> {
+    normalize_mac(&mac_for_device_id(device_id)).ok_or_else(|| "Invalid device ID".to_string())
+}
+
+fn validate_cut_minutes(minutes: u32) -> Result<(), String> {
+    if minutes == 0 || minutes > MAX_CUT_MINUTES {
+        return Err(format!("Cut duration must be between 1 and {} minutes", MAX_CUT_MINUTES));
+    }
+    Ok(())
+}
+
+/// Run an action past the protection policy before anything is sent.
+/// Returns the result to hand back instead when the action is refused or
+/// still has to be confirmed.
+async fn check_policy(
+    state: &AppState,
+    context: &ActionContext,
+    action: ControlAction,
+    device: &NetworkDevice,
+    gateway_ip: Ipv4Addr,
+) -> Result<Option<CutResult>, String> {
+    let database = state.database.lock().await;
+    let policy = ControlPolicy::load(&database, Some(gateway_ip)).await
+        .map_err(|e| format!("Failed to load protection policy: {}", e))?;
+    drop(database);
+
+    if let Err(refusal) = policy.check(action, device.ip, &device.mac) {
+        log::warn!("Refused to {} device {} ({}): {}", action.as_str(), device.ip, device.mac, refusal);
+        return Ok(Some(CutResult::refused(refusal)));
+    }
+
+    if let Some(reason) = policy.confirmation_reason(&device.mac) {
+        let device_id = device_id_for_mac(&device.mac);
+        if let Some(request) = require_confirmation(state, context, action.as_str(), &device_id, reason).await {
+            return Ok(Some(CutResult::needs_confirmation(request)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Redeem the context's token for `action` on `target`. Without a valid
+/// token a new one is issued for the UI to send back once the user agrees.
+pub async fn require_confirmation(
+    state: &AppState,
+    context: &ActionContext,
+    action: &str,
+    target: &str,
+    reason: String,
+) -> Option<ConfirmationRequest> {
+    if context.confirmed {
+        return None;
+    }
+
+    let mut confirmations = state.confirmations.lock().await;
+    if let Some(token) = &context.confirmation_token {
+        if confirmations.redeem(token, action, target) {
+            log::info!("{} of {} confirmed by {}", action, target, context.actor);
+            return None;
+        }
+    }
+
+    Some(ConfirmationRequest {
+        token: confirmations.issue(action, target),
+        action: action.to_string(),
+        target: target.to_string(),
+        reason,
+        expires_in_secs: CONFIRMATION_TTL.as_secs(),
+    })
+}
+
+/// Record the outcome of a control action in the audit log
+pub async fn audit_action(
+    state: &AppState,
+    context: &ActionContext,
+    action: &str,
+    target: &str,
+    outcome: &Result<CutResult, String>,
+    mut details: serde_json::Value,
+) {
+    let (audit_outcome, message) = match outcome {
+        Ok(result) if result.success => (AuditOutcome::Succeeded, &result.message),
+        Ok(result) if result.confirmation.is_some() => (AuditOutcome::ConfirmationRequired, &result.message),
+        Ok(result) if result.refusal.is_some() => (AuditOutcome::Refused, &result.message),
+        Ok(result) => (AuditOutcome::Failed, &result.message),
+        Err(message) => (AuditOutcome::Failed, message),
+    };
+    details["message"] = serde_json::json!(message);
+
+    let database = state.database.lock().await;
+    audit::record(&database, AuditRecord {
+        actor: context.actor.clone(),
+        action: action.to_string(),
+        target: target.to_string(),
+        reason: context.reason.clone(),
+        outcome: audit_outcome.as_str().to_string(),
+        details: Some(details.to_string()),
+    }).await;
+}
+
+/// Cut a device, restoring it automatically after `duration_minutes` if given
+pub async fn cut_device_by_id(
+    state: &AppState,
+    device_id: &str,
+    duration_minutes: Option<u32>,
+    mode: CutMode,
+    context: &ActionContext,
+) -> Result<CutResult, String> {
+    let outcome = apply_cut(state, device_id, duration_minutes, mode, context).await;
+    audit_action(state, context, "cut", device_id, &outcome, serde_json::json!({
+        "durationMinutes": duration_minutes,
+        "mode": mode.as_str(),
+    })).await;
+    outcome
+}
+
+async fn apply_cut(
+    state: &AppState,
+    device_id: &str,
+    duration_minutes: Option<u32>,
+    mode: CutMode,
+    context: &ActionContext,
+) -> Result<CutResult, String> {
+    if let Some(minutes) = duration_minutes {
+        validate_cut_minutes(minutes)?;
+    }
+
+    let device = find_device(state, device_id).await?;
 
     // Get gateway info
     let scanner = state.scanner.lock().await;
@@ -44,145 +348,402 @@ pub async fn cut_device(
         .map_err(|e| format!("Failed to get gateway info: {}", e))?;
     drop(scanner);
 
+    if let Some(result) = check_policy(state, context, ControlAction::Cut, &device, gateway_ip).await? {
+        return Ok(result);
+    }
+
     // Get ARP controller and set gateway if needed
     let mut arp = state.arp_controller.lock().await;
     if arp.gateway_ip.is_none() {
-        arp.set_gateway(gateway_ip, gateway_mac.clone())
+        arp.set_gateway(gateway_ip, gateway_mac.clone()).await
             .map_err(|e| format!("Failed to set gateway: {}", e))?;
     }
 
     // Cut the device
-    arp.cut_device(device.ip, device.mac.clone()).await
+    arp.cut_device(device.ip, device.mac.clone(), mode).await
         .map_err(|e| format!("Failed to cut device: {}", e))?;
 
+    if let Some(minutes) = duration_minutes {
+        let expires_at = SystemTime::now() + Duration::from_secs(u64::from(minutes) * 60);
+        arp.set_expiry(&device.mac, Some(expires_at)).await
+            .map_err(|e| format!("Failed to set cut timer: {}", e))?;
+    }
+
+    drop(arp);
+
     log::info!("Successfully cut device: {} ({})", device.ip, device.mac);
 
-    Ok(CutResult {
-        success: true,
-        message: format!("Device {} has been cut from network", device_id),
-    })
+    record_event(state, EventType::DeviceCut, device_id, serde_json::json!({
+        "ip": device.ip.to_string(),
+        "mac": device.mac,
+        "durationMinutes": duration_minutes,
+        "mode": mode.as_str(),
+    })).await;
+
+    let scope = match mode {
+        CutMode::InternetOnly => "cut from the internet",
+        CutMode::FullIsolation => "isolated from the network",
+    };
+    let message = match duration_minutes {
+        Some(minutes) => format!("Device {} has been {} for {} minutes", device_id, scope, minutes),
+        None => format!("Device {} has been {}", device_id, scope),
+    };
+
+    Ok(CutResult::ok(message))
 }
 
+/// Replace the timer on a cut device; `None` keeps it cut until restored
 #[tauri::command]
-pub async fn restore_device(
+pub async fn set_cut_timer(
     state: State<'_, AppState>,
     device_id: String,
+    duration_minutes: Option<u32>,
+    reason: Option<String>,
 ) -> Result<CutResult, String> {
-    // Validate device_id
-    if device_id.is_empty() {
-        return Err("Invalid device ID".to_string());
+    let context = ActionContext::user(reason, None)?;
+    if let Some(minutes) = duration_minutes {
+        validate_cut_minutes(minutes)?;
     }
 
-    // Parse device_id (MAC address with underscores)
-    let mac_address = device_id.replace('_', ":");
+    let expires_at = duration_minutes
+        .map(|minutes| SystemTime::now() + Duration::from_secs(u64::from(minutes) * 60));
+    update_cut_timer(&state, &device_id, expires_at, &context, serde_json::json!({
+        "durationMinutes": duration_minutes,
+    })).await
+}
 
-    // Get device info from scanner
+/// Move the end of a timed cut by `minutes`, negative to shorten it. A
+/// timer moved into the past restores the device right away.
+#[tauri::command]
+pub async fn extend_cut_timer(
+    state: State<'_, AppState>,
+    device_id: String,
+    minutes: i32,
+    reason: Option<String>,
+) -> Result<CutResult, String> {
+    let context = ActionContext::user(reason, None)?;
+    if minutes == 0 {
+        return Err("Timer change must not be zero".to_string());
+    }
+
+    let mac = device_mac(&device_id)?;
+
+    let arp = state.arp_controller.lock().await;
+    let current = arp.get_cut(&mac).await
+        .ok_or_else(|| format!("Device {} is not cut", device_id))?
+        .expires_at
+        .ok_or_else(|| format!("Device {} has no cut timer", device_id))?;
+    drop(arp);
+
+    let delta = Duration::from_secs(u64::from(minutes.unsigned_abs()) * 60);
+    let expires_at = if minutes > 0 {
+        current + delta
+    } else {
+        current.checked_sub(delta).unwrap_or(SystemTime::UNIX_EPOCH)
+    };
+
+    let max_expiry = SystemTime::now() + Duration::from_secs(u64::from(MAX_CUT_MINUTES) * 60);
+    if expires_at > max_expiry {
+        return Err(format!("Cut timer cannot run longer than {} minutes", MAX_CUT_MINUTES));
+    }
+
+    update_cut_timer(&state, &device_id, Some(expires_at), &context, serde_json::json!({
+        "minutes": minutes,
+    })).await
+}
+
+async fn update_cut_timer(
+    state: &AppState,
+    device_id: &str,
+    expires_at: Option<SystemTime>,
+    context: &ActionContext,
+    details: serde_json::Value,
+) -> Result<CutResult, String> {
+    if expires_at.is_some_and(|t| t <= SystemTime::now()) {
+        return restore_device_by_id(state, device_id, context).await;
+    }
+
+    let outcome = apply_cut_timer(state, device_id, expires_at).await;
+    audit_action(state, context, "set_cut_timer", device_id, &outcome, details).await;
+    outcome
+}
+
+async fn apply_cut_timer(state: &AppState, device_id: &str, expires_at: Option<SystemTime>) -> Result<CutResult, String> {
+    let mac = device_mac(device_id)?;
+
+    let arp = state.arp_controller.lock().await;
+    arp.set_expiry(&mac, expires_at).await
+        .map_err(|e| format!("Failed to set cut timer: {}", e))?;
+    drop(arp);
+
+    let remaining_secs = expires_at
+        .and_then(|t| t.duration_since(SystemTime::now()).ok())
+        .map(|d| d.as_secs());
+
+    log::info!("Cut timer for device {} set to {:?} seconds", device_id, remaining_secs);
+
+    let message = match remaining_secs {
+        Some(secs) => format!("Device {} will be restored in {} minutes", device_id, secs.div_ceil(60)),
+        None => format!("Device {} will stay cut until restored", device_id),
+    };
+
+    Ok(CutResult::ok(message))
+}
+
+pub async fn restore_device_by_id(state: &AppState, device_id: &str, context: &ActionContext) -> Result<CutResult, String> {
+    let outcome = apply_restore(state, device_id).await;
+    audit_action(state, context, "restore", device_id, &outcome, serde_json::json!({})).await;
+    outcome
+}
+
+async fn apply_restore(state: &AppState, device_id: &str) -> Result<CutResult, String> {
+    // Restored by MAC, so a device that moved or went offline since the
+    // last scan still gets its connection back
+    let mac = device_mac(device_id)?;
+
+    let arp = state.arp_controller.lock().await;
+    let cut = arp.get_cut(&mac).await
+        .ok_or_else(|| format!("Device {} is not cut", device_id))?;
+    arp.restore_device(&mac).await
+        .map_err(|e| format!("Failed to restore device: {}", e))?;
+
+    drop(arp);
+
+    log::info!("Successfully restored device: {} ({})", cut.target_ip, cut.target_mac);
+
+    record_event(state, EventType::DeviceRestored, device_id, serde_json::json!({
+        "ip": cut.target_ip.to_string(),
+        "mac": cut.target_mac,
+    })).await;
+
+    Ok(CutResult::ok(format!("Device {} has been restored to network", device_id)))
+}
+
+async fn apply_restore_all(state: &AppState) -> Result<CutResult, String> {
+    let arp = state.arp_controller.lock().await;
+    let restored = arp.restore_all().await
+        .map_err(|e| format!("Failed to restore devices: {}", e))?;
+    drop(arp);
+
+    log::info!("Restored all {} redirected devices", restored.len());
+
+    for spoof in &restored {
+        let device_id = device_id_for_mac(&spoof.target_mac);
+        record_event(state, EventType::DeviceRestored, &device_id, serde_json::json!({
+            "ip": spoof.target_ip.to_string(),
+            "mac": spoof.target_mac,
+        })).await;
+    }
+
+    Ok(CutResult::ok(format!("Restored {} devices", restored.len())))
+}
+
+fn validate_limit(limit: &BandwidthLimit) -> Result<(), String> {
+    if limit.upload_mbps.is_none() && limit.download_mbps.is_none() {
+        return Err("Bandwidth limit needs an upload or download rate".to_string());
+    }
+    for mbps in [limit.upload_mbps, limit.download_mbps].into_iter().flatten() {
+        if !(0.1..=10000.0).contains(&mbps) {
+            return Err("Bandwidth limit must be between 0.1 and 10000 Mbps".to_string());
+        }
+    }
+    if let Some(kb) = limit.burst_kb {
+        if kb == 0 || kb > MAX_BURST_KB {
+            return Err(format!("Burst size must be between 1 and {} KB", MAX_BURST_KB));
+        }
+    }
+    Ok(())
+}
+
+pub async fn limit_device_bandwidth(
+    state: &AppState,
+    device_id: &str,
+    limit: BandwidthLimit,
+    context: &ActionContext,
+) -> Result<CutResult, String> {
+    let outcome = apply_limit(state, device_id, limit, context).await;
+    audit_action(state, context, "limit", device_id, &outcome, serde_json::json!({
+        "uploadMbps": limit.upload_mbps,
+        "downloadMbps": limit.download_mbps,
+        "burstKb": limit.burst_kb,
+    })).await;
+    outcome
+}
+
+async fn apply_limit(
+    state: &AppState,
+    device_id: &str,
+    limit: BandwidthLimit,
+    context: &ActionContext,
+) -> Result<CutResult, String> {
+    validate_limit(&limit)?;
+
+    let device = find_device(state, device_id).await?;
+
+    // Get gateway info
     let scanner = state.scanner.lock().await;
-    let devices = scanner.get_discovered_devices().await;
+    let (gateway_ip, gateway_mac) = scanner.get_gateway().await
+        .map_err(|e| format!("Failed to get gateway info: {}", e))?;
     drop(scanner);
 
-    // Find the device by MAC
-    let device = devices.iter()
-        .find(|d| d.mac.to_lowercase() == mac_address.to_lowercase())
-        .ok_or_else(|| format!("Device {} not found", device_id))?;
+    if let Some(result) = check_policy(state, context, ControlAction::Limit, &device, gateway_ip).await? {
+        return Ok(result);
+    }
+
+    // Limited traffic is redirected through us, so the gateway must be known
+    let mut arp = state.arp_controller.lock().await;
+    if arp.gateway_ip.is_none() {
+        arp.set_gateway(gateway_ip, gateway_mac.clone()).await
+            .map_err(|e| format!("Failed to set gateway: {}", e))?;
+    }
+
+    arp.limit_device(device.ip, device.mac.clone(), limit).await
+        .map_err(|e| format!("Failed to limit bandwidth: {}", e))?;
+
+    drop(arp);
+
+    let describe = |mbps: Option<f64>| mbps.map_or("unlimited".to_string(), |m| format!("{} Mbps", m));
+    let summary = format!("upload {}, download {}", describe(limit.upload_mbps), describe(limit.download_mbps));
+    log::info!("Setting bandwidth limit: {} for device {}", summary, device_id);
+
+    record_event(state, EventType::LimitSet, device_id, serde_json::json!({
+        "limitMbps": limit.min_mbps(),
+        "uploadMbps": limit.upload_mbps,
+        "downloadMbps": limit.download_mbps,
+        "burstKb": limit.burst_kb,
+    })).await;
+
+    Ok(CutResult::ok(format!("Bandwidth limit set to {} for device {}", summary, device_id)))
+}
+
+pub async fn remove_device_bandwidth_limit(state: &AppState, device_id: &str, context: &ActionContext) -> Result<CutResult, String> {
+    let outcome = apply_limit_removal(state, device_id).await;
+    audit_action(state, context, "unlimit", device_id, &outcome, serde_json::json!({})).await;
+    outcome
+}
+
+async fn apply_limit_removal(state: &AppState, device_id: &str) -> Result<CutResult, String> {
+    let mac = device_mac(device_id)?;
 
-    // Restore the device
     let arp = state.arp_controller.lock().await;
-    arp.restore_device(device.ip).await
-        .map_err(|e| format!("Failed to restore device: {}", e))?;
+    if arp.get_limit(&mac).await.is_none() {
+        return Err(format!("Device {} has no bandwidth limit", device_id));
+    }
+    arp.remove_limit(&mac).await
+        .map_err(|e| format!("Failed to remove bandwidth limit: {}", e))?;
+
+    drop(arp);
+
+    log::info!("Removing bandwidth limit for device {}", device_id);
 
-    log::info!("Successfully restored device: {} ({})", device.ip, device.mac);
+    record_event(state, EventType::LimitRemoved, device_id, serde_json::json!({})).await;
 
-    Ok(CutResult {
-        success: true,
-        message: format!("Device {} has been restored to network", device_id),
-    })
+    Ok(CutResult::ok(format!("Bandwidth limit removed for device {}", device_id)))
 }
 
 #[tauri::command]
-pub async fn limit_bandwidth(
+pub async fn update_device_name(
     state: State<'_, AppState>,
     device_id: String,
-    limit_mbps: f64,
-) -> Result<CutResult, String> {
+    name: String,
+) -> Result<(), String> {
     // Validate input
     if device_id.is_empty() {
         return Err("Invalid device ID".to_string());
     }
 
-    if limit_mbps <= 0.0 || limit_mbps > 10000.0 {
-        return Err("Bandwidth limit must be between 0.1 and 10000 Mbps".to_string());
+    // Stored as typed; the UI escapes it when rendering. An empty name
+    // clears the custom name.
+    let name = name.trim();
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(format!("Device name too long (max {} characters)", MAX_NAME_LEN));
+    }
+    if name.chars().any(char::is_control) {
+        return Err("Device name cannot contain control characters".to_string());
     }
+    let name = Some(name).filter(|n| !n.is_empty());
 
-    let mut _bandwidth_controller = state.bandwidth_controller.lock().await;
+    let database = state.database.lock().await;
+    let updated = database.set_device_custom_name(&device_id, name).await
+        .map_err(|e| format!("Failed to update device name: {}", e))?;
+    drop(database);
 
-    // TODO: Implement bandwidth limiting with actual IP
-    // let device_ip = get_device_ip_from_id(&device_id)?;
-    // bandwidth_controller.limit_bandwidth(device_ip, limit_mbps).await?;
+    if !updated {
+        return Err(format!("Device {} not found", device_id));
+    }
 
-    log::info!("Setting bandwidth limit: {} Mbps for device {}", limit_mbps, device_id);
+    log::info!("Updated device {} name to: {:?}", device_id, name);
 
-    Ok(CutResult {
-        success: true,
-        message: format!("Bandwidth limit set to {} Mbps for device {}", limit_mbps, device_id),
-    })
+    record_event(&state, EventType::DeviceRenamed, &device_id, serde_json::json!({
+        "name": name,
+    })).await;
+
+    Ok(())
 }
 
 #[tauri::command]
-pub async fn update_device_name(
+pub async fn update_device_notes(
     state: State<'_, AppState>,
     device_id: String,
-    name: String,
+    notes: String,
 ) -> Result<(), String> {
-    // Validate input
     if device_id.is_empty() {
         return Err("Invalid device ID".to_string());
     }
 
-    if name.len() > 100 {
-        return Err("Device name too long (max 100 characters)".to_string());
+    if notes.chars().count() > MAX_NOTES_LEN {
+        return Err(format!("Notes too long (max {} characters)", MAX_NOTES_LEN));
     }
+    let notes = Some(notes.trim()).filter(|n| !n.is_empty());
 
-    // Sanitize name - remove any potential XSS attempts
-    let sanitized_name = name
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
-        .replace('"', "&quot;")
-        .replace('\'', "&#x27;");
-
-    let mut _database = state.database.lock().await;
+    let database = state.database.lock().await;
+    let updated = database.set_device_notes(&device_id, notes).await
+        .map_err(|e| format!("Failed to update device notes: {}", e))?;
 
-    // TODO: Update device name in database
-    // database.update_device_name(&device_id, &sanitized_name).await
-    //     .map_err(|e| e.to_string())?;
-
-    log::info!("Updated device {} name to: {}", device_id, sanitized_name);
+    if !updated {
+        return Err(format!("Device {} not found", device_id));
+    }
 
     Ok(())
 }
 
+/// Set the device type shown for a device, or clear it with `None` to go
+/// back to the detected type
 #[tauri::command]
-pub async fn remove_bandwidth_limit(
+pub async fn update_device_icon(
     state: State<'_, AppState>,
     device_id: String,
-) -> Result<CutResult, String> {
-    // Validate input
+    icon: Option<String>,
+) -> Result<(), String> {
     if device_id.is_empty() {
         return Err("Invalid device ID".to_string());
     }
 
-    let mut _bandwidth_controller = state.bandwidth_controller.lock().await;
+    if let Some(icon) = &icon {
+        if !DEVICE_ICONS.contains(&icon.as_str()) {
+            return Err(format!("Unknown device icon: {}", icon));
+        }
+    }
 
-    // TODO: Implement bandwidth limit removal with actual IP
-    // let device_ip = get_device_ip_from_id(&device_id)?;
-    // bandwidth_controller.remove_limit(device_ip).await?;
+    let database = state.database.lock().await;
+    let updated = database.set_device_icon(&device_id, icon.as_deref()).await
+        .map_err(|e| format!("Failed to update device icon: {}", e))?;
 
-    log::info!("Removing bandwidth limit for device {}", device_id);
+    if !updated {
+        return Err(format!("Device {} not found", device_id));
+    }
 
-    Ok(CutResult {
-        success: true,
-        message: format!("Bandwidth limit removed for device {}", device_id),
-    })
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn remove_bandwidth_limit(
+    state: State<'_, AppState>,
+    device_id: String,
+    reason: Option<String>,
+) -> Result<CutResult, String> {
+    let context = ActionContext::user(reason, None)?;
+    remove_device_bandwidth_limit(&state, &device_id, &context).await
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -227,8 +788,8 @@ pub async fn get_bandwidth_updates(state: State<'_, AppState>) -> Result<Vec<Ban
             let _traffic_stats = packet_monitor.get_traffic_stats().await;
 
             for device in devices.iter() {
-                let device_id = device.mac.replace(':', "_").to_lowercase();
-                let is_cut = cut_devices.iter().any(|c| c.target_ip == device.ip && c.active);
+                let device_id = device_id_for_mac(&device.mac);
+                let is_cut = cut_devices.iter().any(|c| c.target_mac.eq_ignore_ascii_case(&device.mac) && c.active);
 
                 if is_cut {
                     bandwidth_updates.push(BandwidthUpdate {
@@ -247,8 +808,8 @@ pub async fn get_bandwidth_updates(state: State<'_, AppState>) -> Result<Vec<Ban
         // Use network stats as fallback (doesn't require privileges)
 
         for device in devices.iter() {
-            let device_id = device.mac.replace(':', "_").to_lowercase();
-            let is_cut = cut_devices.iter().any(|c| c.target_ip == device.ip && c.active);
+            let device_id = device_id_for_mac(&device.mac);
+            let is_cut = cut_devices.iter().any(|c| c.target_mac.eq_ignore_ascii_case(&device.mac) && c.active);
 
             if is_cut {
                 // Device is cut, report 0 bandwidth
@@ -279,6 +840,25 @@ pub async fn get_bandwidth_updates(state: State<'_, AppState>) -> Result<Vec<Ban
         }
     }
 
+    // Devices going through the forwarder report what was actually forwarded
+    let arp = state.arp_controller.lock().await;
+    let forwarded = arp.get_forwarded_devices().await;
+    drop(arp);
+
+    let bandwidth_controller = state.bandwidth_controller.lock().await;
+    for spoof in forwarded {
+        let Some(rate) = bandwidth_controller.get_forwarded_rate(&spoof.target_mac).await else {
+            continue;
+        };
+        let device_id = device_id_for_mac(&spoof.target_mac);
+        bandwidth_updates.retain(|u| u.device_id != device_id);
+        bandwidth_updates.push(BandwidthUpdate {
+            device_id,
+            bandwidth_current: rate,
+        });
+    }
+    drop(bandwidth_controller);
+
     if bandwidth_updates.is_empty() {
         log::debug!("No devices found for bandwidth updates");
     } else {
@@ -288,3 +868,41 @@ pub async fn get_bandwidth_updates(state: State<'_, AppState>) -> Result<Vec<Ban
     Ok(bandwidth_updates)
 }
 
+
+/// Forwarding statistics for a limited device
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceBandwidthStats {
+    pub device_id: String,
+    pub ip: String,
+    pub limit: Option<BandwidthLimit>,
+    #[serde(flatten)]
+    pub stats: BandwidthStats,
+}
+
+#[tauri::command]
+pub async fn get_bandwidth_stats(state: State<'_, AppState>) -> Result<Vec<DeviceBandwidthStats>, String> {
+    let scanner = state.scanner.lock().await;
+    let devices = scanner.get_discovered_devices().await;
+    drop(scanner);
+
+    let bandwidth_controller = state.bandwidth_controller.lock().await;
+    let statistics = bandwidth_controller.get_all_statistics().await;
+    let limits = bandwidth_controller.get_limits().await;
+    drop(bandwidth_controller);
+
+    let stats = devices.into_iter()
+        .filter_map(|device| {
+            let mac = normalize_mac(&device.mac)?;
+            let stats = statistics.get(&mac)?.clone();
+            Some(DeviceBandwidthStats {
+                device_id: device_id_for_mac(&device.mac),
+                ip: device.ip.to_string(),
+                limit: limits.get(&mac).copied(),
+                stats,
+            })
+        })
+        .collect();
+
+    Ok(stats)
+}
@@ -0,0 +1,37 @@
+use crate::AppState;
+use crate::modules::transmitter::DryRunFrame;
+use serde::Serialize;
+use tauri::State;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRunLog {
+    pub enabled: bool,
+    pub frames: Vec<DryRunFrame>,
+}
+
+/// Preview cuts and restores: while enabled, frames are logged instead of
+/// being sent
+#[tauri::command]
+pub async fn set_dry_run(state: State<'_, AppState>, enabled: bool) -> Result<bool, String> {
+    let arp = state.arp_controller.lock().await;
+    arp.set_dry_run(enabled).await
+        .map_err(|e| format!("Failed to switch dry-run mode: {}", e))?;
+    Ok(enabled)
+}
+
+#[tauri::command]
+pub async fn get_dry_run_log(state: State<'_, AppState>) -> Result<DryRunLog, String> {
+    let arp = state.arp_controller.lock().await;
+    Ok(DryRunLog {
+        enabled: arp.is_dry_run().await,
+        frames: arp.dry_run_frames().await,
+    })
+}
+
+#[tauri::command]
+pub async fn clear_dry_run_log(state: State<'_, AppState>) -> Result<(), String> {
+    let arp = state.arp_controller.lock().await;
+    arp.clear_dry_run_frames().await;
+    Ok(())
+}
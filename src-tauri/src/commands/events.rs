@@ -0,0 +1,35 @@
+use crate::AppState;
+use crate::modules::database::{EventPage, EventQuery, EventType};
+use tauri::State;
+
+#[tauri::command]
+pub async fn get_events(
+    state: State<'_, AppState>,
+    query: Option<EventQuery>,
+) -> Result<EventPage, String> {
+    let query = query.unwrap_or_default();
+
+    if let (Some(since), Some(until)) = (query.since, query.until) {
+        if since > until {
+            return Err("Invalid time range: start is after end".to_string());
+        }
+    }
+
+    let database = state.database.lock().await;
+    database.get_events(&query).await
+        .map_err(|e| format!("Failed to load events: {}", e))
+}
+
+/// Record an event in the log. Failures are logged rather than returned so
+/// that a database problem never blocks a control action.
+pub async fn record_event(
+    state: &AppState,
+    event_type: EventType,
+    device_id: &str,
+    details: serde_json::Value,
+) {
+    let database = state.database.lock().await;
+    if let Err(e) = database.record_event(event_type, device_id, Some(details.to_string())).await {
+        log::warn!("Failed to record {} event for {}: {}", event_type.as_str(), device_id, e);
+    }
+}
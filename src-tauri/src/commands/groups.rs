@@ -0,0 +1,370 @@
+use crate::AppState;
+use crate::modules::audit::{self, AuditOutcome};
+use crate::modules::bandwidth::BandwidthLimit;
+use crate::modules::database::{AuditRecord, CutMode, Database, DeviceGroup};
+use crate::modules::policy::{ControlPolicy, Refusal};
+use crate::utils::safety::mac_for_device_id;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use super::device::{
+    cut_device_by_id, limit_device_bandwidth, remove_device_bandwidth_limit, require_confirmation,
+    restore_device_by_id, ActionContext, ConfirmationRequest, CutResult,
+};
+
+const MAX_GROUP_NAME_LEN: usize = 50;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkDeviceResult {
+    pub device_id: String,
+    pub success: bool,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<Refusal>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkActionReport {
+    pub group_id: i64,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub refused: usize,  // Failures blocked by the protection policy
+    pub results: Vec<BulkDeviceResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<ConfirmationRequest>,  // Set when nothing was done pending confirmation
+}
+
+#[tauri::command]
+pub async fn get_groups(state: State<'_, AppState>) -> Result<Vec<DeviceGroup>, String> {
+    let database = state.database.lock().await;
+    database.get_groups().await
+        .map_err(|e| format!("Failed to load groups: {}", e))
+}
+
+#[tauri::command]
+pub async fn create_group(
+    state: State<'_, AppState>,
+    name: String,
+    color: Option<String>,
+) -> Result<DeviceGroup, String> {
+    let name = validate_group(&name, color.as_deref())?;
+
+    let database = state.database.lock().await;
+    ensure_name_available(&database.get_groups().await.map_err(|e| format!("Failed to load groups: {}", e))?, &name, None)?;
+
+    let group = database.create_group(&name, color.as_deref()).await
+        .map_err(|e| format!("Failed to create group: {}", e))?;
+
+    log::info!("Created device group {} ({})", group.name, group.id);
+    Ok(group)
+}
+
+#[tauri::command]
+pub async fn update_group(
+    state: State<'_, AppState>,
+    group_id: i64,
+    name: String,
+    color: Option<String>,
+) -> Result<DeviceGroup, String> {
+    let name = validate_group(&name, color.as_deref())?;
+
+    let database = state.database.lock().await;
+    ensure_name_available(&database.get_groups().await.map_err(|e| format!("Failed to load groups: {}", e))?, &name, Some(group_id))?;
+
+    let updated = database.update_group(group_id, &name, color.as_deref()).await
+        .map_err(|e| format!("Failed to update group: {}", e))?;
+    if !updated {
+        return Err(format!("Group {} not found", group_id));
+    }
+
+    load_group(&database, group_id).await
+}
+
+#[tauri::command]
+pub async fn delete_group(state: State<'_, AppState>, group_id: i64) -> Result<(), String> {
+    let database = state.database.lock().await;
+    let deleted = database.delete_group(group_id).await
+        .map_err(|e| format!("Failed to delete group: {}", e))?;
+    if !deleted {
+        return Err(format!("Group {} not found", group_id));
+    }
+
+    log::info!("Deleted device group {}", group_id);
+    Ok(())
+}
+
+/// Mark a group as protected, so cutting or limiting its members needs
+/// confirmation
+#[tauri::command]
+pub async fn set_group_protected(
+    state: State<'_, AppState>,
+    group_id: i64,
+    protected: bool,
+) -> Result<DeviceGroup, String> {
+    let database = state.database.lock().await;
+    let updated = database.set_group_protected(group_id, protected).await
+        .map_err(|e| format!("Failed to update group: {}", e))?;
+    if !updated {
+        return Err(format!("Group {} not found", group_id));
+    }
+
+    log::info!("Group {} is {}", group_id, if protected { "protected" } else { "no longer protected" });
+    load_group(&database, group_id).await
+}
+
+#[tauri::command]
+pub async fn add_devices_to_group(
+    state: State<'_, AppState>,
+    group_id: i64,
+    device_ids: Vec<String>,
+) -> Result<DeviceGroup, String> {
+    if device_ids.iter().any(|id| id.is_empty()) {
+        return Err("Invalid device ID".to_string());
+    }
+
+    let database = state.database.lock().await;
+    load_group(&database, group_id).await?;
+
+    let missing = database.add_group_members(group_id, &device_ids).await
+        .map_err(|e| format!("Failed to add devices to group: {}", e))?;
+    if !missing.is_empty() {
+        return Err(format!("Devices not found: {}", missing.join(", ")));
+    }
+
+    load_group(&database, group_id).await
+}
+
+#[tauri::command]
+pub async fn remove_devices_from_group(
+    state: State<'_, AppState>,
+    group_id: i64,
+    device_ids: Vec<String>,
+) -> Result<DeviceGroup, String> {
+    let database = state.database.lock().await;
+    load_group(&database, group_id).await?;
+
+    database.remove_group_members(group_id, &device_ids).await
+        .map_err(|e| format!("Failed to remove devices from group: {}", e))?;
+
+    load_group(&database, group_id).await
+}
+
+#[tauri::command]
+pub async fn cut_group(
+    state: State<'_, AppState>,
+    group_id: i64,
+    mode: Option<CutMode>,
+    reason: Option<String>,
+    confirmation_token: Option<String>,
+) -> Result<BulkActionReport, String> {
+    let mut context = ActionContext::user(reason, confirmation_token)?;
+    let members = group_members(&state, group_id).await?;
+    if let Some(request) = confirm_group(&state, &mut context, "cut", group_id).await? {
+        return Ok(pending_confirmation(group_id, request));
+    }
+
+    let mut results = Vec::with_capacity(members.len());
+    for device_id in members {
+        let outcome = cut_device_by_id(&state, &device_id, None, mode.unwrap_or_default(), &context).await;
+        results.push(to_result(device_id, outcome));
+    }
+
+    Ok(report(group_id, "cut", results))
+}
+
+#[tauri::command]
+pub async fn restore_group(
+    state: State<'_, AppState>,
+    group_id: i64,
+    reason: Option<String>,
+) -> Result<BulkActionReport, String> {
+    let context = ActionContext::user(reason, None)?;
+    let members = group_members(&state, group_id).await?;
+
+    let mut results = Vec::with_capacity(members.len());
+    for device_id in members {
+        let outcome = restore_device_by_id(&state, &device_id, &context).await;
+        results.push(to_result(device_id, outcome));
+    }
+
+    Ok(report(group_id, "restore", results))
+}
+
+#[tauri::command]
+pub async fn limit_group_bandwidth(
+    state: State<'_, AppState>,
+    group_id: i64,
+    limit_mbps: f64,
+    reason: Option<String>,
+    confirmation_token: Option<String>,
+) -> Result<BulkActionReport, String> {
+    if limit_mbps <= 0.0 || limit_mbps > 10000.0 {
+        return Err("Bandwidth limit must be between 0.1 and 10000 Mbps".to_string());
+    }
+
+    let mut context = ActionContext::user(reason, confirmation_token)?;
+    let members = group_members(&state, group_id).await?;
+    if let Some(request) = confirm_group(&state, &mut context, "limit", group_id).await? {
+        return Ok(pending_confirmation(group_id, request));
+    }
+
+    let mut results = Vec::with_capacity(members.len());
+    for device_id in members {
+        let outcome = limit_device_bandwidth(&state, &device_id, BandwidthLimit::symmetric(limit_mbps), &context).await;
+        results.push(to_result(device_id, outcome));
+    }
+
+    Ok(report(group_id, "limit", results))
+}
+
+#[tauri::command]
+pub async fn remove_group_bandwidth_limit(
+    state: State<'_, AppState>,
+    group_id: i64,
+    reason: Option<String>,
+) -> Result<BulkActionReport, String> {
+    let context = ActionContext::user(reason, None)?;
+    let members = group_members(&state, group_id).await?;
+
+    let mut results = Vec::with_capacity(members.len());
+    for device_id in members {
+        let outcome = remove_device_bandwidth_limit(&state, &device_id, &context).await;
+        results.push(to_result(device_id, outcome));
+    }
+
+    Ok(report(group_id, "unlimit", results))
+}
+
+/// Trim and check a group name and optional `#rrggbb` color
+fn validate_group(name: &str, color: Option<&str>) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Group name cannot be empty".to_string());
+    }
+    if name.chars().count() > MAX_GROUP_NAME_LEN {
+        return Err(format!("Group name too long (max {} characters)", MAX_GROUP_NAME_LEN));
+    }
+
+    if let Some(color) = color {
+        let valid = color.len() == 7
+            && color.starts_with('#')
+            && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+        if !valid {
+            return Err("Group color must be a hex color like #3b82f6".to_string());
+        }
+    }
+
+    Ok(name.to_string())
+}
+
+fn ensure_name_available(groups: &[DeviceGroup], name: &str, group_id: Option<i64>) -> Result<(), String> {
+    let taken = groups
+        .iter()
+        .any(|g| Some(g.id) != group_id && g.name.eq_ignore_ascii_case(name));
+    if taken {
+        return Err(format!("A group named {} already exists", name));
+    }
+    Ok(())
+}
+
+async fn load_group(database: &Database, group_id: i64) -> Result<DeviceGroup, String> {
+    database.get_group(group_id).await
+        .map_err(|e| format!("Failed to load group: {}", e))?
+        .ok_or_else(|| format!("Group {} not found", group_id))
+}
+
+async fn group_members(state: &AppState, group_id: i64) -> Result<Vec<String>, String> {
+    let database = state.database.lock().await;
+    Ok(load_group(&database, group_id).await?.device_ids)
+}
+
+/// Ask for one confirmation covering the whole group when it is protected
+/// or has sensitive members. Once confirmed, the members are not asked
+/// about again.
+async fn confirm_group(
+    state: &AppState,
+    context: &mut ActionContext,
+    action: &str,
+    group_id: i64,
+) -> Result<Option<ConfirmationRequest>, String> {
+    let database = state.database.lock().await;
+    let group = load_group(&database, group_id).await?;
+    let policy = ControlPolicy::load(&database, None).await
+        .map_err(|e| format!("Failed to load protection policy: {}", e))?;
+    drop(database);
+
+    let reason = if group.protected {
+        Some(format!("Group {} is protected", group.name))
+    } else {
+        group.device_ids
+            .iter()
+            .find_map(|device_id| policy.confirmation_reason(&mac_for_device_id(device_id)))
+    };
+    let Some(reason) = reason else {
+        return Ok(None);
+    };
+
+    let target = format!("group:{}", group_id);
+    let Some(request) = require_confirmation(state, context, action, &target, reason).await else {
+        context.confirmed = true;
+        return Ok(None);
+    };
+
+    let database = state.database.lock().await;
+    audit::record(&database, AuditRecord {
+        actor: context.actor.clone(),
+        action: action.to_string(),
+        target,
+        reason: context.reason.clone(),
+        outcome: AuditOutcome::ConfirmationRequired.as_str().to_string(),
+        details: Some(serde_json::json!({ "message": request.reason }).to_string()),
+    }).await;
+
+    Ok(Some(request))
+}
+
+fn pending_confirmation(group_id: i64, request: ConfirmationRequest) -> BulkActionReport {
+    BulkActionReport {
+        group_id,
+        succeeded: 0,
+        failed: 0,
+        refused: 0,
+        results: Vec::new(),
+        confirmation: Some(request),
+    }
+}
+
+fn to_result(device_id: String, outcome: Result<CutResult, String>) -> BulkDeviceResult {
+    match outcome {
+        Ok(result) => BulkDeviceResult {
+            device_id,
+            success: result.success,
+            message: result.message,
+            refusal: result.refusal,
+        },
+        Err(message) => BulkDeviceResult {
+            device_id,
+            success: false,
+            message,
+            refusal: None,
+        },
+    }
+}
+
+fn report(group_id: i64, action: &str, results: Vec<BulkDeviceResult>) -> BulkActionReport {
+    let succeeded = results.iter().filter(|r| r.success).count();
+    let failed = results.len() - succeeded;
+    let refused = results.iter().filter(|r| r.refusal.is_some()).count();
+
+    log::info!("Group {} {}: {} succeeded, {} failed ({} refused)", group_id, action, succeeded, failed, refused);
+
+    BulkActionReport {
+        group_id,
+        succeeded,
+        failed,
+        refused,
+        results,
+        confirmation: None,
+    }
+}
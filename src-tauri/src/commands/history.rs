@@ -0,0 +1,60 @@
+use crate::AppState;
+use crate::modules::database::{BandwidthPoint, HistoryResolution, HistoryRetention};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryRange {
+    pub start: chrono::DateTime<chrono::Utc>,
+    pub end: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[tauri::command]
+pub async fn get_device_history(
+    state: State<'_, AppState>,
+    device_id: String,
+    range: HistoryRange,
+    resolution: Option<HistoryResolution>,
+) -> Result<Vec<BandwidthPoint>, String> {
+    if device_id.is_empty() {
+        return Err("Invalid device ID".to_string());
+    }
+
+    let end = range.end.unwrap_or_else(chrono::Utc::now);
+    if range.start > end {
+        return Err("Invalid time range: start is after end".to_string());
+    }
+
+    let resolution = resolution
+        .unwrap_or_else(|| HistoryResolution::for_span((end - range.start).num_seconds()));
+
+    let database = state.database.lock().await;
+    database.get_bandwidth_history(&device_id, range.start, end, resolution).await
+        .map_err(|e| format!("Failed to load bandwidth history: {}", e))
+}
+
+#[tauri::command]
+pub async fn get_history_retention(state: State<'_, AppState>) -> Result<HistoryRetention, String> {
+    let database = state.database.lock().await;
+    database.get_history_retention().await
+        .map_err(|e| format!("Failed to load history retention: {}", e))
+}
+
+#[tauri::command]
+pub async fn update_history_retention(
+    state: State<'_, AppState>,
+    retention: HistoryRetention,
+) -> Result<(), String> {
+    if retention.raw_hours == 0 || retention.minute_days == 0 || retention.hour_days == 0 || retention.day_days == 0 {
+        return Err("Retention periods must be at least 1".to_string());
+    }
+
+    let database = state.database.lock().await;
+    database.set_history_retention(&retention).await
+        .map_err(|e| format!("Failed to save history retention: {}", e))?;
+
+    // Apply the new retention right away instead of waiting for the next pass
+    database.prune_bandwidth_history(&retention).await
+        .map_err(|e| format!("Failed to prune bandwidth history: {}", e))
+}
@@ -0,0 +1,73 @@
+use crate::AppState;
+use crate::modules::inventory::{self, ConflictStrategy, ExportFormat, ImportReport};
+use tauri::State;
+
+#[tauri::command]
+pub async fn export_inventory(
+    state: State<'_, AppState>,
+    path: String,
+    format: Option<ExportFormat>,
+) -> Result<(), String> {
+    if path.is_empty() {
+        return Err("Invalid export path".to_string());
+    }
+
+    let format = format.unwrap_or_else(|| ExportFormat::from_path(&path));
+
+    let database = state.database.lock().await;
+    let export = inventory::build_export(&database).await
+        .map_err(|e| format!("Failed to collect inventory: {}", e))?;
+    drop(database);
+
+    let contents = match format {
+        ExportFormat::Json => inventory::to_json(&export),
+        ExportFormat::Csv => inventory::to_csv(&export),
+    }
+    .map_err(|e| format!("Failed to encode export: {}", e))?;
+
+    tokio::fs::write(&path, contents).await
+        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+
+    log::info!("Exported {} devices to {}", export.devices.len(), path);
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn import_inventory(
+    state: State<'_, AppState>,
+    path: String,
+    format: Option<ExportFormat>,
+    strategy: Option<ConflictStrategy>,
+) -> Result<ImportReport, String> {
+    if path.is_empty() {
+        return Err("Invalid import path".to_string());
+    }
+
+    let format = format.unwrap_or_else(|| ExportFormat::from_path(&path));
+
+    let contents = tokio::fs::read_to_string(&path).await
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let export = inventory::parse(&contents, format)
+        .map_err(|e| format!("Invalid export file: {}", e))?;
+
+    let database = state.database.lock().await;
+    let (report, replaced_quotas) = inventory::merge_into(&database, export, strategy.unwrap_or_default()).await
+        .map_err(|e| format!("Failed to import inventory: {}", e))?;
+    drop(database);
+
+    // Replaced quotas may have had their action applied; undo it
+    let quota_manager = state.quota_manager.lock().await;
+    for quota in &replaced_quotas {
+        if let Err(e) = quota_manager.lift_action(quota).await {
+            log::warn!("Failed to lift quota action for {}: {}", quota.device_id, e);
+        }
+    }
+
+    log::info!(
+        "Imported inventory from {}: {} added, {} updated, {} conflicts",
+        path, report.devices_added, report.devices_updated, report.conflicts.len()
+    );
+
+    Ok(report)
+}
@@ -1,3 +1,15 @@
 pub mod network;
 pub mod device;
-pub mod settings;
\ No newline at end of file
+pub mod settings;
+pub mod events;
+pub mod history;
+pub mod quota;
+pub mod inventory;
+pub mod backup;
+pub mod security;
+pub mod groups;
+pub mod schedule;
+pub mod protection;
+pub mod dry_run;
+pub mod audit;
+pub mod arp_watch;
\ No newline at end of file
@@ -1,5 +1,14 @@
 use crate::AppState;
+use crate::modules::bandwidth::BandwidthLimit;
+use crate::modules::database::{CutMode, Database, DeviceRecord, EventType, InfrastructureEvidence};
+use crate::modules::scanner::{find_shared_macs, NetworkDevice};
+use crate::modules::scheduler::{self, ScheduleTransition};
+use crate::modules::verifier::Verification;
+use crate::utils::safety::{device_id_for_mac, normalize_mac};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::Ipv4Addr;
+use std::time::SystemTime;
 use tauri::State;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,6 +28,8 @@ pub struct Device {
     pub id: String,
     pub name: String,
     pub custom_name: Option<String>,
+    pub notes: Option<String>,
+    pub icon: Option<String>,
     pub ip: String,
     pub mac: String,
     pub manufacturer: Option<String>,
@@ -26,9 +37,15 @@ pub struct Device {
     pub status: String,
     pub bandwidth_current: f64,
     pub bandwidth_limit: Option<f64>,
+    pub bandwidth_limits: Option<BandwidthLimit>,  // Per-direction rates and burst
     pub is_gateway: bool,
     pub is_current_device: bool,
+    pub is_infrastructure: bool,  // A repeater, mesh node or bridge fronting other hosts
     pub last_seen: chrono::DateTime<chrono::Utc>,
+    pub next_transition: Option<ScheduleTransition>,
+    pub cut_remaining_secs: Option<u64>,  // Time left on a timed cut
+    pub cut_mode: Option<CutMode>,  // Set while the device is cut
+    pub verification: Option<Verification>,  // Whether the last cut or restore took effect
 }
 
 #[tauri::command]
@@ -40,19 +57,64 @@ pub async fn scan_network(state: State<'_, AppState>) -> Result<Vec<Device>, Str
 
     log::info!("Starting network scan...");
 
+    // Keep the previous results so we can tell which devices went away
+    let previous_devices = scanner.get_discovered_devices().await;
+
     // Perform the actual network scan
     let scanned_devices = scanner.scan_network().await
         .map_err(|e| format!("Failed to scan network: {}", e))?;
 
+    track_inventory_changes(&state, &previous_devices, &scanned_devices).await;
+    let infrastructure = classify_infrastructure(&state, &scanned_devices).await;
+
+    // Names, notes and icons the user has set, keyed by device ID
+    let database = state.database.lock().await;
+    let records: HashMap<String, DeviceRecord> = match database.get_all_devices().await {
+        Ok(records) => records.into_iter().map(|r| (r.id.clone(), r)).collect(),
+        Err(e) => {
+            log::warn!("Failed to load stored device details: {}", e);
+            HashMap::new()
+        }
+    };
+
+    // Schedules, to show each device's next scheduled change
+    let schedules = database.get_schedules().await.unwrap_or_else(|e| {
+        log::warn!("Failed to load schedules: {}", e);
+        Vec::new()
+    });
+    let groups = database.get_groups().await.unwrap_or_default();
+    drop(database);
+    let now = chrono::Local::now();
+
     // Get our own IP for comparison
     let (_, _, our_ip) = scanner.get_interface_info();
+    drop(scanner);
+
+    // Let cut and limited devices that changed address be followed, then
+    // look up each cut's mode and expiry, keyed by MAC
+    let arp = state.arp_controller.lock().await;
+    let sightings: Vec<(Ipv4Addr, String)> = scanned_devices.iter()
+        .map(|d| (d.ip, d.mac.clone()))
+        .collect();
+    arp.observe_devices(&sightings).await;
+    let cuts: HashMap<String, (CutMode, Option<SystemTime>)> = arp.get_cut_devices().await
+        .into_iter()
+        .map(|s| (s.target_mac, (s.mode, s.expires_at)))
+        .collect();
+    let verifications = arp.get_verifications().await;
+    drop(arp);
+    let now_system = SystemTime::now();
+
+    let bandwidth_controller = state.bandwidth_controller.lock().await;
+    let limits = bandwidth_controller.get_limits().await;
+    drop(bandwidth_controller);
 
     // Convert NetworkDevice to Device format expected by frontend
     let devices: Vec<Device> = scanned_devices
         .into_iter()
         .map(|device| {
             // Generate a unique ID from the MAC address
-            let device_id = device.mac.replace(':', "_").to_lowercase();
+            let device_id = device_id_for_mac(&device.mac);
 
             // Determine if this is our device
             let is_current = our_ip.contains(&device.ip.to_string());
@@ -60,6 +122,18 @@ pub async fn scan_network(state: State<'_, AppState>) -> Result<Vec<Device>, Str
             // Determine device status (all devices start as online)
             let status = "online".to_string();
 
+            let record = records.get(&device_id);
+            let icon = record.and_then(|r| r.icon.clone());
+            let next_transition = scheduler::next_transition(&schedules, &groups, &device_id, now);
+            let mac = normalize_mac(&device.mac).unwrap_or_default();
+            let cut = cuts.get(&mac);
+            let cut_remaining_secs = cut
+                .and_then(|(_, expires_at)| *expires_at)
+                .map(|t| t.duration_since(now_system).map(|d| d.as_secs()).unwrap_or(0));
+            let verification = verifications.get(&mac).cloned();
+            let is_infrastructure = infrastructure.contains(&mac);
+            let limit = limits.get(&mac).copied();
+
             Device {
                 id: device_id,
                 name: device.hostname.clone().unwrap_or_else(|| {
@@ -67,17 +141,26 @@ pub async fn scan_network(state: State<'_, AppState>) -> Result<Vec<Device>, Str
                     let last_octet = device.ip.octets()[3];
                     format!("{}-{}", device.device_type, last_octet)
                 }),
-                custom_name: None,
+                custom_name: record.and_then(|r| r.custom_name.clone()),
+                notes: record.and_then(|r| r.notes.clone()),
                 ip: device.ip.to_string(),
                 mac: device.mac,
                 manufacturer: device.manufacturer,
-                device_type: device.device_type,
+                // The user's icon choice overrides the detected type
+                device_type: icon.clone().unwrap_or(device.device_type),
+                icon,
                 status,
                 bandwidth_current: 0.0, // Will be updated by bandwidth monitoring
-                bandwidth_limit: None,
+                bandwidth_limit: limit.and_then(|l| l.min_mbps()),
+                bandwidth_limits: limit,
                 is_gateway: device.is_gateway,
                 is_current_device: is_current,
+                is_infrastructure,
                 last_seen: chrono::Utc::now(),
+                next_transition,
+                cut_remaining_secs,
+                cut_mode: cut.map(|(mode, _)| *mode),
+                verification,
             }
         })
         .collect();
@@ -88,6 +171,171 @@ pub async fn scan_network(state: State<'_, AppState>) -> Result<Vec<Device>, Str
     Ok(devices)
 }
 
+/// Store scanned devices and record new devices, IP changes and devices going offline
+async fn track_inventory_changes(
+    state: &AppState,
+    previous_devices: &[NetworkDevice],
+    scanned_devices: &[NetworkDevice],
+) {
+    let database = state.database.lock().await;
+    let now = chrono::Utc::now().timestamp();
+
+    for device in scanned_devices {
+        let device_id = device_id_for_mac(&device.mac);
+
+        let existing = match database.get_device_by_mac(&device.mac).await {
+            Ok(existing) => existing,
+            Err(e) => {
+                log::warn!("Failed to load device {} from database: {}", device.mac, e);
+                continue;
+            }
+        };
+
+        let record = match &existing {
+            Some(record) => DeviceRecord {
+                ip: device.ip.to_string(),
+                hostname: device.hostname.clone(),
+                manufacturer: device.manufacturer.clone(),
+                device_type: device.device_type.clone(),
+                last_seen: now,
+                ..record.clone()
+            },
+            None => DeviceRecord {
+                id: device_id.clone(),
+                mac: device.mac.clone(),
+                ip: device.ip.to_string(),
+                hostname: device.hostname.clone(),
+                custom_name: None,
+                manufacturer: device.manufacturer.clone(),
+                device_type: device.device_type.clone(),
+                first_seen: now,
+                last_seen: now,
+                total_bytes: 0,
+                is_blocked: false,
+                bandwidth_limit: None,
+                notes: None,
+                icon: None,
+            },
+        };
+
+        if let Err(e) = database.upsert_device(&record).await {
+            log::warn!("Failed to store device {}: {}", device.mac, e);
+            continue;
+        }
+
+        let event = match &existing {
+            None => Some((EventType::NewDevice, serde_json::json!({
+                "ip": device.ip.to_string(),
+                "mac": device.mac,
+                "manufacturer": device.manufacturer,
+            }))),
+            Some(previous) if previous.ip != record.ip => Some((EventType::IpChanged, serde_json::json!({
+                "oldIp": previous.ip,
+                "newIp": record.ip,
+            }))),
+            _ => None,
+        };
+
+        if let Some((event_type, details)) = event {
+            if let Err(e) = database.record_event(event_type, &record.id, Some(details.to_string())).await {
+                log::warn!("Failed to record {} event for {}: {}", event_type.as_str(), record.id, e);
+            }
+        }
+    }
+
+    for device in previous_devices {
+        if scanned_devices.iter().any(|d| d.mac == device.mac) {
+            continue;
+        }
+
+        let device_id = device_id_for_mac(&device.mac);
+        let details = serde_json::json!({ "ip": device.ip.to_string() });
+        if let Err(e) = database.record_event(EventType::DeviceOffline, &device_id, Some(details.to_string())).await {
+            log::warn!("Failed to record device_offline event for {}: {}", device_id, e);
+        }
+    }
+}
+
+/// Store devices the scan or the packet monitor found fronting several
+/// hosts, and return the MACs (normalised) still classified as infrastructure
+async fn classify_infrastructure(state: &AppState, scanned_devices: &[NetworkDevice]) -> HashSet<String> {
+    let mut detected: HashMap<String, (InfrastructureEvidence, Vec<Ipv4Addr>)> = find_shared_macs(scanned_devices)
+        .into_iter()
+        .map(|(mac, ips)| (mac, (InfrastructureEvidence::SharedArp, ips)))
+        .collect();
+
+    let packet_monitor = state.packet_monitor.lock().await;
+    if let Some(monitor) = packet_monitor.as_ref() {
+        // Seeing the traffic is stronger evidence than the ARP table
+        for (mac, ips) in monitor.get_bridging_macs().await {
+            let entry = detected.entry(mac).or_insert((InfrastructureEvidence::Bridging, Vec::new()));
+            entry.0 = InfrastructureEvidence::Bridging;
+            entry.1.extend(ips);
+            entry.1.sort();
+            entry.1.dedup();
+        }
+    }
+    drop(packet_monitor);
+
+    let database = state.database.lock().await;
+    for (mac, (evidence, ips)) in &detected {
+        match database.record_infrastructure_device(mac, *evidence, ips).await {
+            Ok(true) => {
+                log::info!("Classified {} as infrastructure ({}, {} hosts)", mac, evidence.as_str(), ips.len());
+                if let Err(e) = add_bridge_record(&database, mac, ips).await {
+                    log::warn!("Failed to add infrastructure device {} to the inventory: {}", mac, e);
+                }
+                let device_id = device_id_for_mac(mac);
+                let details = serde_json::json!({
+                    "evidence": evidence.as_str(),
+                    "hosts": ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>(),
+                });
+                if let Err(e) = database.record_event(EventType::InfrastructureDetected, &device_id, Some(details.to_string())).await {
+                    log::warn!("Failed to record infrastructure_detected event for {}: {}", device_id, e);
+                }
+            }
+            Ok(false) => {}
+            Err(e) => log::warn!("Failed to store infrastructure device {}: {}", mac, e),
+        }
+    }
+
+    match database.get_infrastructure_devices().await {
+        Ok(devices) => devices.into_iter().filter(|d| !d.dismissed).filter_map(|d| normalize_mac(&d.mac)).collect(),
+        Err(e) => {
+            log::warn!("Failed to load infrastructure devices: {}", e);
+            HashSet::new()
+        }
+    }
+}
+
+/// Add a device only seen bridging traffic to the inventory, so events can
+/// be recorded against it. Scanned devices are already there.
+async fn add_bridge_record(database: &Database, mac: &str, ips: &[Ipv4Addr]) -> anyhow::Result<()> {
+    // Stored the way the scanner reports MACs, so a later scan updates it
+    let mac = mac.to_uppercase();
+    if database.get_device_by_mac(&mac).await?.is_some() {
+        return Ok(());
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    database.upsert_device(&DeviceRecord {
+        id: device_id_for_mac(&mac),
+        mac,
+        ip: ips.first().map(|ip| ip.to_string()).unwrap_or_default(),
+        hostname: None,
+        custom_name: None,
+        manufacturer: None,
+        device_type: "router".to_string(),
+        first_seen: now,
+        last_seen: now,
+        total_bytes: 0,
+        is_blocked: false,
+        bandwidth_limit: None,
+        notes: None,
+        icon: None,
+    }).await
+}
+
 #[tauri::command]
 pub async fn get_network_info(state: State<'_, AppState>) -> Result<NetworkInfo, String> {
     println!("get_network_info command called");
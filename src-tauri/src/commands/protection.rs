@@ -0,0 +1,89 @@
+use crate::AppState;
+use crate::modules::database::{InfrastructureDevice, ProtectedDevice, ProtectionLevel};
+use crate::utils::safety::normalize_mac;
+use tauri::State;
+
+const MAX_LABEL_LEN: usize = 100;
+
+/// MAC addresses that cuts, limits, group actions, schedules and quotas
+/// all leave alone, or that need confirmation before the user acts on them
+#[tauri::command]
+pub async fn get_protected_devices(state: State<'_, AppState>) -> Result<Vec<ProtectedDevice>, String> {
+    let database = state.database.lock().await;
+    database.get_protected_devices().await
+        .map_err(|e| format!("Failed to load protected devices: {}", e))
+}
+
+#[tauri::command]
+pub async fn add_protected_device(
+    state: State<'_, AppState>,
+    mac: String,
+    label: Option<String>,
+    level: Option<ProtectionLevel>,
+) -> Result<ProtectedDevice, String> {
+    let mac = normalize_mac(&mac).ok_or_else(|| format!("Invalid MAC address: {}", mac))?;
+
+    let label = label.map(|l| l.trim().to_string()).filter(|l| !l.is_empty());
+    if let Some(label) = &label {
+        if label.chars().count() > MAX_LABEL_LEN {
+            return Err(format!("Label too long (max {} characters)", MAX_LABEL_LEN));
+        }
+        if label.chars().any(char::is_control) {
+            return Err("Label cannot contain control characters".to_string());
+        }
+    }
+
+    let database = state.database.lock().await;
+    let device = database.add_protected_device(&mac, label.as_deref(), level.unwrap_or_default()).await
+        .map_err(|e| format!("Failed to protect device: {}", e))?;
+
+    log::info!("Protected device {} ({})", mac, device.level.as_str());
+    Ok(device)
+}
+
+#[tauri::command]
+pub async fn remove_protected_device(
+    state: State<'_, AppState>,
+    mac: String,
+) -> Result<(), String> {
+    let mac = normalize_mac(&mac).ok_or_else(|| format!("Invalid MAC address: {}", mac))?;
+
+    let database = state.database.lock().await;
+    let removed = database.remove_protected_device(&mac).await
+        .map_err(|e| format!("Failed to remove protected device: {}", e))?;
+    if !removed {
+        return Err(format!("Device {} is not protected", mac));
+    }
+
+    log::info!("Removed protection from device {}", mac);
+    Ok(())
+}
+
+/// Devices classified as repeaters, mesh nodes or bridges
+#[tauri::command]
+pub async fn get_infrastructure_devices(state: State<'_, AppState>) -> Result<Vec<InfrastructureDevice>, String> {
+    let database = state.database.lock().await;
+    database.get_infrastructure_devices().await
+        .map_err(|e| format!("Failed to load infrastructure devices: {}", e))
+}
+
+/// Mark a device wrongly classified as infrastructure as an ordinary one,
+/// or undo that
+#[tauri::command]
+pub async fn dismiss_infrastructure_device(
+    state: State<'_, AppState>,
+    mac: String,
+    dismissed: bool,
+) -> Result<(), String> {
+    let mac = normalize_mac(&mac).ok_or_else(|| format!("Invalid MAC address: {}", mac))?;
+
+    let database = state.database.lock().await;
+    let updated = database.dismiss_infrastructure_device(&mac, dismissed).await
+        .map_err(|e| format!("Failed to update infrastructure device: {}", e))?;
+    if !updated {
+        return Err(format!("Device {} is not classified as infrastructure", mac));
+    }
+
+    log::info!("{} infrastructure classification of {}", if dismissed { "Dismissed" } else { "Restored" }, mac);
+    Ok(())
+}
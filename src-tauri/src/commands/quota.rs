@@ -0,0 +1,113 @@
+use crate::AppState;
+use crate::modules::database::{DeviceQuota, QuotaAction, QuotaPeriod};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaConfig {
+    pub period: QuotaPeriod,
+    pub limit_bytes: i64,
+    pub action: QuotaAction,
+    pub limit_mbps: Option<f64>,
+}
+
+#[tauri::command]
+pub async fn get_quotas(state: State<'_, AppState>) -> Result<Vec<DeviceQuota>, String> {
+    let database = state.database.lock().await;
+    database.get_quotas().await
+        .map_err(|e| format!("Failed to load quotas: {}", e))
+}
+
+#[tauri::command]
+pub async fn set_device_quota(
+    state: State<'_, AppState>,
+    device_id: String,
+    config: QuotaConfig,
+) -> Result<DeviceQuota, String> {
+    // Validate input
+    if device_id.is_empty() {
+        return Err("Invalid device ID".to_string());
+    }
+
+    if config.limit_bytes <= 0 {
+        return Err("Quota must be greater than zero".to_string());
+    }
+
+    if config.action == QuotaAction::Limit {
+        match config.limit_mbps {
+            Some(limit) if limit > 0.0 && limit <= 10000.0 => {}
+            _ => return Err("Bandwidth limit must be between 0.1 and 10000 Mbps".to_string()),
+        }
+    }
+
+    let database = state.database.lock().await;
+    let existing = database.get_quota(&device_id).await
+        .map_err(|e| format!("Failed to load quota: {}", e))?;
+    drop(database);
+
+    // Keep usage for the current cycle when only the limit changes
+    let keep_usage = matches!(&existing, Some(q) if q.period == config.period && q.action == config.action);
+
+    let quota = match existing {
+        Some(existing) if keep_usage => DeviceQuota {
+            limit_bytes: config.limit_bytes,
+            limit_mbps: config.limit_mbps,
+            ..existing
+        },
+        existing => {
+            if let Some(previous) = existing.filter(|q| q.enforced) {
+                let quota_manager = state.quota_manager.lock().await;
+                quota_manager.lift_action(&previous).await
+                    .map_err(|e| format!("Failed to lift previous quota action: {}", e))?;
+            }
+
+            DeviceQuota {
+                device_id: device_id.clone(),
+                period: config.period,
+                limit_bytes: config.limit_bytes,
+                action: config.action,
+                limit_mbps: config.limit_mbps,
+                cycle_start: config.period.cycle_start(chrono::Local::now()),
+                used_bytes: 0,
+                enforced: false,
+                enforced_ip: None,
+            }
+        }
+    };
+
+    let database = state.database.lock().await;
+    database.upsert_quota(&quota).await
+        .map_err(|e| format!("Failed to save quota: {}", e))?;
+
+    log::info!("Set {} quota of {} bytes for device {}", quota.period.as_str(), quota.limit_bytes, device_id);
+
+    Ok(quota)
+}
+
+#[tauri::command]
+pub async fn remove_device_quota(
+    state: State<'_, AppState>,
+    device_id: String,
+) -> Result<(), String> {
+    if device_id.is_empty() {
+        return Err("Invalid device ID".to_string());
+    }
+
+    let database = state.database.lock().await;
+    let existing = database.get_quota(&device_id).await
+        .map_err(|e| format!("Failed to load quota: {}", e))?;
+    database.delete_quota(&device_id).await
+        .map_err(|e| format!("Failed to remove quota: {}", e))?;
+    drop(database);
+
+    if let Some(quota) = existing.filter(|q| q.enforced) {
+        let quota_manager = state.quota_manager.lock().await;
+        quota_manager.lift_action(&quota).await
+            .map_err(|e| format!("Failed to lift quota action: {}", e))?;
+    }
+
+    log::info!("Removed quota for device {}", device_id);
+
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use crate::AppState;
+use crate::modules::database::{Schedule, ScheduleAction, ScheduleTarget};
+use chrono::Weekday;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+const MAX_SCHEDULE_NAME_LEN: usize = 50;
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleConfig {
+    pub name: String,
+    pub target: ScheduleTarget,
+    pub target_id: String,
+    pub action: ScheduleAction,
+    pub limit_mbps: Option<f64>,
+    pub days: Vec<Weekday>,
+    pub start_minute: u32,
+    pub end_minute: u32,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[tauri::command]
+pub async fn get_schedules(state: State<'_, AppState>) -> Result<Vec<Schedule>, String> {
+    let database = state.database.lock().await;
+    database.get_schedules().await
+        .map_err(|e| format!("Failed to load schedules: {}", e))
+}
+
+#[tauri::command]
+pub async fn create_schedule(
+    state: State<'_, AppState>,
+    config: ScheduleConfig,
+) -> Result<Schedule, String> {
+    let mut schedule = validate_schedule(&state, 0, config).await?;
+
+    let database = state.database.lock().await;
+    schedule.id = database.create_schedule(&schedule).await
+        .map_err(|e| format!("Failed to create schedule: {}", e))?;
+    drop(database);
+
+    log::info!("Created schedule {} ({})", schedule.name, schedule.id);
+    reevaluate(&state).await;
+
+    Ok(schedule)
+}
+
+#[tauri::command]
+pub async fn update_schedule(
+    state: State<'_, AppState>,
+    schedule_id: i64,
+    config: ScheduleConfig,
+) -> Result<Schedule, String> {
+    let database = state.database.lock().await;
+    let existing = database.get_schedule(schedule_id).await
+        .map_err(|e| format!("Failed to load schedule: {}", e))?
+        .ok_or_else(|| format!("Schedule {} not found", schedule_id))?;
+    drop(database);
+
+    let schedule = Schedule {
+        created_at: existing.created_at,
+        ..validate_schedule(&state, schedule_id, config).await?
+    };
+
+    let database = state.database.lock().await;
+    database.update_schedule(&schedule).await
+        .map_err(|e| format!("Failed to update schedule: {}", e))?;
+    drop(database);
+
+    reevaluate(&state).await;
+
+    Ok(schedule)
+}
+
+#[tauri::command]
+pub async fn delete_schedule(state: State<'_, AppState>, schedule_id: i64) -> Result<(), String> {
+    let database = state.database.lock().await;
+    let deleted = database.delete_schedule(schedule_id).await
+        .map_err(|e| format!("Failed to delete schedule: {}", e))?;
+    drop(database);
+
+    if !deleted {
+        return Err(format!("Schedule {} not found", schedule_id));
+    }
+
+    log::info!("Deleted schedule {}", schedule_id);
+
+    // Lifts anything the schedule had applied
+    reevaluate(&state).await;
+
+    Ok(())
+}
+
+async fn validate_schedule(state: &AppState, id: i64, config: ScheduleConfig) -> Result<Schedule, String> {
+    let name = config.name.trim();
+    if name.is_empty() {
+        return Err("Schedule name cannot be empty".to_string());
+    }
+    if name.chars().count() > MAX_SCHEDULE_NAME_LEN {
+        return Err(format!("Schedule name too long (max {} characters)", MAX_SCHEDULE_NAME_LEN));
+    }
+
+    if config.days.is_empty() {
+        return Err("Schedule must run on at least one day".to_string());
+    }
+
+    if config.start_minute >= MINUTES_PER_DAY || config.end_minute >= MINUTES_PER_DAY {
+        return Err("Schedule times must be between 00:00 and 23:59".to_string());
+    }
+
+    let limit_mbps = match config.action {
+        ScheduleAction::Limit => match config.limit_mbps {
+            Some(limit) if limit > 0.0 && limit <= 10000.0 => Some(limit),
+            _ => return Err("Bandwidth limit must be between 0.1 and 10000 Mbps".to_string()),
+        },
+        ScheduleAction::Cut => None,
+    };
+
+    let database = state.database.lock().await;
+    let target_exists = match config.target {
+        ScheduleTarget::Device => {
+            let devices = database.get_all_devices().await
+                .map_err(|e| format!("Failed to load devices: {}", e))?;
+            devices.iter().any(|d| d.id == config.target_id)
+        }
+        ScheduleTarget::Group => match config.target_id.parse::<i64>() {
+            Ok(group_id) => database.get_group(group_id).await
+                .map_err(|e| format!("Failed to load group: {}", e))?
+                .is_some(),
+            Err(_) => false,
+        },
+    };
+    if !target_exists {
+        return Err(format!("{} {} not found", config.target.as_str(), config.target_id));
+    }
+
+    let mut days = config.days;
+    days.sort_by_key(|d| d.num_days_from_monday());
+    days.dedup();
+
+    Ok(Schedule {
+        id,
+        name: name.to_string(),
+        target: config.target,
+        target_id: config.target_id,
+        action: config.action,
+        limit_mbps,
+        days,
+        start_minute: config.start_minute,
+        end_minute: config.end_minute,
+        enabled: config.enabled,
+        created_at: chrono::Utc::now().timestamp(),
+    })
+}
+
+/// Apply schedule changes now rather than at the next scheduler tick
+async fn reevaluate(state: &AppState) {
+    let scheduler = state.scheduler.lock().await;
+    if let Err(e) = scheduler.evaluate().await {
+        log::warn!("Failed to evaluate schedules: {}", e);
+    }
+}
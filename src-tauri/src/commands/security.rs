@@ -0,0 +1,24 @@
+use crate::AppState;
+use crate::modules::crypto;
+use tauri::State;
+
+/// Re-encrypt stored data under a new key. With a passphrase the key is
+/// derived from it and NETSNIP_PASSPHRASE must be set on later launches;
+/// otherwise a new random key is written to the key file.
+#[tauri::command]
+pub async fn rotate_encryption_key(
+    state: State<'_, AppState>,
+    passphrase: Option<String>,
+) -> Result<usize, String> {
+    let passphrase = passphrase.filter(|p| !p.is_empty());
+    if passphrase.as_ref().is_some_and(|p| p.chars().count() < 8) {
+        return Err("Passphrase must be at least 8 characters".to_string());
+    }
+
+    let mut database = state.database.lock().await;
+    let rotated = crypto::rotate_key(&mut database, passphrase.as_deref()).await
+        .map_err(|e| format!("Failed to rotate encryption key: {}", e))?;
+
+    log::info!("Rotated encryption key, re-encrypted {} values", rotated);
+    Ok(rotated)
+}
@@ -1,4 +1,5 @@
 use crate::AppState;
+use crate::modules::database::{CutRecoveryMode, InfrastructureProtection};
 use serde::{Deserialize, Serialize};
 use tauri::State;
 
@@ -27,20 +28,82 @@ impl Default for Settings {
     }
 }
 
+/// Each field is stored as its own JSON-encoded setting, keyed by field
+/// name, so other modules can read single values such as `self_protection`
 #[tauri::command]
-pub async fn get_settings(_state: State<'_, AppState>) -> Result<Settings, String> {
-    // TODO: Load from database
-    Ok(Settings::default())
+pub async fn get_settings(state: State<'_, AppState>) -> Result<Settings, String> {
+    let mut fields = match serde_json::to_value(Settings::default()) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => return Ok(Settings::default()),
+    };
+
+    let database = state.database.lock().await;
+    for (key, value) in fields.iter_mut() {
+        let stored = database.get_setting(key).await
+            .map_err(|e| format!("Failed to load settings: {}", e))?;
+        // Values that no longer parse keep their default
+        if let Some(parsed) = stored.and_then(|v| serde_json::from_str(&v).ok()) {
+            *value = parsed;
+        }
+    }
+    drop(database);
+
+    serde_json::from_value(serde_json::Value::Object(fields))
+        .map_err(|e| format!("Failed to load settings: {}", e))
 }
 
 #[tauri::command]
 pub async fn update_settings(
-    _state: State<'_, AppState>,
-    _settings: Settings,
+    state: State<'_, AppState>,
+    settings: Settings,
 ) -> Result<(), String> {
-    let mut _database = _state.database.lock().await;
+    let fields = match serde_json::to_value(&settings) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => return Err("Failed to save settings".to_string()),
+    };
 
-    // TODO: Save to database
+    let database = state.database.lock().await;
+    for (key, value) in fields {
+        database.set_setting(&key, &value.to_string()).await
+            .map_err(|e| format!("Failed to save settings: {}", e))?;
+    }
 
     Ok(())
+}
+
+/// What happens at startup to devices left cut by a run that crashed
+#[tauri::command]
+pub async fn get_cut_recovery_mode(state: State<'_, AppState>) -> Result<CutRecoveryMode, String> {
+    let database = state.database.lock().await;
+    database.get_cut_recovery_mode().await
+        .map_err(|e| format!("Failed to load cut recovery mode: {}", e))
+}
+
+#[tauri::command]
+pub async fn update_cut_recovery_mode(
+    state: State<'_, AppState>,
+    mode: CutRecoveryMode,
+) -> Result<(), String> {
+    let database = state.database.lock().await;
+    database.set_cut_recovery_mode(mode).await
+        .map_err(|e| format!("Failed to save cut recovery mode: {}", e))
+}
+
+/// Whether cuts and limits on repeaters and bridges are refused, need
+/// confirmation, or are let through
+#[tauri::command]
+pub async fn get_infrastructure_protection(state: State<'_, AppState>) -> Result<InfrastructureProtection, String> {
+    let database = state.database.lock().await;
+    database.get_infrastructure_protection().await
+        .map_err(|e| format!("Failed to load infrastructure protection: {}", e))
+}
+
+#[tauri::command]
+pub async fn update_infrastructure_protection(
+    state: State<'_, AppState>,
+    protection: InfrastructureProtection,
+) -> Result<(), String> {
+    let database = state.database.lock().await;
+    database.set_infrastructure_protection(protection).await
+        .map_err(|e| format!("Failed to save infrastructure protection: {}", e))
 }
\ No newline at end of file
@@ -4,6 +4,7 @@ mod commands;
 mod modules;
 mod utils;
 
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tauri::Manager;
@@ -13,6 +14,12 @@ use modules::bandwidth::BandwidthController;
 use modules::database::Database;
 use modules::packet_monitor::PacketMonitor;
 use modules::network_stats::NetworkStats;
+use modules::bandwidth_history::BandwidthHistory;
+use modules::quota::QuotaManager;
+use modules::scheduler::Scheduler;
+use modules::watchdog::Watchdog;
+use modules::audit::ConfirmationTokens;
+use modules::crypto;
 use pnet::datalink;
 
 pub struct AppState {
@@ -22,11 +29,50 @@ pub struct AppState {
     pub database: Arc<Mutex<Database>>,
     pub packet_monitor: Arc<Mutex<Option<PacketMonitor>>>,
     pub network_stats: Arc<Mutex<NetworkStats>>,
+    pub bandwidth_history: Arc<Mutex<BandwidthHistory>>,
+    pub quota_manager: Arc<Mutex<QuotaManager>>,
+    pub scheduler: Arc<Mutex<Scheduler>>,
+    pub watchdog: Arc<Mutex<Watchdog>>,
+    pub confirmations: Arc<Mutex<ConfirmationTokens>>,
+}
+
+/// Environment variable that overrides the database location
+const DB_PATH_ENV: &str = "NETSNIP_DB_PATH";
+const DB_FILE_NAME: &str = "netsnip.db";
+
+/// Resolve where the database lives: the override if set, otherwise the
+/// platform app data directory. A database left in the working directory
+/// by older versions is moved over on first run.
+fn resolve_database_path(data_dir: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if let Ok(path) = std::env::var(DB_PATH_ENV) {
+        if !path.is_empty() {
+            return Ok(PathBuf::from(path));
+        }
+    }
+
+    std::fs::create_dir_all(data_dir)?;
+    let db_path = data_dir.join(DB_FILE_NAME);
+
+    let legacy_path = Path::new(DB_FILE_NAME);
+    if !db_path.exists() && legacy_path.is_file() {
+        // Renaming fails across filesystems, where copying and removing works
+        if std::fs::rename(legacy_path, &db_path).is_err() {
+            std::fs::copy(legacy_path, &db_path)?;
+            std::fs::remove_file(legacy_path)?;
+        }
+        log::info!("Moved database from {} to {}", legacy_path.display(), db_path.display());
+    }
+
+    Ok(db_path)
 }
 
 impl AppState {
-    pub async fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let database = Database::new("netsnip.db").await?;
+    pub async fn new(data_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let db_path = resolve_database_path(data_dir)?;
+        log::info!("Using database at {}", db_path.display());
+        let mut database = Database::new(&db_path).await?;
+        crypto::unlock(&mut database).await?;
+        let database = Arc::new(Mutex::new(database));
 
         // Find the network interface for ARP controller
         let interface = datalink::interfaces()
@@ -39,11 +85,13 @@ impl AppState {
             .ok_or("No suitable network interface found")?;
 
         // Create ARP controller with the interface
-        let arp_controller = ArpController::new(interface.clone())?;
+        let mut arp_controller = ArpController::new(interface.clone())?;
+        arp_controller.set_journal(database.clone());
 
         // Try to create packet monitor (may fail if no permissions)
         let packet_monitor = match PacketMonitor::new(Some(interface.name.clone())) {
-            Ok(monitor) => {
+            Ok(mut monitor) => {
+                monitor.set_journal(database.clone());
                 log::info!("Packet monitor initialized");
                 Some(monitor)
             },
@@ -53,13 +101,43 @@ impl AppState {
             }
         };
 
+        // Limited devices are forwarded through the bandwidth controller
+        let bandwidth_controller = Arc::new(Mutex::new(BandwidthController::new()));
+        if let Err(e) = arp_controller.set_bandwidth(bandwidth_controller.clone()) {
+            log::warn!("Could not set up packet forwarding: {}. Bandwidth limits will not work.", e);
+        }
+
+        let scanner = Arc::new(Mutex::new(NetworkScanner::new()?));
+        let arp_controller = Arc::new(Mutex::new(arp_controller));
+        let packet_monitor = Arc::new(Mutex::new(packet_monitor));
+
+        let quota_manager = QuotaManager::new(
+            packet_monitor.clone(),
+            scanner.clone(),
+            database.clone(),
+            arp_controller.clone(),
+        );
+
+        let scheduler = Scheduler::new(
+            scanner.clone(),
+            database.clone(),
+            arp_controller.clone(),
+        );
+
+        let watchdog = Watchdog::new(arp_controller.clone(), database.clone());
+
         Ok(Self {
-            scanner: Arc::new(Mutex::new(NetworkScanner::new()?)),
-            arp_controller: Arc::new(Mutex::new(arp_controller)),
-            bandwidth_controller: Arc::new(Mutex::new(BandwidthController::new())),
-            database: Arc::new(Mutex::new(database)),
-            packet_monitor: Arc::new(Mutex::new(packet_monitor)),
+            scanner,
+            arp_controller,
+            bandwidth_controller,
+            database,
+            packet_monitor,
             network_stats: Arc::new(Mutex::new(NetworkStats::new())),
+            bandwidth_history: Arc::new(Mutex::new(BandwidthHistory::new())),
+            quota_manager: Arc::new(Mutex::new(quota_manager)),
+            scheduler: Arc::new(Mutex::new(scheduler)),
+            watchdog: Arc::new(Mutex::new(watchdog)),
+            confirmations: Arc::new(Mutex::new(ConfirmationTokens::new())),
         })
     }
 }
@@ -70,14 +148,38 @@ fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .setup(|app| {
-            let runtime = tokio::runtime::Runtime::new()?;
-            let app_state = runtime.block_on(AppState::new())?;
+            let data_dir = app.path().app_data_dir()?;
+
+            // Run on Tauri's runtime so background tasks outlive the setup hook
+            let app_state = tauri::async_runtime::block_on(AppState::new(&data_dir))?;
 
             // Try to start monitoring
-            runtime.block_on(async {
+            tauri::async_runtime::block_on(async {
+                // Deal with cuts left behind by a crash before anything else runs
+                let database = app_state.database.lock().await;
+                let recovery_mode = database.get_cut_recovery_mode().await.unwrap_or_else(|e| {
+                    log::warn!("Could not load cut recovery mode: {}", e);
+                    Default::default()
+                });
+                drop(database);
+
+                let mut arp = app_state.arp_controller.lock().await;
+                if let Err(e) = arp.recover_journal(recovery_mode).await {
+                    log::warn!("Could not recover journaled cuts: {}", e);
+                }
+                drop(arp);
+
+                // Conflicting claims on the gateway's address are the ones
+                // that matter most to the ARP spoofing detection
+                let gateway = app_state.scanner.lock().await.get_gateway().await;
+
                 // First try packet monitoring if available
                 let packet_monitor = app_state.packet_monitor.lock().await;
                 if let Some(monitor) = packet_monitor.as_ref() {
+                    match gateway {
+                        Ok((gateway_ip, _)) => monitor.arp_watch().set_gateway(gateway_ip).await,
+                        Err(e) => log::warn!("Could not determine gateway for ARP spoofing detection: {}", e),
+                    }
                     if let Err(e) = monitor.start_monitoring().await {
                         log::warn!("Could not start packet monitoring: {}", e);
                     } else {
@@ -93,6 +195,38 @@ fn main() {
                 } else {
                     log::info!("Network stats monitoring started");
                 }
+                drop(network_stats);
+
+                // Record per-device bandwidth history for usage charts
+                let bandwidth_history = app_state.bandwidth_history.lock().await;
+                if let Err(e) = bandwidth_history.start_sampling(
+                    app_state.packet_monitor.clone(),
+                    app_state.scanner.clone(),
+                    app_state.database.clone(),
+                ).await {
+                    log::warn!("Could not start bandwidth history sampling: {}", e);
+                }
+                drop(bandwidth_history);
+
+                // Track data usage against per-device quotas
+                let quota_manager = app_state.quota_manager.lock().await;
+                if let Err(e) = quota_manager.start_enforcement().await {
+                    log::warn!("Could not start quota enforcement: {}", e);
+                }
+                drop(quota_manager);
+
+                // Apply and lift scheduled cuts and limits
+                let scheduler = app_state.scheduler.lock().await;
+                if let Err(e) = scheduler.start().await {
+                    log::warn!("Could not start scheduler: {}", e);
+                }
+                drop(scheduler);
+
+                // Back out of every cut if spoofing costs us our own connection
+                let watchdog = app_state.watchdog.lock().await;
+                if let Err(e) = watchdog.start().await {
+                    log::warn!("Could not start connectivity watchdog: {}", e);
+                }
             });
 
             app.manage(app_state);
@@ -110,14 +244,87 @@ fn main() {
             commands::network::get_network_info,
             commands::device::get_devices,
             commands::device::cut_device,
+            commands::device::set_cut_timer,
+            commands::device::extend_cut_timer,
             commands::device::restore_device,
+            commands::device::restore_all,
             commands::device::limit_bandwidth,
             commands::device::remove_bandwidth_limit,
             commands::device::get_bandwidth_updates,
+            commands::device::get_bandwidth_stats,
             commands::device::update_device_name,
+            commands::device::update_device_notes,
+            commands::device::update_device_icon,
             commands::settings::get_settings,
             commands::settings::update_settings,
+            commands::settings::get_cut_recovery_mode,
+            commands::settings::update_cut_recovery_mode,
+            commands::settings::get_infrastructure_protection,
+            commands::settings::update_infrastructure_protection,
+            commands::events::get_events,
+            commands::history::get_device_history,
+            commands::history::get_history_retention,
+            commands::history::update_history_retention,
+            commands::quota::get_quotas,
+            commands::quota::set_device_quota,
+            commands::quota::remove_device_quota,
+            commands::inventory::export_inventory,
+            commands::inventory::import_inventory,
+            commands::backup::backup_database,
+            commands::backup::restore_database,
+            commands::security::rotate_encryption_key,
+            commands::groups::get_groups,
+            commands::groups::create_group,
+            commands::groups::update_group,
+            commands::groups::delete_group,
+            commands::groups::add_devices_to_group,
+            commands::groups::remove_devices_from_group,
+            commands::groups::cut_group,
+            commands::groups::restore_group,
+            commands::groups::limit_group_bandwidth,
+            commands::groups::remove_group_bandwidth_limit,
+            commands::groups::set_group_protected,
+            commands::schedule::get_schedules,
+            commands::schedule::create_schedule,
+            commands::schedule::update_schedule,
+            commands::schedule::delete_schedule,
+            commands::protection::get_protected_devices,
+            commands::protection::add_protected_device,
+            commands::protection::remove_protected_device,
+            commands::protection::get_infrastructure_devices,
+            commands::protection::dismiss_infrastructure_device,
+            commands::arp_watch::get_arp_alerts,
+            commands::arp_watch::clear_arp_alerts,
+            commands::arp_watch::get_arp_history,
+            commands::dry_run::set_dry_run,
+            commands::dry_run::get_dry_run_log,
+            commands::dry_run::clear_dry_run_log,
+            commands::audit::get_audit_log,
+            commands::audit::verify_audit_log,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app_handle, event| {
+            if let tauri::RunEvent::Exit = event {
+                // Give every cut device its connection back before exiting
+                let app_state = app_handle.state::<AppState>();
+                tauri::async_runtime::block_on(async {
+                    // Background tasks are stopped first so none of them
+                    // acts on a device after it has been restored
+                    if let Err(e) = app_state.bandwidth_history.lock().await.stop_sampling().await {
+                        log::warn!("Could not stop bandwidth sampling: {}", e);
+                    }
+                    if let Err(e) = app_state.quota_manager.lock().await.stop_enforcement().await {
+                        log::warn!("Could not stop quota enforcement: {}", e);
+                    }
+                    if let Err(e) = app_state.scheduler.lock().await.stop().await {
+                        log::warn!("Could not stop scheduler: {}", e);
+                    }
+                    if let Err(e) = app_state.watchdog.lock().await.stop().await {
+                        log::warn!("Could not stop connectivity watchdog: {}", e);
+                    }
+                    commands::device::restore_all_on_exit(&app_state).await;
+                });
+            }
+        });
 }
\ No newline at end of file
@@ -1,22 +1,52 @@
 use anyhow::Result;
-use pnet::datalink::{self, Channel, NetworkInterface};
+use pnet::datalink::NetworkInterface;
 use pnet::packet::arp::{ArpHardwareTypes, ArpOperations, MutableArpPacket};
 use pnet::packet::ethernet::{EtherTypes, MutableEthernetPacket};
 use pnet::packet::Packet;
 use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
 use tokio::time::{interval, sleep};
+use crate::utils::safety::{device_id_for_mac, normalize_mac};
+use crate::modules::bandwidth::{BandwidthController, BandwidthLimit};
+use crate::modules::database::{CutJournalEntry, CutMode, CutRecoveryMode, Database, EventType};
+use crate::modules::device_tracker::DeviceTracker;
+use crate::modules::forwarder::Forwarder;
+use crate::modules::gateway::{GatewayMonitor, GatewayProbe};
+use crate::modules::healer::Healer;
+use crate::modules::scanner::NetworkScanner;
+use crate::modules::transmitter::{DryRunFrame, Transmitter};
+use crate::modules::verifier::{Verification, VerifiedAction, Verifier};
 
 pub struct ArpController {
     interface: NetworkInterface,
+    transmitter: Transmitter,
     our_mac: [u8; 6],
     pub gateway_ip: Option<Ipv4Addr>,
-    gateway_mac: Option<[u8; 6]>,
-    active_spoofs: Arc<Mutex<HashMap<Ipv4Addr, ArpSpoof>>>,
+    gateway_mac: Arc<Mutex<Option<[u8; 6]>>>,
+    gateway_suspect: Arc<Mutex<bool>>,  // Traffic contradicted the gateway MAC
+    active_spoofs: Arc<Mutex<HashMap<String, ArpSpoof>>>,  // Keyed by MAC, so cuts follow the device
+    vacated: Arc<Mutex<HashMap<Ipv4Addr, ArpSpoof>>>,
+    sightings: Arc<Mutex<HashMap<String, Ipv4Addr>>>,
+    lan_hosts: Arc<Mutex<HashMap<Ipv4Addr, [u8; 6]>>>,  // Hosts seen by scans, for full isolation
     spoofing_active: Arc<Mutex<bool>>,
+    last_tick: Arc<Mutex<Option<Instant>>>,  // When the spoofing loop last ran
+    journal: Option<Arc<Mutex<Database>>>,
+    bandwidth: Option<Arc<Mutex<BandwidthController>>>,
+    forwarder: Option<Forwarder>,
+    verifier: Option<Verifier>,
+    healer: Option<Healer>,  // Follows up restores; needs the verifier
+}
+
+/// State of the spoofing loop, as checked by the watchdog
+pub struct SpoofHealth {
+    pub redirected: usize,
+    pub loop_running: bool,
+    pub last_tick: Option<Instant>,
+    pub dry_run: bool,
+    pub probe: Option<GatewayProbe>,  // None until the gateway is known
 }
 
 #[derive(Clone, Debug)]
@@ -27,6 +57,34 @@ pub struct ArpSpoof {
     pub gateway_mac: String,
     pub active: bool,
     pub cut_time: SystemTime,
+    pub expires_at: Option<SystemTime>,  // Restored automatically at this time
+    pub forward: bool,  // Redirected through the forwarder to limit it, not cut
+    pub mode: CutMode,
+}
+
+impl ArpSpoof {
+    pub(crate) fn journal_entry(&self) -> CutJournalEntry {
+        CutJournalEntry {
+            target_ip: self.target_ip.to_string(),
+            target_mac: self.target_mac.clone(),
+            gateway_ip: self.gateway_ip.to_string(),
+            gateway_mac: self.gateway_mac.clone(),
+            cut_at: unix_secs(self.cut_time),
+            expires_at: self.expires_at.map(unix_secs),
+            forward: self.forward,
+            mode: self.mode.as_str().to_string(),
+        }
+    }
+}
+
+/// The key a device's spoof is stored under. Spoofs are only made for MACs
+/// that normalise, so one that does not matches none.
+fn spoof_key(mac: &str) -> String {
+    normalize_mac(mac).unwrap_or_default()
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
 }
 
 impl ArpController {
@@ -34,24 +92,184 @@ impl ArpController {
         interface: NetworkInterface,
     ) -> Result<Self> {
         let our_mac = interface.mac.map(|m| m.octets()).unwrap_or([0; 6]);
+        let transmitter = Transmitter::new(interface.clone());
+        let verifier = match Verifier::new(interface.clone()) {
+            Ok(verifier) => Some(verifier),
+            Err(e) => {
+                log::warn!("Cuts and restores will not be verified: {}", e);
+                None
+            }
+        };
+        let our_ip = interface.ips.iter().find_map(|ip| match ip.ip() {
+            IpAddr::V4(ipv4) => Some(ipv4),
+            _ => None,
+        });
+        let healer = verifier.clone().zip(our_ip).map(|(verifier, our_ip)| Healer {
+            transmitter: transmitter.clone(),
+            verifier,
+            our_mac,
+            our_ip,
+            journal: None,
+        });
 
         Ok(Self {
+            transmitter,
             interface,
             our_mac,
             gateway_ip: None,
-            gateway_mac: None,
+            gateway_mac: Arc::new(Mutex::new(None)),
+            gateway_suspect: Arc::new(Mutex::new(false)),
             active_spoofs: Arc::new(Mutex::new(HashMap::new())),
+            vacated: Arc::new(Mutex::new(HashMap::new())),
+            sightings: Arc::new(Mutex::new(HashMap::new())),
+            lan_hosts: Arc::new(Mutex::new(HashMap::new())),
             spoofing_active: Arc::new(Mutex::new(false)),
+            last_tick: Arc::new(Mutex::new(None)),
+            journal: None,
+            bandwidth: None,
+            forwarder: None,
+            verifier,
+            healer,
         })
     }
 
-    pub fn set_gateway(&mut self, gateway_ip: Ipv4Addr, gateway_mac: String) -> Result<()> {
+    /// Record active cuts in the database so they can be undone after a crash
+    pub fn set_journal(&mut self, database: Arc<Mutex<Database>>) {
+        if let Some(healer) = self.healer.as_mut() {
+            healer.journal = Some(database.clone());
+        }
+        self.journal = Some(database);
+    }
+
+    /// Enable bandwidth limiting: limited devices are redirected to us and
+    /// their frames forwarded through their token bucket
+    pub fn set_bandwidth(&mut self, bandwidth: Arc<Mutex<BandwidthController>>) -> Result<()> {
+        let forwarder = Forwarder::new(
+            self.interface.clone(),
+            self.active_spoofs.clone(),
+            bandwidth.clone(),
+            self.gateway_ip,
+            self.gateway_mac.clone(),
+            self.gateway_suspect.clone(),
+            self.sightings.clone(),
+        )?;
+        self.bandwidth = Some(bandwidth);
+        self.forwarder = Some(forwarder);
+        Ok(())
+    }
+
+    fn tracker(&self) -> DeviceTracker {
+        DeviceTracker {
+            spoofs: self.active_spoofs.clone(),
+            transmitter: self.transmitter.clone(),
+            journal: self.journal.clone(),
+            vacated: self.vacated.clone(),
+            pending: self.sightings.clone(),
+        }
+    }
+
+    /// Tell the controller where devices were seen, e.g. after a scan, so
+    /// cut and limited devices that changed address are followed
+    pub async fn observe_devices(&self, sightings: &[(Ipv4Addr, String)]) {
+        self.tracker().observe(sightings).await;
+
+        let mut lan_hosts = self.lan_hosts.lock().await;
+        for (ip, mac) in sightings {
+            if let Ok(mac) = Self::parse_mac(mac) {
+                lan_hosts.insert(*ip, mac);
+            }
+        }
+    }
+
+    /// Set the gateway new cuts are made against. The MAC is re-verified
+    /// while spoofing, so a stale lookup here is corrected within a check.
+    pub async fn set_gateway(&mut self, gateway_ip: Ipv4Addr, gateway_mac: String) -> Result<()> {
+        let gateway_mac = Self::parse_mac(&gateway_mac)?;
         self.gateway_ip = Some(gateway_ip);
-        self.gateway_mac = Some(Self::parse_mac(&gateway_mac)?);
+        *self.gateway_mac.lock().await = Some(gateway_mac);
+        if let Some(forwarder) = &self.forwarder {
+            forwarder.set_gateway_ip(gateway_ip).await;
+        }
+        Ok(())
+    }
+
+    /// The gateway new cuts are made against, looked up with `scanner` if
+    /// none has been set yet
+    pub async fn ensure_gateway(&mut self, scanner: &Mutex<NetworkScanner>) -> Result<Ipv4Addr> {
+        if let Some(gateway_ip) = self.gateway_ip {
+            return Ok(gateway_ip);
+        }
+        let (gateway_ip, gateway_mac) = scanner.lock().await.get_gateway().await?;
+        self.set_gateway(gateway_ip, gateway_mac).await?;
+        Ok(gateway_ip)
+    }
+
+    pub async fn cut_device(&self, target_ip: Ipv4Addr, target_mac: String, mode: CutMode) -> Result<()> {
+        self.spoof_device(target_ip, target_mac, false, mode).await
+    }
+
+    /// Limit a device's bandwidth. Unless it is cut, the device is redirected
+    /// to us and its traffic forwarded within `limit`.
+    pub async fn limit_device(&self, target_ip: Ipv4Addr, target_mac: String, limit: BandwidthLimit) -> Result<()> {
+        let (Some(bandwidth), Some(forwarder)) = (&self.bandwidth, &self.forwarder) else {
+            return Err(anyhow::anyhow!("Packet forwarding is not available"));
+        };
+
+        bandwidth.lock().await.limit_bandwidth(&target_mac, limit).await?;
+
+        // A cut device picks up the limit when it is restored
+        let redirected = self.active_spoofs.lock().await.contains_key(&spoof_key(&target_mac));
+        if redirected {
+            return Ok(());
+        }
+
+        let result = match self.spoof_device(target_ip, target_mac.clone(), true, CutMode::InternetOnly).await {
+            Ok(()) => forwarder.start().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            bandwidth.lock().await.remove_limit(&target_mac).await?;
+            self.release_device(&target_mac).await?;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Remove a device's bandwidth limit, releasing it if it was only
+    /// redirected for the limit
+    pub async fn remove_limit(&self, target_mac: &str) -> Result<()> {
+        let Some(spoof) = self.active_spoofs.lock().await.get(&spoof_key(target_mac)).cloned() else {
+            return Ok(());
+        };
+
+        if let Some(bandwidth) = &self.bandwidth {
+            bandwidth.lock().await.remove_limit(target_mac).await?;
+        }
+
+        if spoof.forward {
+            self.release_device(target_mac).await?;
+        }
+
         Ok(())
     }
 
-    pub async fn cut_device(&self, target_ip: Ipv4Addr, target_mac: String) -> Result<()> {
+    /// The bandwidth limit on a device, if it has one
+    pub async fn get_limit(&self, target_mac: &str) -> Option<BandwidthLimit> {
+        match &self.bandwidth {
+            Some(bandwidth) => bandwidth.lock().await.get_limit(target_mac).await,
+            None => None,
+        }
+    }
+
+    async fn has_limit(&self, target_mac: &str) -> bool {
+        self.get_limit(target_mac).await.is_some()
+    }
+
+    async fn spoof_device(&self, target_ip: Ipv4Addr, target_mac: String, forward: bool, mode: CutMode) -> Result<()> {
+        let target_mac = normalize_mac(&target_mac)
+            .ok_or_else(|| anyhow::anyhow!("Invalid MAC address {}", target_mac))?;
+
         // Safety check: prevent self-blocking
         let our_ip = self.get_our_ip()?;
         if target_ip == our_ip {
@@ -60,7 +278,7 @@ impl ArpController {
 
         // Check if gateway is set
         let gateway_ip = self.gateway_ip.ok_or_else(|| anyhow::anyhow!("Gateway not configured"))?;
-        let gateway_mac = self.gateway_mac.ok_or_else(|| anyhow::anyhow!("Gateway MAC not configured"))?;
+        let gateway_mac = self.gateway_mac.lock().await.ok_or_else(|| anyhow::anyhow!("Gateway MAC not configured"))?;
 
         // Don't cut the gateway
         if target_ip == gateway_ip {
@@ -75,32 +293,86 @@ impl ArpController {
         );
 
         // Add to active spoofs
-        let mut spoofs = self.active_spoofs.lock().await;
-        spoofs.insert(target_ip, ArpSpoof {
+        let spoof = ArpSpoof {
             target_ip,
             target_mac: target_mac.clone(),
             gateway_ip,
             gateway_mac: gateway_mac_str.clone(),
             active: true,
             cut_time: SystemTime::now(),
-        });
+            expires_at: None,
+            forward,
+            mode,
+        };
+        let mut spoofs = self.active_spoofs.lock().await;
+        let previous = spoofs.insert(target_mac.clone(), spoof.clone());
         drop(spoofs);
 
-        log::info!("Cutting device {} ({})", target_ip, target_mac);
+        // Cut again with internet-only mode after full isolation
+        if let Some(previous) = previous.filter(|p| p.mode == CutMode::FullIsolation && mode != CutMode::FullIsolation) {
+            Self::restore_peers(&self.transmitter, &self.lan_hosts, &previous, self.our_mac).await?;
+        }
+
+        // Journal the cut before any poison goes out
+        self.journal_cut(&spoof.journal_entry()).await;
+
+        if forward {
+            log::info!("Redirecting device {} ({}) through the forwarder", target_ip, target_mac);
+        } else {
+            log::info!("Cutting device {} ({}), mode: {}", target_ip, target_mac, mode.as_str());
+        }
 
         // Start ARP spoofing
         self.start_spoofing().await?;
 
         // Send initial poison packets
         self.send_arp_poison(target_ip, &target_mac, gateway_ip, &gateway_mac_str).await?;
+        if mode == CutMode::FullIsolation {
+            let frames = Self::peer_frames(&spoof, &*self.lan_hosts.lock().await, self.our_mac, true);
+            if !frames.is_empty() {
+                self.transmitter.send(frames).await?;
+            }
+        }
 
+        Self::verify(&self.transmitter, self.verifier.as_ref(), &spoof, VerifiedAction::Cut).await;
         Ok(())
     }
 
-    pub async fn restore_device(&self, target_ip: Ipv4Addr) -> Result<()> {
+    pub async fn restore_device(&self, target_mac: &str) -> Result<()> {
+        let key = spoof_key(target_mac);
+        let Some(target_ip) = self.active_spoofs.lock().await.get(&key).map(|s| s.target_ip) else {
+            return Ok(());
+        };
+
+        // A limited device keeps being forwarded instead of being released
+        if self.has_limit(&key).await {
+            if let Some(forwarder) = &self.forwarder {
+                let mut spoofs = self.active_spoofs.lock().await;
+                if let Some(spoof) = spoofs.get_mut(&key) {
+                    let isolated = spoof.clone();
+                    spoof.forward = true;
+                    spoof.expires_at = None;
+                    spoof.mode = CutMode::InternetOnly;
+                    let entry = spoof.journal_entry();
+                    drop(spoofs);
+
+                    log::info!("Restoring device {} through the forwarder to keep its bandwidth limit", target_ip);
+                    Self::restore_peers(&self.transmitter, &self.lan_hosts, &isolated, self.our_mac).await?;
+                    self.journal_cut(&entry).await;
+                    return forwarder.start().await;
+                }
+            }
+        }
+
+        self.release_device(target_mac).await
+    }
+
+    /// Stop redirecting a device and give it its real ARP entries back
+    async fn release_device(&self, target_mac: &str) -> Result<()> {
         let mut spoofs = self.active_spoofs.lock().await;
-        if let Some(mut spoof) = spoofs.remove(&target_ip) {
+        if let Some(mut spoof) = spoofs.remove(&spoof_key(target_mac)) {
             spoof.active = false;
+            let target_ip = spoof.target_ip;
             let target_mac = spoof.target_mac.clone();
             let gateway_mac = spoof.gateway_mac.clone();
             let gateway_ip = spoof.gateway_ip;
@@ -110,6 +382,9 @@ impl ArpController {
 
             // Send restoration packets
             self.send_arp_restore(target_ip, &target_mac, gateway_ip, &gateway_mac).await?;
+            Self::restore_peers(&self.transmitter, &self.lan_hosts, &spoof, self.our_mac).await?;
+            self.clear_journal_entry(&target_mac).await;
+            Self::heal(self.healer.as_ref(), &spoof).await;
 
             // Check if we should stop spoofing
             let spoofs = self.active_spoofs.lock().await;
@@ -122,14 +397,260 @@ impl ArpController {
         Ok(())
     }
 
-    pub async fn is_device_cut(&self, target_ip: Ipv4Addr) -> bool {
+    /// Set when a cut device is restored automatically; `None` keeps it cut
+    /// until restored by hand
+    pub async fn set_expiry(&self, target_mac: &str, expires_at: Option<SystemTime>) -> Result<()> {
+        let mut spoofs = self.active_spoofs.lock().await;
+        let spoof = spoofs.get_mut(&spoof_key(target_mac))
+            .filter(|s| s.active && !s.forward)
+            .ok_or_else(|| anyhow::anyhow!("Device {} is not cut", target_mac))?;
+        spoof.expires_at = expires_at;
+        let entry = spoof.journal_entry();
+        drop(spoofs);
+
+        self.journal_cut(&entry).await;
+        Ok(())
+    }
+
+    /// Restore every redirected device and stop spoofing, e.g. on shutdown
+    /// or in an emergency. Bandwidth limits are dropped along with the
+    /// redirects enforcing them. Devices whose restore packets could not be
+    /// sent stay in the journal for the next start. Returns the devices
+    /// restored.
+    pub async fn restore_all(&self) -> Result<Vec<ArpSpoof>> {
+        let spoofs: Vec<ArpSpoof> = {
+            let mut spoofs = self.active_spoofs.lock().await;
+            spoofs.drain().map(|(_, spoof)| spoof).collect()
+        };
+        self.stop_spoofing().await?;
+
+        let mut restored = Vec::new();
+        for spoof in spoofs {
+            if let Some(bandwidth) = &self.bandwidth {
+                if let Err(e) = bandwidth.lock().await.remove_limit(&spoof.target_mac).await {
+                    log::warn!("Failed to remove bandwidth limit of {}: {}", spoof.target_ip, e);
+                }
+            }
+
+            let restored_all = match self.send_arp_restore(spoof.target_ip, &spoof.target_mac, spoof.gateway_ip, &spoof.gateway_mac).await {
+                Ok(()) => Self::restore_peers(&self.transmitter, &self.lan_hosts, &spoof, self.our_mac).await,
+                Err(e) => Err(e),
+            };
+            match restored_all {
+                Ok(()) => {
+                    self.clear_journal_entry(&spoof.target_mac).await;
+                    Self::heal(self.healer.as_ref(), &spoof).await;
+                    restored.push(spoof);
+                }
+                Err(e) => log::warn!("Failed to restore device {}: {}", spoof.target_ip, e),
+            }
+        }
+
+        log::info!("Restored {} redirected devices", restored.len());
+        Ok(restored)
+    }
+
+    /// Handle cuts journaled by a previous run that did not shut down
+    /// cleanly, either restoring the devices or cutting them again
+    pub async fn recover_journal(&mut self, mode: CutRecoveryMode) -> Result<usize> {
+        let Some(journal) = self.journal.clone() else {
+            return Ok(0);
+        };
+
+        let entries = journal.lock().await.get_cut_journal().await?;
+        if entries.is_empty() {
+            return Ok(0);
+        }
+
+        log::warn!("Found {} cuts left from a previous run, recovery mode: {}", entries.len(), mode.as_str());
+
+        let mut recovered = 0;
+        for entry in entries {
+            let result = match entry.target_ip.parse::<Ipv4Addr>() {
+                Ok(target_ip) => self.recover_entry(target_ip, &entry, mode).await,
+                Err(_) => {
+                    // Nothing we can send to; drop the entry
+                    self.clear_journal_entry(&entry.target_mac).await;
+                    Err(anyhow::anyhow!("Invalid journaled IP address"))
+                }
+            };
+
+            match result {
+                Ok(()) => recovered += 1,
+                Err(e) => log::warn!("Failed to recover cut of {}: {}", entry.target_ip, e),
+            }
+        }
+
+        Ok(recovered)
+    }
+
+    async fn recover_entry(&mut self, target_ip: Ipv4Addr, entry: &CutJournalEntry, mode: CutRecoveryMode) -> Result<()> {
+        let gateway_ip: Ipv4Addr = entry.gateway_ip.parse()
+            .map_err(|_| anyhow::anyhow!("Invalid journaled gateway address"))?;
+
+        // A timed cut that ran out while we were down is restored either way,
+        // as is a device that was only redirected for a bandwidth limit
+        let expires_at = entry.expires_at.map(|secs| UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64));
+        let mode = if entry.forward || expires_at.is_some_and(|t| t <= SystemTime::now()) {
+            CutRecoveryMode::Restore
+        } else {
+            mode
+        };
+
+        match mode {
+            CutRecoveryMode::Restore => {
+                // Hosts a fully isolated device was cut off from are not
+                // known yet; their ARP caches correct themselves once the
+                // poisoning has stopped
+                self.send_arp_restore(target_ip, &entry.target_mac, gateway_ip, &entry.gateway_mac).await?;
+                self.clear_journal_entry(&entry.target_mac).await;
+                log::info!("Restored device {} left cut by a previous run", target_ip);
+            }
+            CutRecoveryMode::Reapply => {
+                if self.gateway_ip.is_none() {
+                    self.set_gateway(gateway_ip, entry.gateway_mac.clone()).await?;
+                }
+                let cut_mode = CutMode::parse(&entry.mode).unwrap_or_default();
+                self.cut_device(target_ip, entry.target_mac.clone(), cut_mode).await?;
+                // Still the same cut, so it keeps its time
+                if let Some(spoof) = self.active_spoofs.lock().await.get_mut(&spoof_key(&entry.target_mac)) {
+                    spoof.cut_time = UNIX_EPOCH + Duration::from_secs(entry.cut_at.max(0) as u64);
+                }
+                self.set_expiry(&entry.target_mac, expires_at).await?;
+                log::info!("Re-applied cut of device {} from a previous run", target_ip);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn journal_cut(&self, entry: &CutJournalEntry) {
+        if let Some(journal) = &self.journal {
+            let database = journal.lock().await;
+            if let Err(e) = database.add_cut_journal_entry(entry).await {
+                log::warn!("Failed to journal cut of {}: {}", entry.target_ip, e);
+            }
+        }
+    }
+
+    async fn clear_journal_entry(&self, target_mac: &str) {
+        if let Some(journal) = &self.journal {
+            let database = journal.lock().await;
+            if let Err(e) = database.remove_cut_journal_entry(target_mac).await {
+                log::warn!("Failed to clear journal entry for {}: {}", target_mac, e);
+            }
+        }
+    }
+
+    pub async fn health(&self) -> SpoofHealth {
+        let redirected = self.active_spoofs.lock().await.len();
+        let loop_running = *self.spoofing_active.lock().await;
+        let last_tick = *self.last_tick.lock().await;
+
+        let probe = match (self.gateway_ip, self.get_our_ip()) {
+            (Some(gateway_ip), Ok(our_ip)) => Some(GatewayProbe {
+                interface: self.interface.clone(),
+                transmitter: self.transmitter.clone(),
+                our_mac: self.our_mac,
+                our_ip,
+                gateway_ip,
+            }),
+            _ => None,
+        };
+
+        SpoofHealth {
+            redirected,
+            loop_running,
+            last_tick,
+            dry_run: self.transmitter.is_dry_run().await,
+            probe,
+        }
+    }
+
+    pub async fn is_device_cut(&self, target_mac: &str) -> bool {
+        let spoofs = self.active_spoofs.lock().await;
+        spoofs.get(&spoof_key(target_mac)).is_some_and(|s| s.active && !s.forward)
+    }
+
+    /// When a device's current redirect was made, as a Unix timestamp. A
+    /// later cut replaces the redirect and moves this forward.
+    pub async fn redirected_at(&self, target_mac: &str) -> Option<i64> {
+        let spoofs = self.active_spoofs.lock().await;
+        spoofs.get(&spoof_key(target_mac)).map(|s| unix_secs(s.cut_time))
+    }
+
+    /// The cut of a device, wherever it is now
+    pub async fn get_cut(&self, target_mac: &str) -> Option<ArpSpoof> {
         let spoofs = self.active_spoofs.lock().await;
-        spoofs.contains_key(&target_ip) && spoofs[&target_ip].active
+        spoofs.get(&spoof_key(target_mac)).filter(|s| s.active && !s.forward).cloned()
     }
 
     pub async fn get_cut_devices(&self) -> Vec<ArpSpoof> {
         let spoofs = self.active_spoofs.lock().await;
-        spoofs.values().filter(|s| s.active).cloned().collect()
+        spoofs.values().filter(|s| s.active && !s.forward).cloned().collect()
+    }
+
+    /// In dry-run mode every frame is recorded instead of sent, so a cut or
+    /// restore can be previewed without touching the network. Switching is
+    /// refused while devices are redirected, so a previewed cut never turns
+    /// into a real one.
+    pub async fn set_dry_run(&self, enabled: bool) -> Result<()> {
+        if self.transmitter.is_dry_run().await == enabled {
+            return Ok(());
+        }
+        if !self.active_spoofs.lock().await.is_empty() {
+            return Err(anyhow::anyhow!("Restore all devices before switching dry-run mode"));
+        }
+
+        self.transmitter.set_dry_run(enabled).await;
+        log::info!("Dry-run mode {}", if enabled { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    pub async fn is_dry_run(&self) -> bool {
+        self.transmitter.is_dry_run().await
+    }
+
+    pub async fn dry_run_frames(&self) -> Vec<DryRunFrame> {
+        self.transmitter.dry_run_frames().await
+    }
+
+    pub async fn clear_dry_run_frames(&self) {
+        self.transmitter.clear_dry_run_frames().await
+    }
+
+    /// Outcome of each device's last cut or restore, keyed by MAC
+    pub async fn get_verifications(&self) -> HashMap<String, Verification> {
+        match &self.verifier {
+            Some(verifier) => verifier.get_verifications().await,
+            None => HashMap::new(),
+        }
+    }
+
+    /// Verify a restore, correcting the device's ARP cache again until it
+    /// is confirmed
+    async fn heal(healer: Option<&Healer>, spoof: &ArpSpoof) {
+        if let Some(healer) = healer {
+            healer.heal(spoof).await;
+        }
+    }
+
+    async fn verify(transmitter: &Transmitter, verifier: Option<&Verifier>, spoof: &ArpSpoof, action: VerifiedAction) {
+        // Nothing went out in dry-run mode, so there is nothing to confirm
+        if transmitter.is_dry_run().await {
+            return;
+        }
+        if let Some(verifier) = verifier {
+            if let Err(e) = verifier.watch(&spoof.target_mac, &spoof.gateway_mac, action).await {
+                log::warn!("Cannot verify {} of {}: {}", action.as_str(), spoof.target_ip, e);
+            }
+        }
+    }
+
+    /// Devices redirected through the forwarder for a bandwidth limit
+    pub async fn get_forwarded_devices(&self) -> Vec<ArpSpoof> {
+        let spoofs = self.active_spoofs.lock().await;
+        spoofs.values().filter(|s| s.active && s.forward).cloned().collect()
     }
 
     fn parse_mac(mac_str: &str) -> Result<[u8; 6]> {
@@ -199,11 +720,43 @@ impl ArpController {
         }
         *spoofing = true;
         drop(spoofing);
+        *self.last_tick.lock().await = Some(Instant::now());
 
         let active_spoofs = self.active_spoofs.clone();
         let spoofing_active = self.spoofing_active.clone();
-        let interface = self.interface.clone();
+        let last_tick = self.last_tick.clone();
+        let transmitter = self.transmitter.clone();
         let our_mac = self.our_mac;
+        let journal = self.journal.clone();
+        let bandwidth = self.bandwidth.clone();
+        let forwarder = self.forwarder.clone();
+        let tracker = self.tracker();
+        let healer = self.healer.clone();
+        let lan_hosts = self.lan_hosts.clone();
+
+        // Without our own address and the gateway's the gateway cannot be probed
+        let mut gateway_monitor = match self.get_our_ip().and_then(|our_ip| {
+            let gateway_ip = self.gateway_ip.ok_or_else(|| anyhow::anyhow!("Gateway not configured"))?;
+            Ok((our_ip, gateway_ip))
+        }) {
+            Ok((our_ip, gateway_ip)) => Some(GatewayMonitor {
+                interface: self.interface.clone(),
+                transmitter: transmitter.clone(),
+                our_mac,
+                our_ip,
+                gateway_ip,
+                gateway_mac: self.gateway_mac.clone(),
+                suspect: self.gateway_suspect.clone(),
+                spoofs: active_spoofs.clone(),
+                journal: journal.clone(),
+                last_check: None,
+                pending: None,
+            }),
+            Err(e) => {
+                log::warn!("Gateway MAC will not be re-verified: {}", e);
+                None
+            }
+        };
 
         // Spawn spoofing task
         tokio::spawn(async move {
@@ -218,37 +771,100 @@ impl ArpController {
                         break;
                     }
                 }
+                *last_tick.lock().await = Some(Instant::now());
+
+                // Follow redirected devices the forwarder saw at a new address
+                tracker.process_pending().await;
+
+                // Restore devices whose timed cut has run out
+                let now = SystemTime::now();
+                let expired: Vec<(String, Ipv4Addr)> = {
+                    let spoofs = active_spoofs.lock().await;
+                    spoofs.iter()
+                        .filter(|(_, s)| s.expires_at.is_some_and(|t| t <= now))
+                        .map(|(mac, s)| (mac.clone(), s.target_ip))
+                        .collect()
+                };
+                for (target_mac, target_ip) in expired {
+                    // Limited devices go back to being forwarded
+                    let limited = match (&bandwidth, &forwarder) {
+                        (Some(bandwidth), Some(_)) => bandwidth.lock().await.get_limit(&target_mac).await.is_some(),
+                        _ => false,
+                    };
+
+                    let mut spoofs = active_spoofs.lock().await;
+                    if limited {
+                        let Some(spoof) = spoofs.get_mut(&target_mac) else { continue };
+                        let isolated = spoof.clone();
+                        spoof.forward = true;
+                        spoof.expires_at = None;
+                        spoof.mode = CutMode::InternetOnly;
+                        let spoof = spoof.clone();
+                        drop(spoofs);
+                        if let Err(e) = Self::restore_peers(&transmitter, &lan_hosts, &isolated, our_mac).await {
+                            log::warn!("Failed to reconnect {} to the LAN: {}", target_ip, e);
+                        }
+                        Self::expire_cut(&transmitter, journal.as_ref(), healer.as_ref(), &spoof).await;
+                        if let Some(forwarder) = &forwarder {
+                            if let Err(e) = forwarder.start().await {
+                                log::warn!("Failed to start forwarding for {}: {}", target_ip, e);
+                            }
+                        }
+                    } else {
+                        let Some(spoof) = spoofs.remove(&target_mac) else { continue };
+                        drop(spoofs);
+                        if let Err(e) = Self::restore_peers(&transmitter, &lan_hosts, &spoof, our_mac).await {
+                            log::warn!("Failed to reconnect {} to the LAN: {}", target_ip, e);
+                        }
+                        Self::expire_cut(&transmitter, journal.as_ref(), healer.as_ref(), &spoof).await;
+                    }
+                }
+
+                // Follow the gateway if its MAC has changed
+                if let Some(monitor) = gateway_monitor.as_mut() {
+                    monitor.check().await;
+                }
 
-                // Send poison packets for all active spoofs
+                // Send poison packets for all active spoofs in one batch
                 let spoofs = active_spoofs.lock().await;
+                if spoofs.is_empty() {
+                    drop(spoofs);
+                    *spoofing_active.lock().await = false;
+                    break;
+                }
+                let lan = lan_hosts.lock().await;
+                let mut frames = Vec::with_capacity(spoofs.len() * 2);
                 for spoof in spoofs.values().filter(|s| s.active) {
-                    // Create channel for sending
-                    if let Ok(Channel::Ethernet(mut tx, _)) = datalink::channel(&interface, Default::default()) {
-                        // Parse MACs
-                        if let (Ok(target_mac), Ok(gateway_mac)) =
-                            (Self::parse_mac(&spoof.target_mac), Self::parse_mac(&spoof.gateway_mac)) {
-
-                            // Create poison packets
-                            let packet_to_target = Self::create_arp_reply_static(
-                                spoof.target_ip,
-                                target_mac,
-                                spoof.gateway_ip,
-                                our_mac,  // Pretend to be gateway
-                            );
-
-                            let packet_to_gateway = Self::create_arp_reply_static(
-                                spoof.gateway_ip,
-                                gateway_mac,
-                                spoof.target_ip,
-                                our_mac,  // Pretend to be target
-                            );
-
-                            // Send packets
-                            let _ = tx.send_to(&packet_to_target, None);
-                            let _ = tx.send_to(&packet_to_gateway, None);
-                        }
+                    if spoof.mode == CutMode::FullIsolation && !spoof.forward {
+                        frames.extend(Self::peer_frames(spoof, &lan, our_mac, true));
+                    }
+
+                    // Parse MACs
+                    if let (Ok(target_mac), Ok(gateway_mac)) =
+                        (Self::parse_mac(&spoof.target_mac), Self::parse_mac(&spoof.gateway_mac)) {
+
+                        // Create poison packets
+                        frames.push(Self::create_arp_reply_static(
+                            spoof.target_ip,
+                            target_mac,
+                            spoof.gateway_ip,
+                            our_mac,  // Pretend to be gateway
+                        ));
+
+                        frames.push(Self::create_arp_reply_static(
+                            spoof.gateway_ip,
+                            gateway_mac,
+                            spoof.target_ip,
+                            our_mac,  // Pretend to be target
+                        ));
                     }
                 }
+                drop(lan);
+                drop(spoofs);
+
+                if let Err(e) = transmitter.send(frames).await {
+                    log::warn!("Failed to send ARP poison packets: {}", e);
+                }
             }
             log::info!("ARP spoofing stopped");
         });
@@ -264,13 +880,6 @@ impl ArpController {
     }
 
     async fn send_arp_poison(&self, target_ip: Ipv4Addr, target_mac: &str, gateway_ip: Ipv4Addr, gateway_mac: &str) -> Result<()> {
-        // Create channel
-        let (mut tx, _) = match datalink::channel(&self.interface, Default::default()) {
-            Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
-            Ok(_) => return Err(anyhow::anyhow!("Unhandled channel type")),
-            Err(e) => return Err(anyhow::anyhow!("Failed to create channel: {}", e)),
-        };
-
         let target_mac_bytes = Self::parse_mac(target_mac)?;
         let gateway_mac_bytes = Self::parse_mac(gateway_mac)?;
 
@@ -290,52 +899,140 @@ impl ArpController {
             self.our_mac,
         );
 
-        let _ = tx.send_to(&packet_to_target, None)
-            .ok_or_else(|| anyhow::anyhow!("Failed to send ARP packet"))?;
-        let _ = tx.send_to(&packet_to_gateway, None)
-            .ok_or_else(|| anyhow::anyhow!("Failed to send ARP packet"))?;
-
-        Ok(())
+        self.transmitter.send(vec![packet_to_target, packet_to_gateway]).await
     }
 
     async fn send_arp_restore(&self, target_ip: Ipv4Addr, target_mac: &str, gateway_ip: Ipv4Addr, gateway_mac: &str) -> Result<()> {
-        // Create channel
-        let (mut tx, _) = match datalink::channel(&self.interface, Default::default()) {
-            Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
-            Ok(_) => return Err(anyhow::anyhow!("Unhandled channel type")),
-            Err(e) => return Err(anyhow::anyhow!("Failed to create channel: {}", e)),
-        };
+        Self::send_restore_packets(&self.transmitter, target_ip, target_mac, gateway_ip, gateway_mac).await
+    }
 
+    pub(crate) async fn send_restore_packets(
+        transmitter: &Transmitter,
+        target_ip: Ipv4Addr,
+        target_mac: &str,
+        gateway_ip: Ipv4Addr,
+        gateway_mac: &str,
+    ) -> Result<()> {
         let target_mac_bytes = Self::parse_mac(target_mac)?;
         let gateway_mac_bytes = Self::parse_mac(gateway_mac)?;
 
         // Restore correct MACs
-        let packet_to_target = self.create_arp_reply(
+        let packet_to_target = Self::create_arp_reply_static(
             target_ip,
             target_mac_bytes,
             gateway_ip,
             gateway_mac_bytes,  // Correct gateway MAC
         );
 
-        let packet_to_gateway = self.create_arp_reply(
+        let packet_to_gateway = Self::create_arp_reply_static(
             gateway_ip,
             gateway_mac_bytes,
             target_ip,
             target_mac_bytes,  // Correct target MAC
         );
 
+        // Announce the gateway to everyone as well, for hosts that picked
+        // up a poisoned entry we did not aim at them
+        let gratuitous = Self::create_arp_reply_static(
+            gateway_ip,
+            [0xff; 6],
+            gateway_ip,
+            gateway_mac_bytes,
+        );
+
         // Send multiple times to ensure restoration
         for _ in 0..3 {
-            let _ = tx.send_to(&packet_to_target, None)
-                .ok_or_else(|| anyhow::anyhow!("Failed to send restore packet"))?;
-            let _ = tx.send_to(&packet_to_gateway, None)
-                .ok_or_else(|| anyhow::anyhow!("Failed to send restore packet"))?;
+            transmitter.send(vec![packet_to_target.clone(), packet_to_gateway.clone(), gratuitous.clone()]).await?;
             sleep(Duration::from_millis(100)).await;
         }
 
         Ok(())
     }
 
+    /// ARP replies between a fully isolated device and the other hosts on
+    /// the LAN. With `poison` each side is told the other is at our MAC,
+    /// otherwise they are given each other's real address.
+    fn peer_frames(spoof: &ArpSpoof, lan_hosts: &HashMap<Ipv4Addr, [u8; 6]>, our_mac: [u8; 6], poison: bool) -> Vec<Vec<u8>> {
+        let Ok(target_mac) = Self::parse_mac(&spoof.target_mac) else {
+            return Vec::new();
+        };
+
+        let mut frames = Vec::new();
+        for (&peer_ip, &peer_mac) in lan_hosts {
+            // The gateway is handled by the regular poison and restore
+            if peer_ip == spoof.target_ip || peer_ip == spoof.gateway_ip || peer_mac == our_mac || peer_mac == target_mac {
+                continue;
+            }
+            let (as_peer, as_target) = if poison { (our_mac, our_mac) } else { (peer_mac, target_mac) };
+            frames.push(Self::create_arp_reply_static(spoof.target_ip, target_mac, peer_ip, as_peer));
+            frames.push(Self::create_arp_reply_static(peer_ip, peer_mac, spoof.target_ip, as_target));
+        }
+        frames
+    }
+
+    /// Give a fully isolated device and the other LAN hosts each other's
+    /// real addresses back. Does nothing for internet-only cuts.
+    async fn restore_peers(
+        transmitter: &Transmitter,
+        lan_hosts: &Mutex<HashMap<Ipv4Addr, [u8; 6]>>,
+        spoof: &ArpSpoof,
+        our_mac: [u8; 6],
+    ) -> Result<()> {
+        if spoof.mode != CutMode::FullIsolation {
+            return Ok(());
+        }
+
+        let frames = Self::peer_frames(spoof, &*lan_hosts.lock().await, our_mac, false);
+        if frames.is_empty() {
+            return Ok(());
+        }
+
+        log::info!("Reconnecting {} to {} LAN hosts", spoof.target_ip, frames.len() / 2);
+        for _ in 0..3 {
+            transmitter.send(frames.clone()).await?;
+            sleep(Duration::from_millis(100)).await;
+        }
+        Ok(())
+    }
+
+    /// Restore a device whose timed cut has run out. A device still being
+    /// forwarded for a bandwidth limit keeps its redirect.
+    async fn expire_cut(
+        transmitter: &Transmitter,
+        journal: Option<&Arc<Mutex<Database>>>,
+        healer: Option<&Healer>,
+        spoof: &ArpSpoof,
+    ) {
+        log::info!("Timed cut of {} ({}) expired, restoring", spoof.target_ip, spoof.target_mac);
+
+        if !spoof.forward {
+            if let Err(e) = Self::send_restore_packets(transmitter, spoof.target_ip, &spoof.target_mac, spoof.gateway_ip, &spoof.gateway_mac).await {
+                // Left in the journal so the next start restores it
+                log::warn!("Failed to restore device {} after its timed cut: {}", spoof.target_ip, e);
+                return;
+            }
+            Self::heal(healer, spoof).await;
+        }
+
+        if let Some(journal) = journal {
+            let database = journal.lock().await;
+            let journaled = if spoof.forward {
+                database.add_cut_journal_entry(&spoof.journal_entry()).await
+            } else {
+                database.remove_cut_journal_entry(&spoof.target_mac).await
+            };
+            if let Err(e) = journaled {
+                log::warn!("Failed to update journal entry for {}: {}", spoof.target_ip, e);
+            }
+
+            let device_id = device_id_for_mac(&spoof.target_mac);
+            let details = serde_json::json!({ "ip": spoof.target_ip.to_string(), "reason": "expired" });
+            if let Err(e) = database.record_event(EventType::DeviceRestored, &device_id, Some(details.to_string())).await {
+                log::warn!("Failed to record device_restored event for {}: {}", device_id, e);
+            }
+        }
+    }
+
     fn create_arp_reply_static(
         target_ip: Ipv4Addr,
         target_mac: [u8; 6],
@@ -365,4 +1062,84 @@ impl ArpController {
         ethernet_packet.set_payload(arp_packet.packet());
         buffer
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use pnet::ipnetwork::{IpNetwork, Ipv4Network};
+    use pnet::util::MacAddr;
+    use crate::modules::transmitter::ArpFields;
+
+    const OUR_MAC: &str = "02:00:00:00:00:01";
+    const GATEWAY_MAC: &str = "02:00:00:00:00:fe";
+    const DEVICE_MAC: &str = "02:00:00:00:00:10";
+    const GATEWAY_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 1);
+    const DEVICE_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+
+    fn epoch() -> DateTime<Utc> {
+        DateTime::UNIX_EPOCH
+    }
+
+    async fn dry_run_controller() -> ArpController {
+        let interface = NetworkInterface {
+            name: "test0".to_string(),
+            description: String::new(),
+            index: 0,
+            mac: Some(OUR_MAC.parse::<MacAddr>().unwrap()),
+            ips: vec![IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 1, 2), 24).unwrap())],
+            flags: 0,
+        };
+        let mut controller = ArpController::new(interface).unwrap();
+        controller.set_gateway(GATEWAY_IP, GATEWAY_MAC.to_string()).await.unwrap();
+        controller.transmitter.switch_dry_run(true, epoch).await;
+        controller
+    }
+
+    fn reply(sender_mac: &str, sender_ip: Ipv4Addr, target_mac: &str, target_ip: Ipv4Addr) -> ArpFields {
+        ArpFields {
+            operation: "reply".to_string(),
+            sender_mac: sender_mac.to_string(),
+            sender_ip: sender_ip.to_string(),
+            target_mac: target_mac.to_string(),
+            target_ip: target_ip.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run_cut_and_restore_log_exact_frames() {
+        let controller = dry_run_controller().await;
+
+        controller.cut_device(DEVICE_IP, DEVICE_MAC.to_string(), CutMode::InternetOnly).await.unwrap();
+        controller.restore_device(DEVICE_MAC).await.unwrap();
+
+        let frames = controller.dry_run_frames().await;
+        let logged: Vec<(u64, u64, ArpFields)> = frames.into_iter()
+            .map(|f| (f.seq, f.batch, f.arp.expect("every frame is ARP")))
+            .collect();
+
+        let mut expected = vec![
+            // Poison: the device is told we are the gateway and the gateway we are the device
+            (0, 0, reply(OUR_MAC, GATEWAY_IP, DEVICE_MAC, DEVICE_IP)),
+            (1, 0, reply(OUR_MAC, DEVICE_IP, GATEWAY_MAC, GATEWAY_IP)),
+        ];
+        for batch in 1..=3 {
+            let seq = 2 + (batch - 1) * 3;
+            expected.push((seq, batch, reply(GATEWAY_MAC, GATEWAY_IP, DEVICE_MAC, DEVICE_IP)));
+            expected.push((seq + 1, batch, reply(DEVICE_MAC, DEVICE_IP, GATEWAY_MAC, GATEWAY_IP)));
+            expected.push((seq + 2, batch, reply(GATEWAY_MAC, GATEWAY_IP, "ff:ff:ff:ff:ff:ff", GATEWAY_IP)));
+        }
+        assert_eq!(logged, expected);
+    }
+
+    #[tokio::test]
+    async fn dry_run_skips_verification() {
+        let controller = dry_run_controller().await;
+
+        controller.cut_device(DEVICE_IP, DEVICE_MAC.to_string(), CutMode::InternetOnly).await.unwrap();
+        controller.restore_device(DEVICE_MAC).await.unwrap();
+
+        assert!(controller.get_verifications().await.is_empty());
+        assert!(!controller.is_device_cut(DEVICE_MAC).await);
+    }
+}
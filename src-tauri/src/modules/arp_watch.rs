@@ -0,0 +1,404 @@
+use pnet::packet::arp::ArpPacket;
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
+use pnet::packet::Packet;
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use crate::modules::database::{Database, EventType};
+use crate::modules::vendor::VendorLookup;
+
+/// A second MAC claiming an address within this many seconds of the last
+/// claim by another counts as a conflict rather than the address moving
+const CONFLICT_WINDOW_SECS: i64 = 300;
+/// Seconds before an ongoing alert is written to the event log again
+const ALERT_COOLDOWN_SECS: i64 = 600;
+const MAX_CLAIMS_PER_IP: usize = 16;
+const MAX_ALERTS: usize = 100;
+
+/// A MAC address announcing itself as the owner of an IP
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArpClaim {
+    pub mac: String,
+    pub first_seen: i64,  // Unix timestamp
+    pub last_seen: i64,   // Unix timestamp
+    pub count: u64,
+}
+
+/// Who has claimed an IP, oldest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IpHistory {
+    pub ip: String,
+    pub claims: Vec<ArpClaim>,
+}
+
+/// Another host answering for an address that was already taken, which is
+/// what someone intercepting traffic with ARP spoofing looks like
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpoofAlert {
+    pub ip: String,
+    pub mac: String,                   // The MAC that took the address over
+    pub previous_mac: String,          // The MAC that held it
+    pub vendor: Option<String>,
+    pub is_gateway: bool,
+    pub first_seen: i64,               // Unix timestamp
+    pub last_seen: i64,                // Unix timestamp
+    pub count: u64,                    // Conflicting claims seen
+    pub timeline: Vec<ArpClaim>,       // Claims on the IP when last raised
+    #[serde(skip)]
+    last_recorded: i64,                // When it last went to the event log
+}
+
+#[derive(Default)]
+struct WatchState {
+    claims: HashMap<Ipv4Addr, Vec<ArpClaim>>,
+    alerts: Vec<SpoofAlert>,
+    gateway_ip: Option<Ipv4Addr>,
+}
+
+/// Watches captured ARP traffic for addresses claimed by more than one MAC.
+/// Claims made with our own MAC are our cuts and are left out.
+#[derive(Clone)]
+pub struct ArpWatch {
+    our_mac: Option<MacAddr>,
+    state: Arc<Mutex<WatchState>>,
+    vendor_lookup: Arc<VendorLookup>,
+    journal: Option<mpsc::UnboundedSender<SpoofAlert>>,  // Alerts for the event log writer
+}
+
+impl ArpWatch {
+    pub fn new(our_mac: Option<MacAddr>) -> Self {
+        Self {
+            our_mac,
+            state: Arc::new(Mutex::new(WatchState::default())),
+            vendor_lookup: Arc::new(VendorLookup::new()),
+            journal: None,
+        }
+    }
+
+    /// Record alerts in the event log of `database`. They are written by a
+    /// task of their own so capture never waits for the database.
+    pub fn set_journal(&mut self, database: Arc<Mutex<Database>>) {
+        let (journal, alerts) = mpsc::unbounded_channel();
+        tokio::spawn(record_alerts(database, alerts));
+        self.journal = Some(journal);
+    }
+
+    /// Conflicts over the gateway's address are flagged as such; they put
+    /// every host's internet traffic through the spoofer
+    pub async fn set_gateway(&self, gateway_ip: Ipv4Addr) {
+        self.state.lock().await.gateway_ip = Some(gateway_ip);
+    }
+
+    pub async fn inspect(&self, ethernet: &EthernetPacket<'_>) {
+        if ethernet.get_ethertype() != EtherTypes::Arp {
+            return;
+        }
+        let Some(arp) = ArpPacket::new(ethernet.payload()) else {
+            return;
+        };
+
+        let ip = arp.get_sender_proto_addr();
+        let mac = arp.get_sender_hw_addr();
+        // Probes come from 0.0.0.0 and claim nothing
+        if ip.is_unspecified() || mac == MacAddr::zero() || mac.is_broadcast() || mac.is_multicast() {
+            return;
+        }
+        if Some(mac) == self.our_mac || Some(ethernet.get_source()) == self.our_mac {
+            return;
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        if let Some(alert) = self.record_claim(ip, mac.to_string(), now).await {
+            self.raise(alert);
+        }
+    }
+
+    /// Add a claim made at `now` to the IP's history, returning the alert to
+    /// record if it conflicts with a recent claim by another MAC
+    async fn record_claim(&self, ip: Ipv4Addr, mac: String, now: i64) -> Option<SpoofAlert> {
+        let mut state = self.state.lock().await;
+        let is_gateway = state.gateway_ip == Some(ip);
+
+        let claims = state.claims.entry(ip).or_default();
+        if let Some(last) = claims.last_mut().filter(|c| c.mac == mac) {
+            last.last_seen = now;
+            last.count += 1;
+            return None;
+        }
+
+        let previous = claims
+            .iter()
+            .rev()
+            .find(|c| c.mac != mac && now - c.last_seen <= CONFLICT_WINDOW_SECS)
+            .map(|c| c.mac.clone());
+
+        claims.push(ArpClaim { mac: mac.clone(), first_seen: now, last_seen: now, count: 1 });
+        if claims.len() > MAX_CLAIMS_PER_IP {
+            claims.remove(0);
+        }
+        let timeline = claims.clone();
+
+        let previous_mac = previous?;
+        let ip_str = ip.to_string();
+
+        // The address flipping back and forth is one conflict, and the MAC
+        // that first took it over stays the one blamed
+        let ongoing = state.alerts.iter_mut().find(|a| {
+            a.ip == ip_str
+                && ((a.mac == mac && a.previous_mac == previous_mac) || (a.mac == previous_mac && a.previous_mac == mac))
+        });
+        if let Some(alert) = ongoing {
+            alert.last_seen = now;
+            alert.count += 1;
+            alert.timeline = timeline;
+            if now - alert.last_recorded < ALERT_COOLDOWN_SECS {
+                return None;
+            }
+            alert.last_recorded = now;
+            return Some(alert.clone());
+        }
+
+        let alert = SpoofAlert {
+            ip: ip_str,
+            vendor: self.vendor_lookup.lookup(&mac),
+            mac,
+            previous_mac,
+            is_gateway,
+            first_seen: now,
+            last_seen: now,
+            count: 1,
+            timeline,
+            last_recorded: now,
+        };
+        state.alerts.push(alert.clone());
+        if state.alerts.len() > MAX_ALERTS {
+            state.alerts.remove(0);
+        }
+        Some(alert)
+    }
+
+    fn raise(&self, alert: SpoofAlert) {
+        log::warn!(
+            "Possible ARP spoofing: {} ({}) claims {}{}, held by {}",
+            alert.mac,
+            alert.vendor.as_deref().unwrap_or("unknown vendor"),
+            alert.ip,
+            if alert.is_gateway { " (the gateway)" } else { "" },
+            alert.previous_mac,
+        );
+
+        if let Some(journal) = &self.journal {
+            if journal.send(alert).is_err() {
+                log::warn!("ARP spoofing alert writer has stopped");
+            }
+        }
+    }
+
+    /// Alerts raised since startup, most recent first
+    pub async fn get_alerts(&self) -> Vec<SpoofAlert> {
+        let state = self.state.lock().await;
+        state.alerts.iter().rev().cloned().collect()
+    }
+
+    pub async fn clear_alerts(&self) {
+        self.state.lock().await.alerts.clear();
+    }
+
+    /// IP to MAC history of every address seen in ARP traffic
+    pub async fn get_history(&self) -> Vec<IpHistory> {
+        let state = self.state.lock().await;
+        let mut ips: Vec<&Ipv4Addr> = state.claims.keys().collect();
+        ips.sort();
+        ips.into_iter()
+            .map(|ip| IpHistory { ip: ip.to_string(), claims: state.claims[ip].clone() })
+            .collect()
+    }
+}
+
+/// Write alerts to the event log as they are raised
+async fn record_alerts(database: Arc<Mutex<Database>>, mut alerts: mpsc::UnboundedReceiver<SpoofAlert>) {
+    while let Some(alert) = alerts.recv().await {
+        record_alert(&*database.lock().await, &alert).await;
+    }
+}
+
+async fn record_alert(database: &Database, alert: &SpoofAlert) {
+    let details = match serde_json::to_string(alert) {
+        Ok(details) => details,
+        Err(e) => {
+            log::warn!("Failed to encode ARP spoofing alert: {}", e);
+            return;
+        }
+    };
+
+    // Events belong to devices in the inventory. A spoofer no scan has
+    // found is logged against the device whose address it took.
+    let mut device = None;
+    for mac in [&alert.mac, &alert.previous_mac] {
+        match database.get_device_by_mac(mac).await {
+            Ok(Some(record)) => {
+                device = Some(record);
+                break;
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to look up device {}: {}", mac, e),
+        }
+    }
+    let Some(device) = device else {
+        log::warn!("Not logging ARP spoofing alert for {}: neither MAC is a known device", alert.ip);
+        return;
+    };
+
+    if let Err(e) = database.record_event(EventType::ArpSpoofDetected, &device.id, Some(details)).await {
+        log::warn!("Failed to record arp_spoof_detected event for {}: {}", device.id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::database::{DeviceRecord, EventQuery};
+
+    const IP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+    const GATEWAY_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 1);
+    const OWNER: &str = "02:00:00:00:00:10";
+    const SPOOFER: &str = "02:00:00:00:00:66";
+    const START: i64 = 1_700_000_000;
+
+    fn watch() -> ArpWatch {
+        ArpWatch::new(Some("02:00:00:00:00:01".parse().unwrap()))
+    }
+
+    async fn claim(watch: &ArpWatch, mac: &str, at: i64) -> Option<SpoofAlert> {
+        watch.record_claim(IP, mac.to_string(), START + at).await
+    }
+
+    #[tokio::test]
+    async fn repeated_claims_by_one_mac_are_counted_not_raised() {
+        let watch = watch();
+        for at in 0..5 {
+            assert!(claim(&watch, OWNER, at).await.is_none());
+        }
+
+        let history = watch.get_history().await;
+        assert_eq!(history[0].claims.len(), 1);
+        assert_eq!(history[0].claims[0].count, 5);
+        assert_eq!(history[0].claims[0].last_seen, START + 4);
+    }
+
+    #[tokio::test]
+    async fn second_mac_within_the_window_is_a_conflict() {
+        let watch = watch();
+        claim(&watch, OWNER, 0).await;
+
+        let alert = claim(&watch, SPOOFER, CONFLICT_WINDOW_SECS).await.unwrap();
+        assert_eq!(alert.mac, SPOOFER);
+        assert_eq!(alert.previous_mac, OWNER);
+        assert!(!alert.is_gateway);
+        assert_eq!(alert.timeline.len(), 2);
+        assert_eq!(watch.get_alerts().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn second_mac_after_the_window_is_the_address_moving() {
+        let watch = watch();
+        claim(&watch, OWNER, 0).await;
+
+        assert!(claim(&watch, SPOOFER, CONFLICT_WINDOW_SECS + 1).await.is_none());
+        assert!(watch.get_alerts().await.is_empty());
+        assert_eq!(watch.get_history().await[0].claims.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn flip_flopping_is_one_alert_blaming_the_first_taker() {
+        let watch = watch();
+        claim(&watch, OWNER, 0).await;
+        claim(&watch, SPOOFER, 10).await.unwrap();
+
+        // The owner winning the address back is the same conflict
+        assert!(claim(&watch, OWNER, 20).await.is_none());
+        assert!(claim(&watch, SPOOFER, 30).await.is_none());
+
+        let alerts = watch.get_alerts().await;
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].mac, SPOOFER);
+        assert_eq!(alerts[0].previous_mac, OWNER);
+        assert_eq!(alerts[0].count, 3);
+        assert_eq!(alerts[0].first_seen, START + 10);
+        assert_eq!(alerts[0].last_seen, START + 30);
+    }
+
+    #[tokio::test]
+    async fn ongoing_conflict_is_raised_again_after_the_cooldown() {
+        let watch = watch();
+        claim(&watch, OWNER, 0).await;
+        claim(&watch, SPOOFER, 10).await.unwrap();
+
+        // Keep the conflict going with a flip inside each window
+        assert!(claim(&watch, OWNER, 210).await.is_none());
+        assert!(claim(&watch, SPOOFER, 410).await.is_none());
+        assert!(claim(&watch, OWNER, 9 + ALERT_COOLDOWN_SECS).await.is_none());
+
+        let alert = claim(&watch, SPOOFER, 10 + ALERT_COOLDOWN_SECS).await.unwrap();
+        assert_eq!(alert.mac, SPOOFER);
+        assert_eq!(alert.first_seen, START + 10);
+        assert_eq!(watch.get_alerts().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn conflicts_over_the_gateway_are_flagged() {
+        let watch = watch();
+        watch.set_gateway(GATEWAY_IP).await;
+        watch.record_claim(GATEWAY_IP, OWNER.to_string(), START).await;
+
+        let alert = watch.record_claim(GATEWAY_IP, SPOOFER.to_string(), START + 1).await.unwrap();
+        assert!(alert.is_gateway);
+    }
+
+    #[tokio::test]
+    async fn alerts_are_written_to_the_event_log_by_the_writer_task() {
+        let path = std::env::temp_dir().join(format!("netsnip-arp-watch-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let database = Database::new(&path).await.unwrap();
+        database.upsert_device(&DeviceRecord {
+            id: OWNER.replace(':', "_"),
+            mac: OWNER.to_string(),
+            ip: IP.to_string(),
+            hostname: None,
+            custom_name: None,
+            manufacturer: None,
+            device_type: "unknown".to_string(),
+            first_seen: START,
+            last_seen: START,
+            total_bytes: 0,
+            is_blocked: false,
+            bandwidth_limit: None,
+            notes: None,
+            icon: None,
+        }).await.unwrap();
+        let database = Arc::new(Mutex::new(database));
+
+        let mut watch = watch();
+        watch.set_journal(database.clone());
+        claim(&watch, OWNER, 0).await;
+        watch.raise(claim(&watch, SPOOFER, 10).await.unwrap());
+
+        let query = EventQuery { event_types: vec![EventType::ArpSpoofDetected], ..Default::default() };
+        let mut recorded = 0;
+        for _ in 0..50 {
+            recorded = database.lock().await.get_events(&query).await.unwrap().events.len();
+            if recorded > 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(recorded, 1);
+        let _ = std::fs::remove_file(&path);
+    }
+}
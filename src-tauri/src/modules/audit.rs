@@ -0,0 +1,109 @@
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use crate::modules::database::{AuditEntry, AuditRecord, Database};
+
+/// `prev_hash` of the first entry in the audit log
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// How long the user has to confirm an action once asked
+pub const CONFIRMATION_TTL: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Succeeded,
+    Failed,
+    Refused,               // Blocked by the control policy
+    ConfirmationRequired,  // Sent back to the user for confirmation
+}
+
+impl AuditOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditOutcome::Succeeded => "succeeded",
+            AuditOutcome::Failed => "failed",
+            AuditOutcome::Refused => "refused",
+            AuditOutcome::ConfirmationRequired => "confirmation_required",
+        }
+    }
+}
+
+/// SHA-256 over an entry's fields and the hash of the entry before it.
+/// The fields are encoded as a JSON array so no two entries share an
+/// encoding.
+pub fn entry_hash(entry: &AuditEntry) -> String {
+    let fields = serde_json::json!([
+        entry.prev_hash,
+        entry.timestamp,
+        entry.actor,
+        entry.action,
+        entry.target,
+        entry.reason,
+        entry.outcome,
+        entry.details,
+    ]);
+    hex::encode(Sha256::digest(fields.to_string().as_bytes()))
+}
+
+/// Actor recorded for actions taken from the UI
+pub fn local_actor() -> String {
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    format!("user:{}", user)
+}
+
+/// Append an entry to the audit log. Failing to audit does not undo the
+/// action, so errors are only logged.
+pub async fn record(database: &Database, record: AuditRecord) {
+    if let Err(e) = database.append_audit_entry(&record).await {
+        log::error!("Failed to audit {} of {} by {}: {}", record.action, record.target, record.actor, e);
+    }
+}
+
+struct PendingConfirmation {
+    action: String,
+    target: String,
+    issued: Instant,
+}
+
+/// One-time tokens the UI passes back to confirm an action on a sensitive
+/// target. A token is only good for the action and target it was issued
+/// for.
+#[derive(Default)]
+pub struct ConfirmationTokens {
+    pending: HashMap<String, PendingConfirmation>,
+}
+
+impl ConfirmationTokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn issue(&mut self, action: &str, target: &str) -> String {
+        self.pending.retain(|_, p| p.issued.elapsed() < CONFIRMATION_TTL);
+
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = hex::encode(bytes);
+
+        self.pending.insert(token.clone(), PendingConfirmation {
+            action: action.to_string(),
+            target: target.to_string(),
+            issued: Instant::now(),
+        });
+        token
+    }
+
+    /// Use up a token, returning whether it confirms `action` on `target`.
+    /// A token presented for anything else is discarded all the same.
+    pub fn redeem(&mut self, token: &str, action: &str, target: &str) -> bool {
+        match self.pending.remove(token) {
+            Some(p) => p.issued.elapsed() < CONFIRMATION_TTL && p.action == action && p.target == target,
+            None => false,
+        }
+    }
+}
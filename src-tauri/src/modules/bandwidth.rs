@@ -1,17 +1,105 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::Mutex;
+use crate::utils::safety::normalize_mac;
 
+/// Which way a forwarded frame is going, seen from the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+/// Rates for each direction; `None` leaves that direction unlimited.
+/// `burst_kb` is how much may be sent at full speed after an idle period.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BandwidthLimit {
+    pub upload_mbps: Option<f64>,
+    pub download_mbps: Option<f64>,
+    pub burst_kb: Option<u32>,
+}
+
+impl BandwidthLimit {
+    /// The same rate both ways with the default burst
+    pub fn symmetric(limit_mbps: f64) -> Self {
+        Self {
+            upload_mbps: Some(limit_mbps),
+            download_mbps: Some(limit_mbps),
+            burst_kb: None,
+        }
+    }
+
+    /// The tighter of the two rates, for places that show a single figure
+    pub fn min_mbps(&self) -> Option<f64> {
+        match (self.upload_mbps, self.download_mbps) {
+            (Some(up), Some(down)) => Some(up.min(down)),
+            (up, down) => up.or(down),
+        }
+    }
+}
+
+/// Token bucket for one direction. Tokens may go negative: a frame that
+/// arrives before enough tokens have built up is queued until the debt is
+/// paid off rather than dropped.
 #[derive(Debug, Clone)]
-pub struct PacketQueue {
-    pub max_rate: usize,
-    pub current_tokens: usize,
+pub struct TokenBucket {
+    pub rate: f64,      // Bytes per second
+    pub capacity: f64,  // Burst size in bytes
+    pub tokens: f64,
     pub last_refill: Instant,
 }
 
+impl TokenBucket {
+    fn new(limit_mbps: f64, burst_kb: Option<u32>, now: Instant) -> Self {
+        let rate = limit_mbps * 1_000_000.0 / 8.0;
+        let capacity = match burst_kb {
+            Some(kb) => kb as f64 * 1024.0,
+            None => (rate * DEFAULT_BURST.as_secs_f64()).max(MIN_BURST_BYTES),
+        };
+
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    /// How long a frame of `size` bytes has to wait, or `None` if it would
+    /// wait longer than the queue allows
+    fn reserve(&mut self, size: usize, now: Instant) -> Option<Duration> {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+
+        let size = size as f64;
+        let delay = if self.tokens >= size {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((size - self.tokens) / self.rate)
+        };
+
+        if delay > MAX_QUEUE_DELAY {
+            return None;
+        }
+
+        self.tokens -= size;
+        Some(delay)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PacketQueue {
+    pub limit: BandwidthLimit,
+    pub upload: Option<TokenBucket>,
+    pub download: Option<TokenBucket>,
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct DeviceBandwidth {
@@ -23,107 +111,196 @@ pub struct DeviceBandwidth {
     pub current_bandwidth_mbps: f64,
 }
 
+/// Source of the current time, so pacing can be tested without waiting
+type Clock = fn() -> Instant;
+
 pub struct BandwidthController {
-    packet_queues: Arc<Mutex<HashMap<IpAddr, PacketQueue>>>,
-    statistics: Arc<Mutex<HashMap<IpAddr, BandwidthStats>>>,
+    packet_queues: Arc<Mutex<HashMap<String, PacketQueue>>>,  // Keyed by MAC, so limits follow the device
+    statistics: Arc<Mutex<HashMap<String, BandwidthStats>>>,  // Keyed by MAC
     device_bandwidth: Arc<Mutex<HashMap<String, DeviceBandwidth>>>,
     #[allow(dead_code)]
     measurement_interval: Duration,
+    clock: Clock,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct BandwidthStats {
-    pub bytes_sent: u64,
-    pub bytes_received: u64,
+    pub bytes_sent: u64,      // Uploaded by the device
+    pub bytes_received: u64,  // Downloaded by the device
+    pub packets_forwarded: u64,
+    pub packets_delayed: u64,
     pub packets_dropped: u64,
+    pub packets_queued: u64,  // Waiting to be sent right now
+    pub bytes_queued: u64,
+    #[serde(skip)]
     pub last_update: Instant,
+    pub rate_mbps: f64,  // Forwarded rate over the last full window
+    #[serde(skip)]
+    window_start: Instant,
+    #[serde(skip)]
+    window_bytes: u64,
 }
 
+impl BandwidthStats {
+    fn new(now: Instant) -> Self {
+        Self {
+            bytes_sent: 0,
+            bytes_received: 0,
+            packets_forwarded: 0,
+            packets_delayed: 0,
+            packets_dropped: 0,
+            packets_queued: 0,
+            bytes_queued: 0,
+            last_update: now,
+            rate_mbps: 0.0,
+            window_start: now,
+            window_bytes: 0,
+        }
+    }
+}
+
+/// Window the forwarded rate is measured over
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Burst allowed when none is configured, as time at the full rate
+const DEFAULT_BURST: Duration = Duration::from_millis(250);
+/// Smallest burst, so a full-size frame always fits
+const MIN_BURST_BYTES: f64 = 3028.0;
+/// Frames that would have to wait longer than this are dropped
+const MAX_QUEUE_DELAY: Duration = Duration::from_millis(500);
+
 impl BandwidthController {
     pub fn new() -> Self {
+        Self::with_clock(Instant::now)
+    }
+
+    fn with_clock(clock: Clock) -> Self {
         Self {
             packet_queues: Arc::new(Mutex::new(HashMap::new())),
             statistics: Arc::new(Mutex::new(HashMap::new())),
             device_bandwidth: Arc::new(Mutex::new(HashMap::new())),
             measurement_interval: Duration::from_secs(20), // 20-second measurement window
+            clock,
         }
     }
 
-    pub async fn limit_bandwidth(&self, ip: IpAddr, limit_mbps: f64) -> Result<()> {
-        let bytes_per_second = (limit_mbps * 1_000_000.0 / 8.0) as usize;
+    pub async fn limit_bandwidth(&self, mac: &str, limit: BandwidthLimit) -> Result<()> {
+        let mac = normalize_mac(mac).ok_or_else(|| anyhow::anyhow!("Invalid MAC address {}", mac))?;
+        let now = (self.clock)();
         let mut queues = self.packet_queues.lock().await;
 
         queues.insert(
-            ip,
+            mac,
             PacketQueue {
-                max_rate: bytes_per_second,
-                current_tokens: bytes_per_second,
-                last_refill: Instant::now(),
+                limit,
+                upload: limit.upload_mbps.map(|mbps| TokenBucket::new(mbps, limit.burst_kb, now)),
+                download: limit.download_mbps.map(|mbps| TokenBucket::new(mbps, limit.burst_kb, now)),
             },
         );
 
         Ok(())
     }
 
-    pub async fn remove_limit(&self, ip: IpAddr) -> Result<()> {
-        let mut queues = self.packet_queues.lock().await;
-        queues.remove(&ip);
+    pub async fn remove_limit(&self, mac: &str) -> Result<()> {
+        if let Some(mac) = normalize_mac(mac) {
+            self.packet_queues.lock().await.remove(&mac);
+        }
         Ok(())
     }
 
-    pub async fn should_forward_packet(&self, from_ip: IpAddr, packet_size: usize) -> bool {
-        let mut queues = self.packet_queues.lock().await;
+    pub async fn get_limit(&self, mac: &str) -> Option<BandwidthLimit> {
+        let mac = normalize_mac(mac)?;
+        let queues = self.packet_queues.lock().await;
+        queues.get(&mac).map(|q| q.limit)
+    }
 
-        if let Some(queue) = queues.get_mut(&from_ip) {
-            // Token bucket algorithm
-            let now = Instant::now();
-            let elapsed = now.duration_since(queue.last_refill);
-            let tokens_to_add = (elapsed.as_secs_f64() * queue.max_rate as f64) as usize;
+    /// Every limit, keyed by normalised MAC
+    pub async fn get_limits(&self) -> HashMap<String, BandwidthLimit> {
+        let queues = self.packet_queues.lock().await;
+        queues.iter().map(|(mac, q)| (mac.clone(), q.limit)).collect()
+    }
 
-            queue.current_tokens = (queue.current_tokens + tokens_to_add).min(queue.max_rate);
-            queue.last_refill = now;
+    /// Work out when a frame may be forwarded. Returns the delay to queue it
+    /// for, or `None` if the device's queue is full and it should be dropped.
+    /// Every frame that is not dropped must be reported with `packet_sent`.
+    /// `mac` is normalised, as the forwarder's routes carry it.
+    pub async fn pace_packet(&self, mac: &str, direction: Direction, packet_size: usize) -> Option<Duration> {
+        let now = (self.clock)();
+        let mut queues = self.packet_queues.lock().await;
 
-            if queue.current_tokens >= packet_size {
-                queue.current_tokens -= packet_size;
+        let bucket = queues.get_mut(mac).and_then(|queue| match direction {
+            Direction::Upload => queue.upload.as_mut(),
+            Direction::Download => queue.download.as_mut(),
+        });
+        let delay = match bucket {
+            Some(bucket) => bucket.reserve(packet_size, now),
+            None => Some(Duration::ZERO),  // No limit this way
+        };
+        drop(queues);
 
-                // Update statistics
-                self.update_stats(from_ip, packet_size, false).await;
-                return true;
+        let mut stats = self.statistics.lock().await;
+        let entry = stats.entry(mac.to_string()).or_insert_with(|| BandwidthStats::new(now));
+        match delay {
+            Some(delay) => {
+                entry.packets_queued += 1;
+                entry.bytes_queued += packet_size as u64;
+                if !delay.is_zero() {
+                    entry.packets_delayed += 1;
+                }
             }
-
-            // Packet dropped
-            self.update_stats(from_ip, 0, true).await;
-            return false;
+            None => entry.packets_dropped += 1,
         }
+        entry.last_update = now;
 
-        // No limit set, forward packet
-        self.update_stats(from_ip, packet_size, false).await;
-        true
+        delay
     }
 
-    async fn update_stats(&self, ip: IpAddr, bytes: usize, dropped: bool) {
+    /// Record that a frame queued by `pace_packet` has gone out
+    pub async fn packet_sent(&self, mac: &str, direction: Direction, packet_size: usize) {
         let mut stats = self.statistics.lock().await;
-        let entry = stats.entry(ip).or_insert_with(|| BandwidthStats {
-            bytes_sent: 0,
-            bytes_received: 0,
-            packets_dropped: 0,
-            last_update: Instant::now(),
-        });
+        let now = (self.clock)();
+        let entry = stats.entry(mac.to_string()).or_insert_with(|| BandwidthStats::new(now));
 
-        if dropped {
-            entry.packets_dropped += 1;
-        } else {
-            entry.bytes_sent += bytes as u64;
+        let bytes = packet_size as u64;
+        entry.packets_queued = entry.packets_queued.saturating_sub(1);
+        entry.bytes_queued = entry.bytes_queued.saturating_sub(bytes);
+        entry.packets_forwarded += 1;
+        match direction {
+            Direction::Upload => entry.bytes_sent += bytes,
+            Direction::Download => entry.bytes_received += bytes,
+        }
+        entry.window_bytes += bytes;
+        entry.last_update = now;
+
+        let elapsed = now.duration_since(entry.window_start);
+        if elapsed >= RATE_WINDOW {
+            entry.rate_mbps = (entry.window_bytes as f64 * 8.0) / (elapsed.as_secs_f64() * 1_000_000.0);
+            entry.window_start = now;
+            entry.window_bytes = 0;
         }
-        entry.last_update = Instant::now();
     }
 
-    pub async fn get_statistics(&self, ip: IpAddr) -> Option<BandwidthStats> {
+    /// Rate actually forwarded for a device, or `None` if nothing has gone
+    /// through the forwarder for it
+    pub async fn get_forwarded_rate(&self, mac: &str) -> Option<f64> {
+        let mac = normalize_mac(mac)?;
         let stats = self.statistics.lock().await;
-        stats.get(&ip).cloned()
+        let entry = stats.get(&mac)?;
+        // Nothing seen for a while means the device is idle
+        if (self.clock)().duration_since(entry.last_update) > RATE_WINDOW * 2 {
+            return Some(0.0);
+        }
+        Some(entry.rate_mbps)
     }
 
-    pub async fn get_all_statistics(&self) -> HashMap<IpAddr, BandwidthStats> {
+    pub async fn get_statistics(&self, mac: &str) -> Option<BandwidthStats> {
+        let mac = normalize_mac(mac)?;
+        let stats = self.statistics.lock().await;
+        stats.get(&mac).cloned()
+    }
+
+    /// Statistics of every forwarded device, keyed by normalised MAC
+    pub async fn get_all_statistics(&self) -> HashMap<String, BandwidthStats> {
         let stats = self.statistics.lock().await;
         stats.clone()
     }
@@ -191,4 +368,120 @@ impl BandwidthController {
         log::info!("Bandwidth monitoring started (implementation pending)");
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::sync::OnceLock;
+
+    const MAC: &str = "02:00:00:00:00:10";
+
+    static START: OnceLock<Instant> = OnceLock::new();
+
+    thread_local! {
+        static ELAPSED: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+    }
+
+    /// A clock that only moves when `advance` is called. Each test runs on
+    /// its own thread with a single-threaded runtime, so each has its own.
+    fn clock() -> Instant {
+        *START.get_or_init(Instant::now) + ELAPSED.with(Cell::get)
+    }
+
+    fn advance(by: Duration) {
+        ELAPSED.with(|elapsed| elapsed.set(elapsed.get() + by));
+    }
+
+    async fn limited(limit: BandwidthLimit) -> BandwidthController {
+        let controller = BandwidthController::with_clock(clock);
+        controller.limit_bandwidth(MAC, limit).await.unwrap();
+        controller
+    }
+
+    fn millis(ms: u64) -> Option<Duration> {
+        Some(Duration::from_millis(ms))
+    }
+
+    #[tokio::test]
+    async fn upload_and_download_have_separate_buckets() {
+        // 1 Mbps is 125000 bytes a second, with a 31250 byte default burst
+        let controller = limited(BandwidthLimit::symmetric(1.0)).await;
+
+        assert_eq!(controller.pace_packet(MAC, Direction::Upload, 31250).await, Some(Duration::ZERO));
+        assert_eq!(controller.pace_packet(MAC, Direction::Upload, 1250).await, millis(10));
+        // Draining the upload bucket leaves the download burst untouched
+        assert_eq!(controller.pace_packet(MAC, Direction::Download, 31250).await, Some(Duration::ZERO));
+        assert_eq!(controller.pace_packet(MAC, Direction::Download, 1250).await, millis(10));
+    }
+
+    #[tokio::test]
+    async fn direction_without_a_rate_is_not_paced() {
+        let controller = limited(BandwidthLimit {
+            upload_mbps: Some(1.0),
+            download_mbps: None,
+            burst_kb: None,
+        }).await;
+
+        for _ in 0..100 {
+            assert_eq!(controller.pace_packet(MAC, Direction::Download, 1500).await, Some(Duration::ZERO));
+        }
+        assert!(controller.pace_packet(MAC, Direction::Upload, 40000).await.unwrap() > Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn idle_time_refills_no_more_than_the_burst() {
+        let controller = limited(BandwidthLimit {
+            upload_mbps: Some(1.0),
+            download_mbps: Some(1.0),
+            burst_kb: Some(10),
+        }).await;
+
+        assert_eq!(controller.pace_packet(MAC, Direction::Upload, 10240).await, Some(Duration::ZERO));
+        advance(Duration::from_secs(10));
+
+        // Ten idle seconds only buy back the 10 KB burst
+        assert_eq!(controller.pace_packet(MAC, Direction::Upload, 10240).await, Some(Duration::ZERO));
+        assert_eq!(controller.pace_packet(MAC, Direction::Upload, 1250).await, millis(10));
+
+        // Debt is paid off at the configured rate
+        advance(Duration::from_millis(10));
+        assert_eq!(controller.pace_packet(MAC, Direction::Upload, 1250).await, millis(10));
+    }
+
+    #[tokio::test]
+    async fn burst_always_fits_two_full_frames() {
+        // 0.01 Mbps would give a 312 byte burst, below one full-size frame
+        let controller = limited(BandwidthLimit::symmetric(0.01)).await;
+
+        assert_eq!(controller.pace_packet(MAC, Direction::Upload, 1514).await, Some(Duration::ZERO));
+        assert_eq!(controller.pace_packet(MAC, Direction::Upload, 1514).await, Some(Duration::ZERO));
+        assert_ne!(controller.pace_packet(MAC, Direction::Upload, 1).await, Some(Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn frames_that_would_wait_too_long_are_dropped() {
+        let controller = limited(BandwidthLimit {
+            upload_mbps: Some(1.0),
+            download_mbps: Some(1.0),
+            burst_kb: Some(10),
+        }).await;
+        controller.pace_packet(MAC, Direction::Upload, 10240).await;
+
+        // Each 1250 byte frame waits 10ms longer than the one before
+        for frame in 1..=50 {
+            assert_eq!(controller.pace_packet(MAC, Direction::Upload, 1250).await, millis(frame * 10));
+        }
+        assert_eq!(controller.pace_packet(MAC, Direction::Upload, 1250).await, None);
+
+        // A dropped frame takes no tokens, so the next one fits once the
+        // queue has drained a little
+        advance(Duration::from_millis(10));
+        assert_eq!(controller.pace_packet(MAC, Direction::Upload, 1250).await, millis(500));
+
+        let stats = controller.get_statistics(MAC).await.unwrap();
+        assert_eq!(stats.packets_dropped, 1);
+        assert_eq!(stats.packets_delayed, 51);
+        assert_eq!(stats.packets_queued, 52);
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::interval;
+use crate::utils::safety::device_id_for_mac;
+use crate::modules::database::{BandwidthSample, Database, HistoryResolution, RAW_SAMPLE_INTERVAL_SECS};
+use crate::modules::packet_monitor::PacketMonitor;
+use crate::modules::scanner::NetworkScanner;
+
+/// Number of sample ticks between rollup passes (one minute)
+const ROLLUP_EVERY_TICKS: u64 = 6;
+/// Number of sample ticks between retention passes (one hour)
+const PRUNE_EVERY_TICKS: u64 = 360;
+
+/// Samples per-device byte counters from the packet monitor into the
+/// database and keeps the rollup tables up to date.
+pub struct BandwidthHistory {
+    running: Arc<Mutex<bool>>,
+}
+
+impl BandwidthHistory {
+    pub fn new() -> Self {
+        Self {
+            running: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    /// Start the background sampling task
+    pub async fn start_sampling(
+        &self,
+        packet_monitor: Arc<Mutex<Option<PacketMonitor>>>,
+        scanner: Arc<Mutex<NetworkScanner>>,
+        database: Arc<Mutex<Database>>,
+    ) -> Result<()> {
+        let mut running = self.running.lock().await;
+        if *running {
+            return Ok(());
+        }
+        *running = true;
+        drop(running);
+
+        let running = self.running.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = interval(Duration::from_secs(RAW_SAMPLE_INTERVAL_SECS as u64));
+            let mut last_counters: HashMap<Ipv4Addr, (u64, u64)> = HashMap::new();
+            let mut device_ids: HashMap<Ipv4Addr, String> = HashMap::new();
+            let mut tick: u64 = 0;
+
+            loop {
+                ticker.tick().await;
+                tick += 1;
+
+                // Check if we should stop
+                {
+                    let running = running.lock().await;
+                    if !*running {
+                        break;
+                    }
+                }
+
+                // Refresh the IP to device mapping unless a scan currently holds the scanner
+                if let Ok(scanner) = scanner.try_lock() {
+                    device_ids = scanner.get_discovered_devices().await
+                        .into_iter()
+                        .map(|d| (d.ip, device_id_for_mac(&d.mac)))
+                        .collect();
+                }
+
+                let traffic = {
+                    let packet_monitor = packet_monitor.lock().await;
+                    match packet_monitor.as_ref() {
+                        Some(monitor) if monitor.is_running().await => monitor.get_traffic_stats().await,
+                        _ => HashMap::new(),
+                    }
+                };
+
+                let timestamp = chrono::Utc::now().timestamp();
+                let mut samples = Vec::new();
+
+                for (ip, stats) in traffic {
+                    let (prev_sent, prev_received) = last_counters
+                        .insert(ip, (stats.bytes_sent, stats.bytes_received))
+                        .unwrap_or((0, 0));
+
+                    // Counters restart from zero when the monitor clears its stats
+                    let sent = stats.bytes_sent.checked_sub(prev_sent).unwrap_or(stats.bytes_sent);
+                    let received = stats.bytes_received.checked_sub(prev_received).unwrap_or(stats.bytes_received);
+
+                    if sent == 0 && received == 0 {
+                        continue;
+                    }
+
+                    if let Some(device_id) = device_ids.get(&ip) {
+                        samples.push(BandwidthSample {
+                            device_id: device_id.clone(),
+                            timestamp,
+                            bytes_sent: sent as i64,
+                            bytes_received: received as i64,
+                        });
+                    }
+                }
+
+                let database = database.lock().await;
+
+                if let Err(e) = database.insert_bandwidth_samples(&samples).await {
+                    log::warn!("Failed to store bandwidth samples: {}", e);
+                }
+
+                if tick.is_multiple_of(ROLLUP_EVERY_TICKS) {
+                    for resolution in [HistoryResolution::Minute, HistoryResolution::Hour, HistoryResolution::Day] {
+                        if let Err(e) = database.rollup_bandwidth(resolution).await {
+                            log::warn!("Failed to roll up {} bandwidth history: {}", resolution.as_str(), e);
+                        }
+                    }
+                }
+
+                if tick.is_multiple_of(PRUNE_EVERY_TICKS) {
+                    match database.get_history_retention().await {
+                        Ok(retention) => {
+                            if let Err(e) = database.prune_bandwidth_history(&retention).await {
+                                log::warn!("Failed to prune bandwidth history: {}", e);
+                            }
+                        }
+                        Err(e) => log::warn!("Failed to load history retention: {}", e),
+                    }
+                }
+            }
+
+            log::info!("Bandwidth history sampling stopped");
+        });
+
+        log::info!("Bandwidth history sampling started");
+        Ok(())
+    }
+
+    /// Stop the background sampling task
+    pub async fn stop_sampling(&self) -> Result<()> {
+        let mut running = self.running.lock().await;
+        *running = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,416 @@
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::path::{Path, PathBuf};
+use crate::modules::database::Database;
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+type HmacSha256 = Hmac<Sha256>;
+
+/// Prefix marking a stored value as ciphertext
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const KEY_FILE_NAME: &str = "netsnip.key";
+const KEY_FILE_VERSION: u32 = 1;
+const PBKDF2_ITERATIONS: u32 = 600_000;
+const IV_LEN: usize = 16;
+const TAG_LEN: usize = 32;
+
+/// Environment variable holding the passphrase for passphrase-protected databases
+pub const PASSPHRASE_ENV: &str = "NETSNIP_PASSPHRASE";
+
+/// AES-256-CBC with an HMAC-SHA256 tag over the IV and ciphertext
+#[derive(Clone)]
+pub struct Cipher {
+    enc_key: [u8; 32],
+    mac_key: [u8; 32],
+}
+
+impl Cipher {
+    pub fn from_master_key(master: &[u8; 64]) -> Self {
+        let mut enc_key = [0u8; 32];
+        let mut mac_key = [0u8; 32];
+        enc_key.copy_from_slice(&master[..32]);
+        mac_key.copy_from_slice(&master[32..]);
+        Self { enc_key, mac_key }
+    }
+
+    pub fn from_passphrase(passphrase: &str, salt: &[u8], iterations: u32) -> Self {
+        let mut master = [0u8; 64];
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut master);
+        Self::from_master_key(&master)
+    }
+
+    pub fn is_encrypted(value: &str) -> bool {
+        value.starts_with(ENCRYPTED_PREFIX)
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        let mut iv = [0u8; IV_LEN];
+        rand::thread_rng().fill_bytes(&mut iv);
+
+        let data = plaintext.as_bytes();
+        let mut buffer = vec![0u8; data.len() + IV_LEN];
+        buffer[..data.len()].copy_from_slice(data);
+        let ciphertext_len = Aes256CbcEnc::new(&self.enc_key.into(), &iv.into())
+            .encrypt_padded_mut::<Pkcs7>(&mut buffer, data.len())
+            .expect("buffer has room for a full padding block")
+            .len();
+        buffer.truncate(ciphertext_len);
+
+        let mut payload = Vec::with_capacity(IV_LEN + ciphertext_len + TAG_LEN);
+        payload.extend_from_slice(&iv);
+        payload.extend_from_slice(&buffer);
+        let tag = self.tag(&payload);
+        payload.extend_from_slice(&tag);
+
+        format!("{}{}", ENCRYPTED_PREFIX, BASE64.encode(payload))
+    }
+
+    pub fn decrypt(&self, value: &str) -> Result<String> {
+        let encoded = value
+            .strip_prefix(ENCRYPTED_PREFIX)
+            .ok_or_else(|| anyhow::anyhow!("Value is not encrypted"))?;
+        let payload = BASE64.decode(encoded)?;
+        if payload.len() < IV_LEN + TAG_LEN {
+            return Err(anyhow::anyhow!("Encrypted value is truncated"));
+        }
+
+        let (body, tag) = payload.split_at(payload.len() - TAG_LEN);
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.mac_key)
+            .expect("HMAC accepts any key length");
+        mac.update(body);
+        mac.verify_slice(tag)
+            .map_err(|_| anyhow::anyhow!("Encrypted value failed authentication (wrong key?)"))?;
+
+        let (iv, ciphertext) = body.split_at(IV_LEN);
+        let mut buffer = ciphertext.to_vec();
+        let iv: [u8; IV_LEN] = iv.try_into()?;
+        let plaintext = Aes256CbcDec::new(&self.enc_key.into(), &iv.into())
+            .decrypt_padded_mut::<Pkcs7>(&mut buffer)
+            .map_err(|_| anyhow::anyhow!("Encrypted value has invalid padding"))?;
+
+        Ok(String::from_utf8(plaintext.to_vec())?)
+    }
+
+    fn tag(&self, data: &[u8]) -> Vec<u8> {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.mac_key)
+            .expect("HMAC accepts any key length");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+/// On-disk description of where the database key comes from
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+enum KeyFile {
+    File { version: u32, key: String },
+    Passphrase { version: u32, salt: String, iterations: u32 },
+}
+
+impl KeyFile {
+    /// Build the cipher this file describes. Passphrase-protected keys use
+    /// `passphrase` if given, otherwise the passphrase environment variable.
+    fn cipher(&self, passphrase: Option<&str>) -> Result<Cipher> {
+        match self {
+            KeyFile::File { key, .. } => {
+                let bytes = hex::decode(key)?;
+                let master: [u8; 64] = bytes
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("Key file holds a key of the wrong length"))?;
+                Ok(Cipher::from_master_key(&master))
+            }
+            KeyFile::Passphrase { salt, iterations, .. } => {
+                let passphrase = match passphrase {
+                    Some(passphrase) => passphrase.to_string(),
+                    None => std::env::var(PASSPHRASE_ENV).map_err(|_| {
+                        anyhow::anyhow!("The database is passphrase protected; set {} to unlock it", PASSPHRASE_ENV)
+                    })?,
+                };
+                Ok(Cipher::from_passphrase(&passphrase, &hex::decode(salt)?, *iterations))
+            }
+        }
+    }
+}
+
+/// Location of the key file for a database
+pub fn key_path_for(db_path: &Path) -> PathBuf {
+    db_path.with_file_name(KEY_FILE_NAME)
+}
+
+/// Key file written during rotation, promoted once the database is re-encrypted
+fn pending_key_path(key_path: &Path) -> PathBuf {
+    key_path.with_extension("key.new")
+}
+
+/// Create a new key: random and stored in the key file, or derived from
+/// `passphrase` with a random salt
+pub fn generate_key(passphrase: Option<&str>) -> Result<(Cipher, String)> {
+    let key_file = match passphrase {
+        Some(_) => {
+            let mut salt = [0u8; 16];
+            rand::thread_rng().fill_bytes(&mut salt);
+            KeyFile::Passphrase {
+                version: KEY_FILE_VERSION,
+                salt: hex::encode(salt),
+                iterations: PBKDF2_ITERATIONS,
+            }
+        }
+        None => {
+            let mut master = [0u8; 64];
+            rand::thread_rng().fill_bytes(&mut master);
+            KeyFile::File {
+                version: KEY_FILE_VERSION,
+                key: hex::encode(master),
+            }
+        }
+    };
+
+    Ok((key_file.cipher(passphrase)?, serde_json::to_string_pretty(&key_file)?))
+}
+
+fn read_key_file(path: &Path) -> Result<Option<Cipher>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let contents = std::fs::read_to_string(path)?;
+    let key_file: KeyFile = serde_json::from_str(&contents)?;
+    Ok(Some(key_file.cipher(None)?))
+}
+
+/// Write a key file readable only by the current user
+pub fn write_key_file(path: &Path, contents: &str) -> Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Load the key for the database, creating one on first run, and encrypt
+/// any rows that are still stored in plaintext
+pub async fn unlock(database: &mut Database) -> Result<()> {
+    let key_path = key_path_for(database.path());
+    let pending_path = pending_key_path(&key_path);
+
+    let candidates = [
+        (read_key_file(&key_path)?, false),
+        (read_key_file(&pending_path)?, true),
+    ];
+
+    let mut unlocked = false;
+    for (cipher, pending) in candidates {
+        let Some(cipher) = cipher else { continue };
+        if !database.verify_cipher(&cipher).await? {
+            continue;
+        }
+
+        if pending {
+            // A rotation finished in the database but not on disk
+            std::fs::rename(&pending_path, &key_path)?;
+            log::info!("Completed interrupted key rotation");
+        }
+        database.set_cipher(cipher).await?;
+        unlocked = true;
+        break;
+    }
+
+    if !unlocked {
+        if key_path.exists() || database.has_key_check().await? {
+            return Err(anyhow::anyhow!(
+                "The encryption key at {} does not match the database",
+                key_path.display()
+            ));
+        }
+
+        let (cipher, contents) = generate_key(None)?;
+        write_key_file(&key_path, &contents)?;
+        database.set_cipher(cipher).await?;
+        log::info!("Created database encryption key at {}", key_path.display());
+    }
+
+    let migrated = database.encrypt_existing_rows().await?;
+    if migrated > 0 {
+        log::info!("Encrypted {} previously unencrypted values", migrated);
+    }
+
+    Ok(())
+}
+
+/// Re-encrypt the database under a new key and replace the key file
+pub async fn rotate_key(database: &mut Database, passphrase: Option<&str>) -> Result<usize> {
+    let key_path = key_path_for(database.path());
+    let pending_path = pending_key_path(&key_path);
+
+    let (cipher, contents) = generate_key(passphrase)?;
+
+    // Write the new key first so an interruption can always be recovered
+    write_key_file(&pending_path, &contents)?;
+    let rotated = match database.rotate_cipher(cipher).await {
+        Ok(rotated) => rotated,
+        Err(e) => {
+            let _ = std::fs::remove_file(&pending_path);
+            return Err(e);
+        }
+    };
+    std::fs::rename(&pending_path, &key_path)?;
+
+    Ok(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::database::DeviceRecord;
+
+    const MAC: &str = "02:00:00:00:00:10";
+
+    fn cipher(fill: u8) -> Cipher {
+        Cipher::from_master_key(&[fill; 64])
+    }
+
+    /// A directory of its own for each test, since the key file sits next
+    /// to the database
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("netsnip-crypto-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    async fn database_with_device(dir: &TempDir) -> Database {
+        let mut database = Database::new(dir.0.join("netsnip.db")).await.unwrap();
+        unlock(&mut database).await.unwrap();
+        database.upsert_device(&DeviceRecord {
+            id: MAC.replace(':', "_"),
+            mac: MAC.to_string(),
+            ip: "192.168.1.10".to_string(),
+            hostname: None,
+            custom_name: Some("Kitchen speaker".to_string()),
+            manufacturer: None,
+            device_type: "unknown".to_string(),
+            first_seen: 0,
+            last_seen: 0,
+            total_bytes: 0,
+            is_blocked: false,
+            bandwidth_limit: None,
+            notes: None,
+            icon: None,
+        }).await.unwrap();
+        database
+    }
+
+    async fn custom_name(database: &Database) -> Option<String> {
+        database.get_device_by_mac(MAC).await.unwrap().unwrap().custom_name
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let cipher = cipher(1);
+        let sealed = cipher.encrypt("Kitchen speaker");
+
+        assert!(Cipher::is_encrypted(&sealed));
+        assert_eq!(cipher.decrypt(&sealed).unwrap(), "Kitchen speaker");
+        assert_eq!(cipher.decrypt(&cipher.encrypt("")).unwrap(), "");
+        // A fresh IV every time, so equal values are not recognisable
+        assert_ne!(cipher.encrypt("Kitchen speaker"), sealed);
+    }
+
+    #[test]
+    fn tampered_values_are_rejected() {
+        let cipher = cipher(1);
+        let sealed = cipher.encrypt("Kitchen speaker");
+        let payload = BASE64.decode(sealed.strip_prefix(ENCRYPTED_PREFIX).unwrap()).unwrap();
+
+        // Flip a bit in the IV, the ciphertext and the tag in turn
+        for index in [0, IV_LEN, payload.len() - 1] {
+            let mut tampered = payload.clone();
+            tampered[index] ^= 0x01;
+            let tampered = format!("{}{}", ENCRYPTED_PREFIX, BASE64.encode(tampered));
+            assert!(cipher.decrypt(&tampered).is_err(), "byte {} was not authenticated", index);
+        }
+
+        let truncated = format!("{}{}", ENCRYPTED_PREFIX, BASE64.encode(&payload[..IV_LEN + TAG_LEN - 1]));
+        assert!(cipher.decrypt(&truncated).is_err());
+        assert!(Cipher::from_master_key(&[2; 64]).decrypt(&sealed).is_err());
+        assert!(cipher.decrypt("Kitchen speaker").is_err());
+    }
+
+    #[tokio::test]
+    async fn unlock_completes_an_interrupted_rotation() {
+        let dir = TempDir::new("interrupted-rotation");
+        let mut database = database_with_device(&dir).await;
+        let key_path = key_path_for(database.path());
+        let pending_path = pending_key_path(&key_path);
+
+        // The database is re-encrypted but the new key is never promoted
+        let (new_cipher, contents) = generate_key(None).unwrap();
+        write_key_file(&pending_path, &contents).unwrap();
+        database.rotate_cipher(new_cipher).await.unwrap();
+        drop(database);
+
+        let mut database = Database::new(dir.0.join("netsnip.db")).await.unwrap();
+        unlock(&mut database).await.unwrap();
+
+        assert!(!pending_path.exists());
+        assert_eq!(std::fs::read_to_string(&key_path).unwrap(), contents);
+        assert_eq!(custom_name(&database).await.as_deref(), Some("Kitchen speaker"));
+    }
+
+    #[tokio::test]
+    async fn unlock_keeps_the_current_key_when_a_rotation_never_ran() {
+        let dir = TempDir::new("abandoned-rotation");
+        let database = database_with_device(&dir).await;
+        let key_path = key_path_for(database.path());
+        let current = std::fs::read_to_string(&key_path).unwrap();
+
+        // The new key was written but the database was not re-encrypted
+        let (_, contents) = generate_key(None).unwrap();
+        write_key_file(&pending_key_path(&key_path), &contents).unwrap();
+        drop(database);
+
+        let mut database = Database::new(dir.0.join("netsnip.db")).await.unwrap();
+        unlock(&mut database).await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(&key_path).unwrap(), current);
+        assert_eq!(custom_name(&database).await.as_deref(), Some("Kitchen speaker"));
+    }
+
+    #[tokio::test]
+    async fn unlock_refuses_a_key_that_does_not_match() {
+        let dir = TempDir::new("wrong-key");
+        let database = database_with_device(&dir).await;
+        let key_path = key_path_for(database.path());
+        drop(database);
+
+        let (_, contents) = generate_key(None).unwrap();
+        write_key_file(&key_path, &contents).unwrap();
+
+        let mut database = Database::new(dir.0.join("netsnip.db")).await.unwrap();
+        assert!(unlock(&mut database).await.is_err());
+    }
+}
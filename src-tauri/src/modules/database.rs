@@ -1,7 +1,53 @@
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, TimeZone, Utc, Weekday};
+use libsqlite3_sys as ffi;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, Pool, Sqlite, SqlitePool};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection};
+use sqlx::{Connection, FromRow, Pool, QueryBuilder, Sqlite, SqlitePool};
+use std::ffi::{CStr, CString};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use crate::modules::audit;
+use crate::modules::crypto::Cipher;
+
+/// Default and maximum page sizes for event queries
+const DEFAULT_EVENT_PAGE_SIZE: i64 = 100;
+const MAX_EVENT_PAGE_SIZE: i64 = 1000;
+
+/// Default and maximum page sizes for audit log queries
+const DEFAULT_AUDIT_PAGE_SIZE: i64 = 100;
+const MAX_AUDIT_PAGE_SIZE: i64 = 1000;
+
+/// Interval between raw bandwidth samples, in seconds
+pub const RAW_SAMPLE_INTERVAL_SECS: i64 = 10;
+
+const HISTORY_RETENTION_KEY: &str = "history_retention";
+const CUT_RECOVERY_KEY: &str = "cut_recovery_mode";
+const INFRASTRUCTURE_PROTECTION_KEY: &str = "infrastructure_protection";
+
+/// Tables a file must contain to be accepted as a NetSnip database
+const REQUIRED_TABLES: [&str; 3] = ["devices", "network_events", "settings"];
+
+/// Setting holding a known value encrypted with the current key, used to
+/// detect a mismatched key before any data is decrypted
+const KEY_CHECK_SETTING: &str = "encryption_key_check";
+const KEY_CHECK_PLAINTEXT: &str = "netsnip";
+
+/// Columns stored encrypted when a key is loaded. Audit entries are hashed
+/// over their plaintext, so encrypting or re-keying them keeps the chain.
+const ENCRYPTED_COLUMNS: [(&str, &str); 6] = [
+    ("devices", "custom_name"),
+    ("devices", "notes"),
+    ("network_events", "details"),
+    ("protected_devices", "label"),
+    ("audit_log", "reason"),
+    ("audit_log", "details"),
+];
+
+/// Settings holding credentials, stored encrypted and left out of exports
+pub fn is_sensitive_setting(key: &str) -> bool {
+    key.starts_with("secret.") || key.ends_with("_token")
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct DeviceRecord {
@@ -17,31 +63,759 @@ pub struct DeviceRecord {
     pub total_bytes: i64,
     pub is_blocked: bool,
     pub bandwidth_limit: Option<f64>,
+    pub notes: Option<String>,
+    pub icon: Option<String>,  // Device type chosen by the user
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    DeviceCut,
+    DeviceRestored,
+    LimitSet,
+    LimitRemoved,
+    DeviceRenamed,
+    NewDevice,
+    IpChanged,
+    DeviceOffline,
+    QuotaReached,
+    QuotaReset,
+    GatewayChanged,
+    RestoreUnconfirmed,
+    InfrastructureDetected,
+    ArpSpoofDetected,
+}
+
+impl EventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventType::DeviceCut => "device_cut",
+            EventType::DeviceRestored => "device_restored",
+            EventType::LimitSet => "limit_set",
+            EventType::LimitRemoved => "limit_removed",
+            EventType::DeviceRenamed => "device_renamed",
+            EventType::NewDevice => "new_device",
+            EventType::IpChanged => "ip_changed",
+            EventType::DeviceOffline => "device_offline",
+            EventType::QuotaReached => "quota_reached",
+            EventType::QuotaReset => "quota_reset",
+            EventType::GatewayChanged => "gateway_changed",
+            EventType::RestoreUnconfirmed => "restore_unconfirmed",
+            EventType::InfrastructureDetected => "infrastructure_detected",
+            EventType::ArpSpoofDetected => "arp_spoof_detected",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "device_cut" => Some(EventType::DeviceCut),
+            "device_restored" => Some(EventType::DeviceRestored),
+            "limit_set" => Some(EventType::LimitSet),
+            "limit_removed" => Some(EventType::LimitRemoved),
+            "device_renamed" => Some(EventType::DeviceRenamed),
+            "new_device" => Some(EventType::NewDevice),
+            "ip_changed" => Some(EventType::IpChanged),
+            "device_offline" => Some(EventType::DeviceOffline),
+            "quota_reached" => Some(EventType::QuotaReached),
+            "quota_reset" => Some(EventType::QuotaReset),
+            "gateway_changed" => Some(EventType::GatewayChanged),
+            "restore_unconfirmed" => Some(EventType::RestoreUnconfirmed),
+            "infrastructure_detected" => Some(EventType::InfrastructureDetected),
+            "arp_spoof_detected" => Some(EventType::ArpSpoofDetected),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct NetworkEvent {
     pub id: i64,
-    pub event_type: String,
+    pub event_type: EventType,
     pub device_id: String,
     pub timestamp: DateTime<Utc>,
     pub details: Option<String>,
 }
 
+#[derive(FromRow)]
+struct EventRow {
+    id: i64,
+    event_type: String,
+    device_id: String,
+    timestamp: i64,
+    details: Option<String>,
+}
+
+/// Filters and pagination for querying the event log
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct EventQuery {
+    pub device_id: Option<String>,
+    pub event_types: Vec<EventType>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventPage {
+    pub events: Vec<NetworkEvent>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BandwidthSample {
+    pub device_id: String,
+    pub timestamp: i64,  // Unix timestamp
+    pub bytes_sent: i64,
+    pub bytes_received: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryResolution {
+    Raw,
+    Minute,
+    Hour,
+    Day,
+}
+
+impl HistoryResolution {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryResolution::Raw => "raw",
+            HistoryResolution::Minute => "minute",
+            HistoryResolution::Hour => "hour",
+            HistoryResolution::Day => "day",
+        }
+    }
+
+    /// Width of one bucket at this resolution, in seconds
+    pub fn bucket_secs(&self) -> i64 {
+        match self {
+            HistoryResolution::Raw => RAW_SAMPLE_INTERVAL_SECS,
+            HistoryResolution::Minute => 60,
+            HistoryResolution::Hour => 3600,
+            HistoryResolution::Day => 86400,
+        }
+    }
+
+    /// Pick the finest resolution that keeps a chart of the given span readable
+    pub fn for_span(span_secs: i64) -> Self {
+        if span_secs <= 3600 {
+            HistoryResolution::Raw
+        } else if span_secs <= 2 * 86400 {
+            HistoryResolution::Minute
+        } else if span_secs <= 60 * 86400 {
+            HistoryResolution::Hour
+        } else {
+            HistoryResolution::Day
+        }
+    }
+}
+
+/// How long each resolution of bandwidth history is kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryRetention {
+    pub raw_hours: u32,
+    pub minute_days: u32,
+    pub hour_days: u32,
+    pub day_days: u32,
+}
+
+impl Default for HistoryRetention {
+    fn default() -> Self {
+        HistoryRetention {
+            raw_hours: 24,
+            minute_days: 7,
+            hour_days: 90,
+            day_days: 730,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BandwidthPoint {
+    pub timestamp: DateTime<Utc>,
+    pub bytes_sent: i64,
+    pub bytes_received: i64,
+    pub average_mbps: f64,
+}
+
+#[derive(FromRow)]
+struct BandwidthRow {
+    bucket_start: i64,
+    bytes_sent: i64,
+    bytes_received: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaPeriod {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl QuotaPeriod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaPeriod::Daily => "daily",
+            QuotaPeriod::Weekly => "weekly",
+            QuotaPeriod::Monthly => "monthly",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "daily" => Some(QuotaPeriod::Daily),
+            "weekly" => Some(QuotaPeriod::Weekly),
+            "monthly" => Some(QuotaPeriod::Monthly),
+            _ => None,
+        }
+    }
+
+    /// Start of the quota cycle containing `now`, as a Unix timestamp.
+    /// Cycles begin at midnight in `now`'s time zone; weeks start on Monday.
+    pub fn cycle_start<Tz: TimeZone>(&self, now: DateTime<Tz>) -> i64 {
+        let today = now.date_naive();
+        let start_date = match self {
+            QuotaPeriod::Daily => today,
+            QuotaPeriod::Weekly => today - chrono::Duration::days(i64::from(today.weekday().num_days_from_monday())),
+            QuotaPeriod::Monthly => today.with_day(1).unwrap_or(today),
+        };
+
+        start_date
+            .and_hms_opt(0, 0, 0)
+            .and_then(|midnight| midnight.and_local_timezone(now.timezone()).earliest())
+            .map(|midnight| midnight.timestamp())
+            .unwrap_or_else(|| now.timestamp())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaAction {
+    Notify,
+    Limit,
+    Cut,
+}
+
+impl QuotaAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaAction::Notify => "notify",
+            QuotaAction::Limit => "limit",
+            QuotaAction::Cut => "cut",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "notify" => Some(QuotaAction::Notify),
+            "limit" => Some(QuotaAction::Limit),
+            "cut" => Some(QuotaAction::Cut),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceQuota {
+    pub device_id: String,
+    pub period: QuotaPeriod,
+    pub limit_bytes: i64,
+    pub action: QuotaAction,
+    pub limit_mbps: Option<f64>,  // Bandwidth applied by the limit action
+    pub cycle_start: i64,         // Unix timestamp
+    pub used_bytes: i64,
+    pub enforced: bool,
+    pub enforced_ip: Option<String>,  // Address the action was applied to
+}
+
+#[derive(FromRow)]
+struct QuotaRow {
+    device_id: String,
+    period: String,
+    limit_bytes: i64,
+    action: String,
+    limit_mbps: Option<f64>,
+    cycle_start: i64,
+    used_bytes: i64,
+    enforced: bool,
+    enforced_ip: Option<String>,
+}
+
+/// A named set of devices. A device can be in any number of groups, so
+/// groups also serve as tags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceGroup {
+    pub id: i64,
+    pub name: String,
+    pub color: Option<String>,
+    pub created_at: i64,  // Unix timestamp
+    pub protected: bool,  // Actions on members need confirmation
+    pub device_ids: Vec<String>,
+}
+
+#[derive(FromRow)]
+struct GroupRow {
+    id: i64,
+    name: String,
+    color: Option<String>,
+    created_at: i64,
+    protected: bool,
+}
+
+/// A cut that was active when last written. Entries are removed once the
+/// device has been sent restore packets, so anything left at startup was
+/// interrupted by a crash.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct CutJournalEntry {
+    pub target_ip: String,
+    pub target_mac: String,
+    pub gateway_ip: String,
+    pub gateway_mac: String,
+    pub cut_at: i64,  // Unix timestamp
+    pub expires_at: Option<i64>,  // Unix timestamp the cut is lifted at, if timed
+    pub forward: bool,  // Redirected for a bandwidth limit rather than cut
+    pub mode: String,   // CutMode, as stored
+}
+
+/// How much of the network a cut takes away
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CutMode {
+    /// Only the gateway is cut off; the device can still reach the LAN
+    #[default]
+    InternetOnly,
+    /// The device is also cut off from every other host on the LAN
+    FullIsolation,
+}
+
+impl CutMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CutMode::InternetOnly => "internet_only",
+            CutMode::FullIsolation => "full_isolation",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "internet_only" => Some(CutMode::InternetOnly),
+            "full_isolation" => Some(CutMode::FullIsolation),
+            _ => None,
+        }
+    }
+}
+
+/// What to do at startup with cuts left over from a previous run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CutRecoveryMode {
+    /// Send restore packets so the devices get their connection back
+    #[default]
+    Restore,
+    /// Cut the devices again
+    Reapply,
+}
+
+impl CutRecoveryMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CutRecoveryMode::Restore => "restore",
+            CutRecoveryMode::Reapply => "reapply",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "restore" => Some(CutRecoveryMode::Restore),
+            "reapply" => Some(CutRecoveryMode::Reapply),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleTarget {
+    Device,
+    Group,
+}
+
+impl ScheduleTarget {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScheduleTarget::Device => "device",
+            ScheduleTarget::Group => "group",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "device" => Some(ScheduleTarget::Device),
+            "group" => Some(ScheduleTarget::Group),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleAction {
+    Cut,
+    Limit,
+}
+
+impl ScheduleAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScheduleAction::Cut => "cut",
+            ScheduleAction::Limit => "limit",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "cut" => Some(ScheduleAction::Cut),
+            "limit" => Some(ScheduleAction::Limit),
+            _ => None,
+        }
+    }
+}
+
+/// A weekly time window during which a device or group is cut or limited
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Schedule {
+    pub id: i64,
+    pub name: String,
+    pub target: ScheduleTarget,
+    pub target_id: String,        // Device ID, or group ID for group schedules
+    pub action: ScheduleAction,
+    pub limit_mbps: Option<f64>,
+    pub days: Vec<Weekday>,       // Days on which the window starts
+    pub start_minute: u32,        // Minutes after local midnight
+    pub end_minute: u32,          // At or before start for windows past midnight
+    pub enabled: bool,
+    pub created_at: i64,          // Unix timestamp
+}
+
+#[derive(FromRow)]
+struct ScheduleRow {
+    id: i64,
+    name: String,
+    target: String,
+    target_id: String,
+    action: String,
+    limit_mbps: Option<f64>,
+    days: i64,
+    start_minute: i64,
+    end_minute: i64,
+    enabled: bool,
+    created_at: i64,
+}
+
+/// A schedule action the scheduler has applied to a device, kept so it can
+/// be lifted after a restart
+#[derive(Debug, Clone)]
+pub struct ScheduleApplication {
+    pub device_id: String,
+    pub schedule_id: i64,
+    pub action: ScheduleAction,
+    pub limit_mbps: Option<f64>,
+    pub ip: String,
+    pub redirected_at: Option<i64>,  // Unix timestamp of the redirect the schedule made
+}
+
+#[derive(FromRow)]
+struct ScheduleApplicationRow {
+    device_id: String,
+    schedule_id: i64,
+    action: String,
+    limit_mbps: Option<f64>,
+    ip: String,
+    redirected_at: Option<i64>,
+}
+
+/// How strictly a protected device is guarded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProtectionLevel {
+    /// No control action may be applied
+    #[default]
+    Refuse,
+    /// Actions are allowed once the user confirms them
+    Confirm,
+}
+
+impl ProtectionLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProtectionLevel::Refuse => "refuse",
+            ProtectionLevel::Confirm => "confirm",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "refuse" => Some(ProtectionLevel::Refuse),
+            "confirm" => Some(ProtectionLevel::Confirm),
+            _ => None,
+        }
+    }
+}
+
+/// A MAC address control actions are refused for or need confirmation on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProtectedDevice {
+    pub mac: String,  // Lowercase, colon separated
+    pub label: Option<String>,
+    pub level: ProtectionLevel,
+    pub created_at: i64,
+}
+
+#[derive(FromRow)]
+struct ProtectedDeviceRow {
+    mac: String,
+    label: Option<String>,
+    level: String,
+    created_at: i64,
+}
+
+/// How a device was found to stand in for other hosts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InfrastructureEvidence {
+    SharedArp,  // The scan found its MAC behind several IPs
+    Bridging,   // Captured traffic from several IPs carried its MAC
+}
+
+impl InfrastructureEvidence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InfrastructureEvidence::SharedArp => "shared_arp",
+            InfrastructureEvidence::Bridging => "bridging",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "shared_arp" => Some(InfrastructureEvidence::SharedArp),
+            "bridging" => Some(InfrastructureEvidence::Bridging),
+            _ => None,
+        }
+    }
+}
+
+/// A repeater, mesh node or bridge: one MAC that fronts several hosts, so
+/// cutting it takes all of them offline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InfrastructureDevice {
+    pub mac: String,         // Lowercase, colon separated
+    pub evidence: InfrastructureEvidence,
+    pub hosts: Vec<String>,  // IPs last seen behind the MAC
+    pub first_seen: i64,     // Unix timestamp
+    pub last_seen: i64,      // Unix timestamp
+    pub dismissed: bool,     // The user says it is an ordinary device
+}
+
+#[derive(FromRow)]
+struct InfrastructureDeviceRow {
+    mac: String,
+    evidence: String,
+    hosts: String,  // Comma separated
+    first_seen: i64,
+    last_seen: i64,
+    dismissed: bool,
+}
+
+/// What the control policy does with devices classified as infrastructure
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InfrastructureProtection {
+    Refuse,
+    /// Detection can be fooled by a device changing address, so by default
+    /// the user only has to confirm
+    #[default]
+    Confirm,
+    Off,
+}
+
+impl InfrastructureProtection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InfrastructureProtection::Refuse => "refuse",
+            InfrastructureProtection::Confirm => "confirm",
+            InfrastructureProtection::Off => "off",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "refuse" => Some(InfrastructureProtection::Refuse),
+            "confirm" => Some(InfrastructureProtection::Confirm),
+            "off" => Some(InfrastructureProtection::Off),
+            _ => None,
+        }
+    }
+}
+
+/// A control action as recorded in the audit log. Each entry's hash covers
+/// the previous entry's hash, so rows cannot be edited or removed without
+/// breaking the chain.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub id: i64,
+    pub timestamp: i64,  // Unix timestamp
+    pub actor: String,   // "user:<name>", "schedule:<id>" or "quota"
+    pub action: String,
+    pub target: String,  // Device ID, or "group:<id>"
+    pub reason: Option<String>,
+    pub outcome: String,
+    pub details: Option<String>,  // JSON
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+/// The fields of an audit entry supplied by the caller; the rest are filled
+/// in when it is appended
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub reason: Option<String>,
+    pub outcome: String,
+    pub details: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// Result of checking the audit log's hash chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditVerification {
+    pub valid: bool,
+    pub entries: i64,
+    pub first_broken_id: Option<i64>,  // First entry that does not match its hash or predecessor
+}
+
+/// A group as an inventory import leaves it. `id` is set when the group
+/// already exists; members are only ever added.
+#[derive(Debug, Clone)]
+pub struct GroupImport {
+    pub id: Option<i64>,
+    pub name: String,
+    pub color: Option<String>,
+    pub protected: bool,
+    pub device_ids: Vec<String>,
+}
+
+/// A schedule as an inventory import leaves it. New schedules have an `id`
+/// of 0. Group schedules name their group instead, as a group created by the
+/// same import has no ID yet.
+#[derive(Debug, Clone)]
+pub struct ScheduleImport {
+    pub schedule: Schedule,
+    pub group: Option<String>,
+}
+
+/// Everything an inventory import writes
+#[derive(Debug, Default)]
+pub struct ImportChanges {
+    pub devices: Vec<DeviceRecord>,
+    pub quotas: Vec<DeviceQuota>,
+    pub settings: Vec<(String, String)>,
+    pub protected: Vec<ProtectedDevice>,
+    pub groups: Vec<GroupImport>,
+    pub schedules: Vec<ScheduleImport>,
+}
+
 pub struct Database {
     pool: Pool<Sqlite>,
+    path: PathBuf,
+    cipher: Option<Cipher>,
 }
 
 impl Database {
-    pub async fn new(db_path: &str) -> Result<Self> {
-        let connection_string = format!("sqlite://{}?mode=rwc", db_path);
-        let pool = SqlitePool::connect(&connection_string).await?;
+    pub async fn new(db_path: impl AsRef<Path>) -> Result<Self> {
+        let path = db_path.as_ref().to_path_buf();
+        let options = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await?;
 
-        let db = Self { pool };
+        let db = Self { pool, path, cipher: None };
         db.create_tables().await?;
         Ok(db)
     }
 
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Encrypt a value for storage. Values pass through unchanged until a
+    /// key has been loaded.
+    fn seal(&self, value: Option<&str>) -> Option<String> {
+        let value = value?;
+        match &self.cipher {
+            Some(cipher) => Some(cipher.encrypt(value)),
+            None => Some(value.to_string()),
+        }
+    }
+
+    /// Decrypt a stored value. Plaintext rows written before encryption was
+    /// enabled are returned as-is.
+    fn open(&self, value: Option<String>) -> Option<String> {
+        let value = value?;
+        if !Cipher::is_encrypted(&value) {
+            return Some(value);
+        }
+
+        let cipher = self.cipher.as_ref()?;
+        match cipher.decrypt(&value) {
+            Ok(plaintext) => Some(plaintext),
+            Err(e) => {
+                log::warn!("Failed to decrypt stored value: {}", e);
+                None
+            }
+        }
+    }
+
+    fn open_device(&self, mut device: DeviceRecord) -> DeviceRecord {
+        device.custom_name = self.open(device.custom_name.take());
+        device.notes = self.open(device.notes.take());
+        device
+    }
+
+    fn open_audit_entry(&self, mut entry: AuditEntry) -> AuditEntry {
+        entry.reason = self.open(entry.reason.take());
+        entry.details = self.open(entry.details.take());
+        entry
+    }
+
     async fn create_tables(&self) -> Result<()> {
         sqlx::query(
             r#"
@@ -57,13 +831,19 @@ impl Database {
                 last_seen INTEGER NOT NULL,
                 total_bytes INTEGER DEFAULT 0,
                 is_blocked BOOLEAN DEFAULT FALSE,
-                bandwidth_limit REAL
+                bandwidth_limit REAL,
+                notes TEXT,
+                icon TEXT
             )
             "#,
         )
         .execute(&self.pool)
         .await?;
 
+        // Columns added after the first release
+        self.add_column_if_missing("devices", "notes", "TEXT").await?;
+        self.add_column_if_missing("devices", "icon", "TEXT").await?;
+
         sqlx::query(
             r#"
             CREATE TABLE IF NOT EXISTS network_events (
@@ -80,93 +860,488 @@ impl Database {
         .await?;
 
         sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS settings (
-                key TEXT PRIMARY KEY,
-                value TEXT NOT NULL
-            )
-            "#,
+            "CREATE INDEX IF NOT EXISTS idx_network_events_device_time ON network_events(device_id, timestamp)"
         )
         .execute(&self.pool)
         .await?;
 
-        Ok(())
-    }
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_network_events_time ON network_events(timestamp)"
+        )
+        .execute(&self.pool)
+        .await?;
 
-    pub async fn upsert_device(&self, device: &DeviceRecord) -> Result<()> {
         sqlx::query(
             r#"
-            INSERT INTO devices (
-                id, mac, ip, hostname, custom_name, manufacturer,
-                device_type, first_seen, last_seen, total_bytes,
-                is_blocked, bandwidth_limit
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
-            ON CONFLICT(mac) DO UPDATE SET
-                ip = excluded.ip,
-                hostname = excluded.hostname,
-                custom_name = COALESCE(excluded.custom_name, custom_name),
-                manufacturer = excluded.manufacturer,
-                device_type = excluded.device_type,
-                last_seen = excluded.last_seen,
-                total_bytes = excluded.total_bytes,
-                is_blocked = excluded.is_blocked,
-                bandwidth_limit = excluded.bandwidth_limit
+            CREATE TABLE IF NOT EXISTS bandwidth_samples (
+                device_id TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                bytes_sent INTEGER NOT NULL,
+                bytes_received INTEGER NOT NULL
+            )
             "#,
         )
-        .bind(&device.id)
-        .bind(&device.mac)
-        .bind(&device.ip)
-        .bind(&device.hostname)
-        .bind(&device.custom_name)
-        .bind(&device.manufacturer)
-        .bind(&device.device_type)
-        .bind(device.first_seen)
-        .bind(device.last_seen)
-        .bind(device.total_bytes)
-        .bind(device.is_blocked)
-        .bind(device.bandwidth_limit)
         .execute(&self.pool)
         .await?;
 
-        Ok(())
-    }
-
-    pub async fn get_device_by_mac(&self, mac: &str) -> Result<Option<DeviceRecord>> {
-        let device = sqlx::query_as::<_, DeviceRecord>(
-            "SELECT * FROM devices WHERE mac = ?"
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_bandwidth_samples_device_time ON bandwidth_samples(device_id, timestamp)"
         )
-        .bind(mac)
-        .fetch_optional(&self.pool)
+        .execute(&self.pool)
         .await?;
 
-        Ok(device)
-    }
-
-    pub async fn get_all_devices(&self) -> Result<Vec<DeviceRecord>> {
-        let devices = sqlx::query_as::<_, DeviceRecord>(
-            "SELECT * FROM devices ORDER BY last_seen DESC"
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS bandwidth_rollups (
+                device_id TEXT NOT NULL,
+                resolution TEXT NOT NULL,
+                bucket_start INTEGER NOT NULL,
+                bytes_sent INTEGER NOT NULL,
+                bytes_received INTEGER NOT NULL,
+                PRIMARY KEY (device_id, resolution, bucket_start)
+            )
+            "#,
         )
-        .fetch_all(&self.pool)
+        .execute(&self.pool)
         .await?;
 
-        Ok(devices)
-    }
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS device_quotas (
+                device_id TEXT PRIMARY KEY,
+                period TEXT NOT NULL,
+                limit_bytes INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                limit_mbps REAL,
+                cycle_start INTEGER NOT NULL,
+                used_bytes INTEGER NOT NULL DEFAULT 0,
+                enforced BOOLEAN NOT NULL DEFAULT FALSE,
+                enforced_ip TEXT
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
 
-    pub async fn log_event(&self, event: NetworkEvent) -> Result<()> {
         sqlx::query(
             r#"
-            INSERT INTO network_events (event_type, device_id, timestamp, details)
-            VALUES (?, ?, ?, ?)
+            CREATE TABLE IF NOT EXISTS cut_journal (
+                target_mac TEXT PRIMARY KEY,
+                target_ip TEXT NOT NULL,
+                gateway_ip TEXT NOT NULL,
+                gateway_mac TEXT NOT NULL,
+                cut_at INTEGER NOT NULL,
+                expires_at INTEGER,
+                forward BOOLEAN NOT NULL DEFAULT FALSE
+            )
             "#,
         )
-        .bind(&event.event_type)
-        .bind(&event.device_id)
-        .bind(event.timestamp.timestamp())
-        .bind(&event.details)
         .execute(&self.pool)
         .await?;
 
-        Ok(())
+        self.add_column_if_missing("cut_journal", "expires_at", "INTEGER").await?;
+        self.add_column_if_missing("cut_journal", "forward", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+        self.add_column_if_missing("cut_journal", "mode", "TEXT NOT NULL DEFAULT 'internet_only'").await?;
+        self.rekey_cut_journal().await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS schedules (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                target TEXT NOT NULL,
+                target_id TEXT NOT NULL,
+                action TEXT NOT NULL,
+                limit_mbps REAL,
+                days INTEGER NOT NULL,
+                start_minute INTEGER NOT NULL,
+                end_minute INTEGER NOT NULL,
+                enabled BOOLEAN NOT NULL DEFAULT TRUE,
+                created_at INTEGER NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS schedule_applied (
+                device_id TEXT PRIMARY KEY,
+                schedule_id INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                limit_mbps REAL,
+                ip TEXT NOT NULL,
+                redirected_at INTEGER
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        self.add_column_if_missing("schedule_applied", "redirected_at", "INTEGER").await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS device_groups (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT UNIQUE NOT NULL COLLATE NOCASE,
+                color TEXT,
+                created_at INTEGER NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        self.add_column_if_missing("device_groups", "protected", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS device_group_members (
+                group_id INTEGER NOT NULL,
+                device_id TEXT NOT NULL,
+                PRIMARY KEY (group_id, device_id),
+                FOREIGN KEY (group_id) REFERENCES device_groups(id) ON DELETE CASCADE,
+                FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS protected_devices (
+                mac TEXT PRIMARY KEY,
+                label TEXT,
+                created_at INTEGER NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        self.add_column_if_missing("protected_devices", "level", "TEXT NOT NULL DEFAULT 'refuse'").await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS infrastructure_devices (
+                mac TEXT PRIMARY KEY,
+                evidence TEXT NOT NULL,
+                hosts TEXT NOT NULL,
+                first_seen INTEGER NOT NULL,
+                last_seen INTEGER NOT NULL,
+                dismissed BOOLEAN NOT NULL DEFAULT FALSE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp INTEGER NOT NULL,
+                actor TEXT NOT NULL,
+                action TEXT NOT NULL,
+                target TEXT NOT NULL,
+                reason TEXT,
+                outcome TEXT NOT NULL,
+                details TEXT,
+                prev_hash TEXT NOT NULL,
+                hash TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Journals written before cuts followed devices across addresses were
+    /// keyed by IP; key them by MAC so two cut devices can swap addresses
+    async fn rekey_cut_journal(&self) -> Result<()> {
+        let key: Option<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info('cut_journal') WHERE pk = 1")
+            .fetch_optional(&self.pool)
+            .await?;
+        if key.as_deref() != Some("target_ip") {
+            return Ok(());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("ALTER TABLE cut_journal RENAME TO cut_journal_by_ip")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            CREATE TABLE cut_journal (
+                target_mac TEXT PRIMARY KEY,
+                target_ip TEXT NOT NULL,
+                gateway_ip TEXT NOT NULL,
+                gateway_mac TEXT NOT NULL,
+                cut_at INTEGER NOT NULL,
+                expires_at INTEGER,
+                forward BOOLEAN NOT NULL DEFAULT FALSE,
+                mode TEXT NOT NULL DEFAULT 'internet_only'
+            )
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+        // The latest cut of a device wins if it was journaled under two addresses
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO cut_journal (target_mac, target_ip, gateway_ip, gateway_mac, cut_at, expires_at, forward, mode)
+            SELECT lower(target_mac), target_ip, gateway_ip, gateway_mac, cut_at, expires_at, forward, mode
+            FROM cut_journal_by_ip ORDER BY cut_at
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DROP TABLE cut_journal_by_ip")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn add_column_if_missing(&self, table: &str, column: &str, definition: &str) -> Result<()> {
+        let columns: Vec<String> = sqlx::query_scalar(&format!("SELECT name FROM pragma_table_info('{table}')"))
+            .fetch_all(&self.pool)
+            .await?;
+
+        if !columns.iter().any(|c| c == column) {
+            sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"))
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn upsert_device(&self, device: &DeviceRecord) -> Result<()> {
+        self.write_device(device, &self.pool).await
+    }
+
+    async fn write_device<'e, E>(&self, device: &DeviceRecord, executor: E) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query(
+            r#"
+            INSERT INTO devices (
+                id, mac, ip, hostname, custom_name, manufacturer,
+                device_type, first_seen, last_seen, total_bytes,
+                is_blocked, bandwidth_limit, notes, icon
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(mac) DO UPDATE SET
+                ip = excluded.ip,
+                hostname = excluded.hostname,
+                custom_name = COALESCE(excluded.custom_name, custom_name),
+                manufacturer = excluded.manufacturer,
+                device_type = excluded.device_type,
+                last_seen = excluded.last_seen,
+                total_bytes = excluded.total_bytes,
+                is_blocked = excluded.is_blocked,
+                bandwidth_limit = excluded.bandwidth_limit,
+                notes = COALESCE(excluded.notes, notes),
+                icon = COALESCE(excluded.icon, icon)
+            "#,
+        )
+        .bind(&device.id)
+        .bind(&device.mac)
+        .bind(&device.ip)
+        .bind(&device.hostname)
+        .bind(self.seal(device.custom_name.as_deref()))
+        .bind(&device.manufacturer)
+        .bind(&device.device_type)
+        .bind(device.first_seen)
+        .bind(device.last_seen)
+        .bind(device.total_bytes)
+        .bind(device.is_blocked)
+        .bind(device.bandwidth_limit)
+        .bind(self.seal(device.notes.as_deref()))
+        .bind(&device.icon)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Set or clear the user-chosen name. Returns false if the device is unknown.
+    pub async fn set_device_custom_name(&self, device_id: &str, name: Option<&str>) -> Result<bool> {
+        let result = sqlx::query("UPDATE devices SET custom_name = ? WHERE id = ?")
+            .bind(self.seal(name))
+            .bind(device_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Set or clear the free-text notes. Returns false if the device is unknown.
+    pub async fn set_device_notes(&self, device_id: &str, notes: Option<&str>) -> Result<bool> {
+        let result = sqlx::query("UPDATE devices SET notes = ? WHERE id = ?")
+            .bind(self.seal(notes))
+            .bind(device_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Set or clear the icon override. Returns false if the device is unknown.
+    pub async fn set_device_icon(&self, device_id: &str, icon: Option<&str>) -> Result<bool> {
+        let result = sqlx::query("UPDATE devices SET icon = ? WHERE id = ?")
+            .bind(icon)
+            .bind(device_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Look a device up by MAC, in any case
+    pub async fn get_device_by_mac(&self, mac: &str) -> Result<Option<DeviceRecord>> {
+        let device = sqlx::query_as::<_, DeviceRecord>(
+            "SELECT * FROM devices WHERE mac = ? COLLATE NOCASE"
+        )
+        .bind(mac)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(device.map(|device| self.open_device(device)))
+    }
+
+    pub async fn get_all_devices(&self) -> Result<Vec<DeviceRecord>> {
+        let devices = sqlx::query_as::<_, DeviceRecord>(
+            "SELECT * FROM devices ORDER BY last_seen DESC"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(devices.into_iter().map(|device| self.open_device(device)).collect())
+    }
+
+    pub async fn log_event(&self, event: NetworkEvent) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO network_events (event_type, device_id, timestamp, details)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(event.event_type.as_str())
+        .bind(&event.device_id)
+        .bind(event.timestamp.timestamp())
+        .bind(self.seal(event.details.as_deref()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record an event for a device at the current time
+    pub async fn record_event(&self, event_type: EventType, device_id: &str, details: Option<String>) -> Result<()> {
+        self.log_event(NetworkEvent {
+            id: 0,
+            event_type,
+            device_id: device_id.to_string(),
+            timestamp: Utc::now(),
+            details,
+        })
+        .await
+    }
+
+    /// Query the event log, newest first
+    pub async fn get_events(&self, query: &EventQuery) -> Result<EventPage> {
+        let limit = query.limit.unwrap_or(DEFAULT_EVENT_PAGE_SIZE).clamp(1, MAX_EVENT_PAGE_SIZE);
+        let offset = query.offset.unwrap_or(0).max(0);
+
+        let mut count_query = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) FROM network_events");
+        Self::push_event_filters(&mut count_query, query);
+        let total: i64 = count_query
+            .build_query_scalar()
+            .fetch_one(&self.pool)
+            .await?;
+
+        let mut select_query = QueryBuilder::<Sqlite>::new(
+            "SELECT id, event_type, device_id, timestamp, details FROM network_events"
+        );
+        Self::push_event_filters(&mut select_query, query);
+        select_query
+            .push(" ORDER BY timestamp DESC, id DESC LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+
+        let rows = select_query
+            .build_query_as::<EventRow>()
+            .fetch_all(&self.pool)
+            .await?;
+
+        let events = rows
+            .into_iter()
+            .filter_map(|row| {
+                let event_type = match EventType::parse(&row.event_type) {
+                    Some(event_type) => event_type,
+                    None => {
+                        log::warn!("Skipping event {} with unknown type {}", row.id, row.event_type);
+                        return None;
+                    }
+                };
+
+                Some(NetworkEvent {
+                    id: row.id,
+                    event_type,
+                    device_id: row.device_id,
+                    timestamp: DateTime::from_timestamp(row.timestamp, 0).unwrap_or_default(),
+                    details: self.open(row.details),
+                })
+            })
+            .collect();
+
+        Ok(EventPage { events, total, limit, offset })
+    }
+
+    fn push_event_filters(builder: &mut QueryBuilder<'_, Sqlite>, query: &EventQuery) {
+        let mut keyword = " WHERE ";
+
+        if let Some(device_id) = &query.device_id {
+            builder.push(keyword).push("device_id = ").push_bind(device_id.clone());
+            keyword = " AND ";
+        }
+
+        if !query.event_types.is_empty() {
+            builder.push(keyword).push("event_type IN (");
+            let mut types = builder.separated(", ");
+            for event_type in &query.event_types {
+                types.push_bind(event_type.as_str());
+            }
+            types.push_unseparated(")");
+            keyword = " AND ";
+        }
+
+        if let Some(since) = query.since {
+            builder.push(keyword).push("timestamp >= ").push_bind(since.timestamp());
+            keyword = " AND ";
+        }
+
+        if let Some(until) = query.until {
+            builder.push(keyword).push("timestamp <= ").push_bind(until.timestamp());
+        }
     }
 
     pub async fn get_setting(&self, key: &str) -> Result<Option<String>> {
@@ -177,10 +1352,48 @@ impl Database {
         .fetch_optional(&self.pool)
         .await?;
 
+        if is_sensitive_setting(key) {
+            return Ok(self.open(result));
+        }
         Ok(result)
     }
 
+    pub async fn get_all_settings(&self) -> Result<Vec<(String, String)>> {
+        let settings = sqlx::query_as::<_, (String, String)>(
+            "SELECT key, value FROM settings ORDER BY key"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let settings = settings
+            .into_iter()
+            .filter(|(key, _)| key != KEY_CHECK_SETTING)
+            .filter_map(|(key, value)| {
+                if is_sensitive_setting(&key) {
+                    self.open(Some(value)).map(|value| (key, value))
+                } else {
+                    Some((key, value))
+                }
+            })
+            .collect();
+
+        Ok(settings)
+    }
+
     pub async fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        self.write_setting(key, value, &self.pool).await
+    }
+
+    async fn write_setting<'e, E>(&self, key: &str, value: &str, executor: E) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = Sqlite>,
+    {
+        let value = if is_sensitive_setting(key) {
+            self.seal(Some(value)).unwrap_or_default()
+        } else {
+            value.to_string()
+        };
+
         sqlx::query(
             r#"
             INSERT INTO settings (key, value) VALUES (?, ?)
@@ -188,10 +1401,1473 @@ impl Database {
             "#,
         )
         .bind(key)
-        .bind(value)
-        .execute(&self.pool)
+        .bind(&value)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn insert_bandwidth_samples(&self, samples: &[BandwidthSample]) -> Result<()> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for sample in samples {
+            sqlx::query(
+                r#"
+                INSERT INTO bandwidth_samples (device_id, timestamp, bytes_sent, bytes_received)
+                VALUES (?, ?, ?, ?)
+                "#,
+            )
+            .bind(&sample.device_id)
+            .bind(sample.timestamp)
+            .bind(sample.bytes_sent)
+            .bind(sample.bytes_received)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Recompute rollup buckets at the given resolution from the next finer
+    /// one, starting at the most recent bucket already stored so partially
+    /// filled buckets are refreshed on every pass.
+    pub async fn rollup_bandwidth(&self, resolution: HistoryResolution) -> Result<()> {
+        let bucket = resolution.bucket_secs();
+        let since: i64 = sqlx::query_scalar(
+            "SELECT COALESCE(MAX(bucket_start), 0) FROM bandwidth_rollups WHERE resolution = ?"
+        )
+        .bind(resolution.as_str())
+        .fetch_one(&self.pool)
+        .await?;
+
+        let query = match resolution {
+            HistoryResolution::Raw => return Ok(()),
+            HistoryResolution::Minute => sqlx::query(
+                r#"
+                INSERT INTO bandwidth_rollups (device_id, resolution, bucket_start, bytes_sent, bytes_received)
+                SELECT device_id, ?, (timestamp / ?) * ?, SUM(bytes_sent), SUM(bytes_received)
+                FROM bandwidth_samples
+                WHERE timestamp >= ?
+                GROUP BY device_id, timestamp / ?
+                ON CONFLICT(device_id, resolution, bucket_start) DO UPDATE SET
+                    bytes_sent = excluded.bytes_sent,
+                    bytes_received = excluded.bytes_received
+                "#,
+            ),
+            HistoryResolution::Hour | HistoryResolution::Day => sqlx::query(
+                r#"
+                INSERT INTO bandwidth_rollups (device_id, resolution, bucket_start, bytes_sent, bytes_received)
+                SELECT device_id, ?, (bucket_start / ?) * ?, SUM(bytes_sent), SUM(bytes_received)
+                FROM bandwidth_rollups
+                WHERE bucket_start >= ? AND resolution = ?
+                GROUP BY device_id, bucket_start / ?
+                ON CONFLICT(device_id, resolution, bucket_start) DO UPDATE SET
+                    bytes_sent = excluded.bytes_sent,
+                    bytes_received = excluded.bytes_received
+                "#,
+            ),
+        };
+
+        let query = query
+            .bind(resolution.as_str())
+            .bind(bucket)
+            .bind(bucket)
+            .bind(since);
+
+        let query = match resolution {
+            HistoryResolution::Hour => query.bind(HistoryResolution::Minute.as_str()),
+            HistoryResolution::Day => query.bind(HistoryResolution::Hour.as_str()),
+            _ => query,
+        };
+
+        query.bind(bucket).execute(&self.pool).await?;
+
+        Ok(())
+    }
+
+    /// Delete bandwidth history that has aged out of its retention window
+    pub async fn prune_bandwidth_history(&self, retention: &HistoryRetention) -> Result<()> {
+        let now = Utc::now().timestamp();
+
+        sqlx::query("DELETE FROM bandwidth_samples WHERE timestamp < ?")
+            .bind(now - i64::from(retention.raw_hours) * 3600)
+            .execute(&self.pool)
+            .await?;
+
+        let rollup_retention = [
+            (HistoryResolution::Minute, retention.minute_days),
+            (HistoryResolution::Hour, retention.hour_days),
+            (HistoryResolution::Day, retention.day_days),
+        ];
+
+        for (resolution, days) in rollup_retention {
+            sqlx::query("DELETE FROM bandwidth_rollups WHERE resolution = ? AND bucket_start < ?")
+                .bind(resolution.as_str())
+                .bind(now - i64::from(days) * 86400)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_bandwidth_history(
+        &self,
+        device_id: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        resolution: HistoryResolution,
+    ) -> Result<Vec<BandwidthPoint>> {
+        let bucket = resolution.bucket_secs();
+        // Include the bucket that contains the start of the range
+        let start_ts = (start.timestamp() / bucket) * bucket;
+
+        let rows = match resolution {
+            HistoryResolution::Raw => sqlx::query_as::<_, BandwidthRow>(
+                r#"
+                SELECT timestamp AS bucket_start, bytes_sent, bytes_received
+                FROM bandwidth_samples
+                WHERE device_id = ? AND timestamp >= ? AND timestamp <= ?
+                ORDER BY timestamp
+                "#,
+            )
+            .bind(device_id)
+            .bind(start_ts)
+            .bind(end.timestamp())
+            .fetch_all(&self.pool)
+            .await?,
+            _ => sqlx::query_as::<_, BandwidthRow>(
+                r#"
+                SELECT bucket_start, bytes_sent, bytes_received
+                FROM bandwidth_rollups
+                WHERE device_id = ? AND resolution = ? AND bucket_start >= ? AND bucket_start <= ?
+                ORDER BY bucket_start
+                "#,
+            )
+            .bind(device_id)
+            .bind(resolution.as_str())
+            .bind(start_ts)
+            .bind(end.timestamp())
+            .fetch_all(&self.pool)
+            .await?,
+        };
+
+        let points = rows
+            .into_iter()
+            .map(|row| BandwidthPoint {
+                timestamp: DateTime::from_timestamp(row.bucket_start, 0).unwrap_or_default(),
+                bytes_sent: row.bytes_sent,
+                bytes_received: row.bytes_received,
+                average_mbps: ((row.bytes_sent + row.bytes_received) as f64 * 8.0)
+                    / (bucket as f64 * 1_000_000.0),
+            })
+            .collect();
+
+        Ok(points)
+    }
+
+    pub async fn get_history_retention(&self) -> Result<HistoryRetention> {
+        match self.get_setting(HISTORY_RETENTION_KEY).await? {
+            Some(value) => Ok(serde_json::from_str(&value)?),
+            None => Ok(HistoryRetention::default()),
+        }
+    }
+
+    pub async fn set_history_retention(&self, retention: &HistoryRetention) -> Result<()> {
+        let value = serde_json::to_string(retention)?;
+        self.set_setting(HISTORY_RETENTION_KEY, &value).await
+    }
+
+    pub async fn upsert_quota(&self, quota: &DeviceQuota) -> Result<()> {
+        Self::write_quota(quota, &self.pool).await
+    }
+
+    async fn write_quota<'e, E>(quota: &DeviceQuota, executor: E) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query(
+            r#"
+            INSERT INTO device_quotas (
+                device_id, period, limit_bytes, action, limit_mbps,
+                cycle_start, used_bytes, enforced, enforced_ip
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(device_id) DO UPDATE SET
+                period = excluded.period,
+                limit_bytes = excluded.limit_bytes,
+                action = excluded.action,
+                limit_mbps = excluded.limit_mbps,
+                cycle_start = excluded.cycle_start,
+                used_bytes = excluded.used_bytes,
+                enforced = excluded.enforced,
+                enforced_ip = excluded.enforced_ip
+            "#,
+        )
+        .bind(&quota.device_id)
+        .bind(quota.period.as_str())
+        .bind(quota.limit_bytes)
+        .bind(quota.action.as_str())
+        .bind(quota.limit_mbps)
+        .bind(quota.cycle_start)
+        .bind(quota.used_bytes)
+        .bind(quota.enforced)
+        .bind(&quota.enforced_ip)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    pub async fn get_quota(&self, device_id: &str) -> Result<Option<DeviceQuota>> {
+        let row = sqlx::query_as::<_, QuotaRow>(
+            "SELECT * FROM device_quotas WHERE device_id = ?"
+        )
+        .bind(device_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.and_then(Self::quota_from_row))
+    }
+
+    pub async fn get_quotas(&self) -> Result<Vec<DeviceQuota>> {
+        let rows = sqlx::query_as::<_, QuotaRow>(
+            "SELECT * FROM device_quotas ORDER BY device_id"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().filter_map(Self::quota_from_row).collect())
+    }
+
+    pub async fn delete_quota(&self, device_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM device_quotas WHERE device_id = ?")
+            .bind(device_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    fn quota_from_row(row: QuotaRow) -> Option<DeviceQuota> {
+        let (Some(period), Some(action)) = (QuotaPeriod::parse(&row.period), QuotaAction::parse(&row.action)) else {
+            log::warn!("Skipping quota for {} with unknown period or action", row.device_id);
+            return None;
+        };
+
+        Some(DeviceQuota {
+            device_id: row.device_id,
+            period,
+            limit_bytes: row.limit_bytes,
+            action,
+            limit_mbps: row.limit_mbps,
+            cycle_start: row.cycle_start,
+            used_bytes: row.used_bytes,
+            enforced: row.enforced,
+            enforced_ip: row.enforced_ip,
+        })
+    }
+
+    pub async fn add_cut_journal_entry(&self, entry: &CutJournalEntry) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO cut_journal (target_mac, target_ip, gateway_ip, gateway_mac, cut_at, expires_at, forward, mode)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(target_mac) DO UPDATE SET
+                target_ip = excluded.target_ip,
+                gateway_ip = excluded.gateway_ip,
+                gateway_mac = excluded.gateway_mac,
+                cut_at = excluded.cut_at,
+                expires_at = excluded.expires_at,
+                forward = excluded.forward,
+                mode = excluded.mode
+            "#,
+        )
+        .bind(entry.target_mac.to_lowercase())
+        .bind(&entry.target_ip)
+        .bind(&entry.gateway_ip)
+        .bind(&entry.gateway_mac)
+        .bind(entry.cut_at)
+        .bind(entry.expires_at)
+        .bind(entry.forward)
+        .bind(&entry.mode)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_cut_journal_entry(&self, target_mac: &str) -> Result<()> {
+        sqlx::query("DELETE FROM cut_journal WHERE target_mac = ?")
+            .bind(target_mac.to_lowercase())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_cut_journal(&self) -> Result<Vec<CutJournalEntry>> {
+        let entries = sqlx::query_as::<_, CutJournalEntry>(
+            "SELECT * FROM cut_journal ORDER BY cut_at"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+
+    pub async fn get_cut_recovery_mode(&self) -> Result<CutRecoveryMode> {
+        let mode = self.get_setting(CUT_RECOVERY_KEY).await?
+            .and_then(|value| CutRecoveryMode::parse(&value))
+            .unwrap_or_default();
+        Ok(mode)
+    }
+
+    pub async fn set_cut_recovery_mode(&self, mode: CutRecoveryMode) -> Result<()> {
+        self.set_setting(CUT_RECOVERY_KEY, mode.as_str()).await
+    }
+
+    pub async fn get_infrastructure_protection(&self) -> Result<InfrastructureProtection> {
+        let protection = self.get_setting(INFRASTRUCTURE_PROTECTION_KEY).await?
+            .and_then(|value| InfrastructureProtection::parse(&value))
+            .unwrap_or_default();
+        Ok(protection)
+    }
+
+    pub async fn set_infrastructure_protection(&self, protection: InfrastructureProtection) -> Result<()> {
+        self.set_setting(INFRASTRUCTURE_PROTECTION_KEY, protection.as_str()).await
+    }
+
+    pub async fn get_protected_devices(&self) -> Result<Vec<ProtectedDevice>> {
+        let rows = sqlx::query_as::<_, ProtectedDeviceRow>(
+            "SELECT mac, label, level, created_at FROM protected_devices ORDER BY mac"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| self.protected_from_row(row)).collect())
+    }
+
+    /// Add a MAC to the protected list, or update its label and level
+    pub async fn add_protected_device(
+        &self,
+        mac: &str,
+        label: Option<&str>,
+        level: ProtectionLevel,
+    ) -> Result<ProtectedDevice> {
+        let created_at = chrono::Utc::now().timestamp();
+        self.write_protected_device(mac, label, level, created_at, &self.pool).await?;
+
+        let row = sqlx::query_as::<_, ProtectedDeviceRow>(
+            "SELECT mac, label, level, created_at FROM protected_devices WHERE mac = ?"
+        )
+        .bind(mac)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(self.protected_from_row(row))
+    }
+
+    async fn write_protected_device<'e, E>(
+        &self,
+        mac: &str,
+        label: Option<&str>,
+        level: ProtectionLevel,
+        created_at: i64,
+        executor: E,
+    ) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query(
+            r#"
+            INSERT INTO protected_devices (mac, label, level, created_at) VALUES (?, ?, ?, ?)
+            ON CONFLICT(mac) DO UPDATE SET label = excluded.label, level = excluded.level
+            "#,
+        )
+        .bind(mac)
+        .bind(self.seal(label))
+        .bind(level.as_str())
+        .bind(created_at)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    fn protected_from_row(&self, row: ProtectedDeviceRow) -> ProtectedDevice {
+        // An unreadable level errs on the side of refusing
+        let level = ProtectionLevel::parse(&row.level).unwrap_or_else(|| {
+            log::warn!("Unknown protection level {} for {}, refusing actions", row.level, row.mac);
+            ProtectionLevel::Refuse
+        });
+
+        ProtectedDevice {
+            mac: row.mac,
+            label: self.open(row.label),
+            level,
+            created_at: row.created_at,
+        }
+    }
+
+    pub async fn remove_protected_device(&self, mac: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM protected_devices WHERE mac = ?")
+            .bind(mac)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_infrastructure_devices(&self) -> Result<Vec<InfrastructureDevice>> {
+        let rows = sqlx::query_as::<_, InfrastructureDeviceRow>(
+            "SELECT mac, evidence, hosts, first_seen, last_seen, dismissed FROM infrastructure_devices ORDER BY mac"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().filter_map(|row| {
+            let Some(evidence) = InfrastructureEvidence::parse(&row.evidence) else {
+                log::warn!("Skipping infrastructure device {} with unknown evidence {}", row.mac, row.evidence);
+                return None;
+            };
+            Some(InfrastructureDevice {
+                mac: row.mac,
+                evidence,
+                hosts: row.hosts.split(',').filter(|h| !h.is_empty()).map(str::to_string).collect(),
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+                dismissed: row.dismissed,
+            })
+        }).collect())
+    }
+
+    /// Record that `mac` fronts `hosts`, returning whether it had not been
+    /// classified before. A dismissal by the user survives re-detection.
+    pub async fn record_infrastructure_device(
+        &self,
+        mac: &str,
+        evidence: InfrastructureEvidence,
+        hosts: &[Ipv4Addr],
+    ) -> Result<bool> {
+        let now = chrono::Utc::now().timestamp();
+        let hosts = hosts.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join(",");
+
+        let mut tx = self.pool.begin().await?;
+        let known: Option<i64> = sqlx::query_scalar("SELECT 1 FROM infrastructure_devices WHERE mac = ?")
+            .bind(mac)
+            .fetch_optional(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO infrastructure_devices (mac, evidence, hosts, first_seen, last_seen)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(mac) DO UPDATE SET
+                evidence = excluded.evidence,
+                hosts = excluded.hosts,
+                last_seen = excluded.last_seen
+            "#,
+        )
+        .bind(mac)
+        .bind(evidence.as_str())
+        .bind(&hosts)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(known.is_none())
+    }
+
+    pub async fn dismiss_infrastructure_device(&self, mac: &str, dismissed: bool) -> Result<bool> {
+        let result = sqlx::query("UPDATE infrastructure_devices SET dismissed = ? WHERE mac = ?")
+            .bind(dismissed)
+            .bind(mac)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Store a new schedule and return its ID; `schedule.id` is ignored
+    pub async fn create_schedule(&self, schedule: &Schedule) -> Result<i64> {
+        Self::insert_schedule(schedule, &self.pool).await
+    }
+
+    async fn insert_schedule<'e, E>(schedule: &Schedule, executor: E) -> Result<i64>
+    where
+        E: sqlx::Executor<'e, Database = Sqlite>,
+    {
+        let id = sqlx::query(
+            r#"
+            INSERT INTO schedules (
+                name, target, target_id, action, limit_mbps, days,
+                start_minute, end_minute, enabled, created_at
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&schedule.name)
+        .bind(schedule.target.as_str())
+        .bind(&schedule.target_id)
+        .bind(schedule.action.as_str())
+        .bind(schedule.limit_mbps)
+        .bind(days_to_mask(&schedule.days))
+        .bind(schedule.start_minute as i64)
+        .bind(schedule.end_minute as i64)
+        .bind(schedule.enabled)
+        .bind(schedule.created_at)
+        .execute(executor)
+        .await?
+        .last_insert_rowid();
+
+        Ok(id)
+    }
+
+    pub async fn update_schedule(&self, schedule: &Schedule) -> Result<bool> {
+        Self::write_schedule(schedule, &self.pool).await
+    }
+
+    async fn write_schedule<'e, E>(schedule: &Schedule, executor: E) -> Result<bool>
+    where
+        E: sqlx::Executor<'e, Database = Sqlite>,
+    {
+        let result = sqlx::query(
+            r#"
+            UPDATE schedules SET
+                name = ?, target = ?, target_id = ?, action = ?, limit_mbps = ?,
+                days = ?, start_minute = ?, end_minute = ?, enabled = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(&schedule.name)
+        .bind(schedule.target.as_str())
+        .bind(&schedule.target_id)
+        .bind(schedule.action.as_str())
+        .bind(schedule.limit_mbps)
+        .bind(days_to_mask(&schedule.days))
+        .bind(schedule.start_minute as i64)
+        .bind(schedule.end_minute as i64)
+        .bind(schedule.enabled)
+        .bind(schedule.id)
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_schedule(&self, schedule_id: i64) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM schedules WHERE id = ?")
+            .bind(schedule_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_schedule(&self, schedule_id: i64) -> Result<Option<Schedule>> {
+        let row = sqlx::query_as::<_, ScheduleRow>("SELECT * FROM schedules WHERE id = ?")
+            .bind(schedule_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.and_then(Self::schedule_from_row))
+    }
+
+    pub async fn get_schedules(&self) -> Result<Vec<Schedule>> {
+        let rows = sqlx::query_as::<_, ScheduleRow>("SELECT * FROM schedules ORDER BY id")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().filter_map(Self::schedule_from_row).collect())
+    }
+
+    fn schedule_from_row(row: ScheduleRow) -> Option<Schedule> {
+        let (Some(target), Some(action)) = (ScheduleTarget::parse(&row.target), ScheduleAction::parse(&row.action)) else {
+            log::warn!("Skipping schedule {} with unknown target or action", row.id);
+            return None;
+        };
+
+        Some(Schedule {
+            id: row.id,
+            name: row.name,
+            target,
+            target_id: row.target_id,
+            action,
+            limit_mbps: row.limit_mbps,
+            days: mask_to_days(row.days),
+            start_minute: row.start_minute as u32,
+            end_minute: row.end_minute as u32,
+            enabled: row.enabled,
+            created_at: row.created_at,
+        })
+    }
+
+    pub async fn get_schedule_applications(&self) -> Result<Vec<ScheduleApplication>> {
+        let rows = sqlx::query_as::<_, ScheduleApplicationRow>("SELECT * FROM schedule_applied")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                let action = ScheduleAction::parse(&row.action)?;
+                Some(ScheduleApplication {
+                    device_id: row.device_id,
+                    schedule_id: row.schedule_id,
+                    action,
+                    limit_mbps: row.limit_mbps,
+                    ip: row.ip,
+                    redirected_at: row.redirected_at,
+                })
+            })
+            .collect())
+    }
+
+    pub async fn set_schedule_application(&self, application: &ScheduleApplication) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO schedule_applied (device_id, schedule_id, action, limit_mbps, ip, redirected_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT(device_id) DO UPDATE SET
+                schedule_id = excluded.schedule_id,
+                action = excluded.action,
+                limit_mbps = excluded.limit_mbps,
+                ip = excluded.ip,
+                redirected_at = excluded.redirected_at
+            "#,
+        )
+        .bind(&application.device_id)
+        .bind(application.schedule_id)
+        .bind(application.action.as_str())
+        .bind(application.limit_mbps)
+        .bind(&application.ip)
+        .bind(application.redirected_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_schedule_application(&self, device_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM schedule_applied WHERE device_id = ?")
+            .bind(device_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn create_group(&self, name: &str, color: Option<&str>) -> Result<DeviceGroup> {
+        let created_at = Utc::now().timestamp();
+        let id = sqlx::query("INSERT INTO device_groups (name, color, created_at) VALUES (?, ?, ?)")
+            .bind(name)
+            .bind(color)
+            .bind(created_at)
+            .execute(&self.pool)
+            .await?
+            .last_insert_rowid();
+
+        Ok(DeviceGroup {
+            id,
+            name: name.to_string(),
+            color: color.map(str::to_string),
+            created_at,
+            protected: false,
+            device_ids: Vec::new(),
+        })
+    }
+
+    pub async fn update_group(&self, group_id: i64, name: &str, color: Option<&str>) -> Result<bool> {
+        let result = sqlx::query("UPDATE device_groups SET name = ?, color = ? WHERE id = ?")
+            .bind(name)
+            .bind(color)
+            .bind(group_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn set_group_protected(&self, group_id: i64, protected: bool) -> Result<bool> {
+        let result = sqlx::query("UPDATE device_groups SET protected = ? WHERE id = ?")
+            .bind(protected)
+            .bind(group_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_group(&self, group_id: i64) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM device_group_members WHERE group_id = ?")
+            .bind(group_id)
+            .execute(&mut *tx)
+            .await?;
+        let result = sqlx::query("DELETE FROM device_groups WHERE id = ?")
+            .bind(group_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_group(&self, group_id: i64) -> Result<Option<DeviceGroup>> {
+        let row = sqlx::query_as::<_, GroupRow>(
+            "SELECT id, name, color, created_at, protected FROM device_groups WHERE id = ?"
+        )
+        .bind(group_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match row {
+            Some(row) => {
+                let device_ids = self.get_group_members(row.id).await?;
+                Ok(Some(Self::group_from_row(row, device_ids)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub async fn get_groups(&self) -> Result<Vec<DeviceGroup>> {
+        let rows = sqlx::query_as::<_, GroupRow>(
+            "SELECT id, name, color, created_at, protected FROM device_groups ORDER BY name"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let members = sqlx::query_as::<_, (i64, String)>(
+            "SELECT group_id, device_id FROM device_group_members ORDER BY device_id"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let device_ids = members
+                    .iter()
+                    .filter(|(group_id, _)| *group_id == row.id)
+                    .map(|(_, device_id)| device_id.clone())
+                    .collect();
+                Self::group_from_row(row, device_ids)
+            })
+            .collect())
+    }
+
+    pub async fn get_group_members(&self, group_id: i64) -> Result<Vec<String>> {
+        let device_ids = sqlx::query_scalar::<_, String>(
+            "SELECT device_id FROM device_group_members WHERE group_id = ? ORDER BY device_id"
+        )
+        .bind(group_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(device_ids)
+    }
+
+    /// Add devices to a group. Devices already in the group are left as-is.
+    /// Returns the IDs not found in the device table, in which case nothing
+    /// is added.
+    pub async fn add_group_members(&self, group_id: i64, device_ids: &[String]) -> Result<Vec<String>> {
+        let mut missing = Vec::new();
+
+        let mut tx = self.pool.begin().await?;
+        for device_id in device_ids {
+            let result = sqlx::query(
+                r#"
+                INSERT OR IGNORE INTO device_group_members (group_id, device_id)
+                SELECT ?, id FROM devices WHERE id = ?
+                "#,
+            )
+            .bind(group_id)
+            .bind(device_id)
+            .execute(&mut *tx)
+            .await?;
+
+            if result.rows_affected() == 0 {
+                let exists: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM devices WHERE id = ?")
+                    .bind(device_id)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                if exists == 0 {
+                    missing.push(device_id.clone());
+                }
+            }
+        }
+
+        if missing.is_empty() {
+            tx.commit().await?;
+        } else {
+            tx.rollback().await?;
+        }
+
+        Ok(missing)
+    }
+
+    pub async fn remove_group_members(&self, group_id: i64, device_ids: &[String]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for device_id in device_ids {
+            sqlx::query("DELETE FROM device_group_members WHERE group_id = ? AND device_id = ?")
+                .bind(group_id)
+                .bind(device_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    fn group_from_row(row: GroupRow, device_ids: Vec<String>) -> DeviceGroup {
+        DeviceGroup {
+            id: row.id,
+            name: row.name,
+            color: row.color,
+            created_at: row.created_at,
+            protected: row.protected,
+            device_ids,
+        }
+    }
+
+    /// Write an inventory import in one transaction, so an import that
+    /// fails part way changes nothing
+    pub async fn apply_import(&self, changes: &ImportChanges) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for device in &changes.devices {
+            self.write_device(device, &mut *tx).await?;
+        }
+        for quota in &changes.quotas {
+            Self::write_quota(quota, &mut *tx).await?;
+        }
+        for (key, value) in &changes.settings {
+            self.write_setting(key, value, &mut *tx).await?;
+        }
+        for device in &changes.protected {
+            self.write_protected_device(&device.mac, device.label.as_deref(), device.level, device.created_at, &mut *tx).await?;
+        }
+
+        for group in &changes.groups {
+            let group_id = match group.id {
+                Some(id) => {
+                    sqlx::query("UPDATE device_groups SET color = ?, protected = ? WHERE id = ?")
+                        .bind(&group.color)
+                        .bind(group.protected)
+                        .bind(id)
+                        .execute(&mut *tx)
+                        .await?;
+                    id
+                }
+                None => sqlx::query("INSERT INTO device_groups (name, color, created_at, protected) VALUES (?, ?, ?, ?)")
+                    .bind(&group.name)
+                    .bind(&group.color)
+                    .bind(Utc::now().timestamp())
+                    .bind(group.protected)
+                    .execute(&mut *tx)
+                    .await?
+                    .last_insert_rowid(),
+            };
+
+            for device_id in &group.device_ids {
+                sqlx::query("INSERT OR IGNORE INTO device_group_members (group_id, device_id) VALUES (?, ?)")
+                    .bind(group_id)
+                    .bind(device_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        for import in &changes.schedules {
+            let mut schedule = import.schedule.clone();
+            if let Some(group) = &import.group {
+                let group_id: i64 = sqlx::query_scalar("SELECT id FROM device_groups WHERE name = ?")
+                    .bind(group)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("Schedule {} targets unknown group {}", schedule.name, group))?;
+                schedule.target_id = group_id.to_string();
+            }
+
+            if schedule.id == 0 {
+                Self::insert_schedule(&schedule, &mut *tx).await?;
+            } else {
+                Self::write_schedule(&schedule, &mut *tx).await?;
+            }
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Append an entry to the audit log, chaining it to the last one
+    pub async fn append_audit_entry(&self, record: &AuditRecord) -> Result<AuditEntry> {
+        let timestamp = Utc::now().timestamp();
+
+        // The transaction keeps two writers from chaining to the same entry
+        let mut tx = self.pool.begin().await?;
+        let prev_hash = sqlx::query_scalar::<_, String>("SELECT hash FROM audit_log ORDER BY id DESC LIMIT 1")
+            .fetch_optional(&mut *tx)
+            .await?
+            .unwrap_or_else(|| audit::GENESIS_HASH.to_string());
+
+        let mut entry = AuditEntry {
+            id: 0,
+            timestamp,
+            actor: record.actor.clone(),
+            action: record.action.clone(),
+            target: record.target.clone(),
+            reason: record.reason.clone(),
+            outcome: record.outcome.clone(),
+            details: record.details.clone(),
+            hash: String::new(),
+            prev_hash,
+        };
+        entry.hash = audit::entry_hash(&entry);
+
+        entry.id = sqlx::query(
+            r#"
+            INSERT INTO audit_log (timestamp, actor, action, target, reason, outcome, details, prev_hash, hash)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(entry.timestamp)
+        .bind(&entry.actor)
+        .bind(&entry.action)
+        .bind(&entry.target)
+        .bind(self.seal(entry.reason.as_deref()))
+        .bind(&entry.outcome)
+        .bind(self.seal(entry.details.as_deref()))
+        .bind(&entry.prev_hash)
+        .bind(&entry.hash)
+        .execute(&mut *tx)
+        .await?
+        .last_insert_rowid();
+        tx.commit().await?;
+
+        Ok(entry)
+    }
+
+    /// Query the audit log, newest first
+    pub async fn get_audit_log(&self, limit: Option<i64>, offset: Option<i64>) -> Result<AuditPage> {
+        let limit = limit.unwrap_or(DEFAULT_AUDIT_PAGE_SIZE).clamp(1, MAX_AUDIT_PAGE_SIZE);
+        let offset = offset.unwrap_or(0).max(0);
+
+        let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM audit_log")
+            .fetch_one(&self.pool)
+            .await?;
+
+        let entries = sqlx::query_as::<_, AuditEntry>(
+            r#"
+            SELECT id, timestamp, actor, action, target, reason, outcome, details, prev_hash, hash
+            FROM audit_log ORDER BY id DESC LIMIT ? OFFSET ?
+            "#,
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|entry| self.open_audit_entry(entry))
+        .collect();
+
+        Ok(AuditPage { entries, total, limit, offset })
+    }
+
+    /// Walk the audit log from the start, checking every entry's hash and
+    /// its link to the entry before it
+    pub async fn verify_audit_log(&self) -> Result<AuditVerification> {
+        let entries = sqlx::query_as::<_, AuditEntry>(
+            r#"
+            SELECT id, timestamp, actor, action, target, reason, outcome, details, prev_hash, hash
+            FROM audit_log ORDER BY id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|entry| self.open_audit_entry(entry))
+        .collect::<Vec<_>>();
+
+        let mut expected_prev = audit::GENESIS_HASH.to_string();
+        let mut first_broken_id = None;
+        for entry in &entries {
+            if entry.prev_hash != expected_prev || entry.hash != audit::entry_hash(entry) {
+                first_broken_id = Some(entry.id);
+                break;
+            }
+            expected_prev = entry.hash.clone();
+        }
+
+        Ok(AuditVerification {
+            valid: first_broken_id.is_none(),
+            entries: entries.len() as i64,
+            first_broken_id,
+        })
+    }
+
+    /// Whether the database was encrypted with `cipher`. A database that has
+    /// never been encrypted accepts any key.
+    pub async fn verify_cipher(&self, cipher: &Cipher) -> Result<bool> {
+        let mut conn = self.pool.acquire().await?;
+        Self::check_key(&mut conn, cipher).await
+    }
+
+    async fn check_key(conn: &mut SqliteConnection, cipher: &Cipher) -> Result<bool> {
+        let check = sqlx::query_scalar::<_, String>("SELECT value FROM settings WHERE key = ?")
+            .bind(KEY_CHECK_SETTING)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        Ok(match check {
+            Some(check) => cipher.decrypt(&check).is_ok_and(|value| value == KEY_CHECK_PLAINTEXT),
+            None => true,
+        })
+    }
+
+    /// Whether any key has ever been used on this database
+    pub async fn has_key_check(&self) -> Result<bool> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM settings WHERE key = ?")
+            .bind(KEY_CHECK_SETTING)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count > 0)
+    }
+
+    /// Use `cipher` for all sensitive values from now on
+    pub async fn set_cipher(&mut self, cipher: Cipher) -> Result<()> {
+        if !self.has_key_check().await? {
+            self.write_key_check(&cipher, &self.pool).await?;
+        }
+        self.cipher = Some(cipher);
+        Ok(())
+    }
+
+    async fn write_key_check<'e, E>(&self, cipher: &Cipher, executor: E) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query(
+            r#"
+            INSERT INTO settings (key, value) VALUES (?, ?)
+            ON CONFLICT(key) DO UPDATE SET value = excluded.value
+            "#,
+        )
+        .bind(KEY_CHECK_SETTING)
+        .bind(cipher.encrypt(KEY_CHECK_PLAINTEXT))
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Encrypt sensitive values still stored in plaintext. Returns the
+    /// number of values encrypted.
+    pub async fn encrypt_existing_rows(&self) -> Result<usize> {
+        let Some(cipher) = &self.cipher else {
+            return Ok(0);
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let encrypted = Self::rewrite_sensitive_values(&mut tx, |value| {
+            if Cipher::is_encrypted(value) {
+                Ok(None)
+            } else {
+                Ok(Some(cipher.encrypt(value)))
+            }
+        })
+        .await?;
+        tx.commit().await?;
+
+        Ok(encrypted)
+    }
+
+    /// Re-encrypt every sensitive value under `cipher` in one transaction and
+    /// switch to it. Returns the number of values re-encrypted.
+    pub async fn rotate_cipher(&mut self, cipher: Cipher) -> Result<usize> {
+        let current = self.cipher.clone();
+
+        let mut tx = self.pool.begin().await?;
+        let rotated = Self::rewrite_sensitive_values(&mut tx, |value| {
+            let plaintext = match (&current, Cipher::is_encrypted(value)) {
+                (Some(current), true) => current.decrypt(value)?,
+                (None, true) => return Err(anyhow::anyhow!("No key loaded to decrypt existing values")),
+                (_, false) => value.to_string(),
+            };
+            Ok(Some(cipher.encrypt(&plaintext)))
+        })
+        .await?;
+        self.write_key_check(&cipher, &mut *tx).await?;
+        tx.commit().await?;
+
+        self.cipher = Some(cipher);
+        Ok(rotated)
+    }
+
+    /// Apply `rewrite` to every stored sensitive value, updating the rows
+    /// for which it returns a new value
+    async fn rewrite_sensitive_values<F>(conn: &mut SqliteConnection, rewrite: F) -> Result<usize>
+    where
+        F: Fn(&str) -> Result<Option<String>>,
+    {
+        let mut rewritten = 0;
+
+        for (table, column) in ENCRYPTED_COLUMNS {
+            let select = format!("SELECT rowid, {column} FROM {table} WHERE {column} IS NOT NULL");
+            let update = format!("UPDATE {table} SET {column} = ? WHERE rowid = ?");
+
+            let rows: Vec<(i64, String)> = sqlx::query_as(&select).fetch_all(&mut *conn).await?;
+            for (rowid, value) in rows {
+                if let Some(value) = rewrite(&value)? {
+                    sqlx::query(&update).bind(value).bind(rowid).execute(&mut *conn).await?;
+                    rewritten += 1;
+                }
+            }
+        }
+
+        let settings: Vec<(String, String)> = sqlx::query_as("SELECT key, value FROM settings")
+            .fetch_all(&mut *conn)
+            .await?;
+        for (key, value) in settings {
+            if !is_sensitive_setting(&key) {
+                continue;
+            }
+            if let Some(value) = rewrite(&value)? {
+                sqlx::query("UPDATE settings SET value = ? WHERE key = ?")
+                    .bind(value)
+                    .bind(&key)
+                    .execute(&mut *conn)
+                    .await?;
+                rewritten += 1;
+            }
+        }
+
+        Ok(rewritten)
+    }
+
+    /// Copy the live database to `dest` using SQLite's online backup API.
+    /// The copy is written next to `dest` and renamed into place, so an
+    /// interrupted backup never leaves a truncated file behind.
+    pub async fn backup_to(&self, dest: &Path) -> Result<()> {
+        let partial = dest.with_extension("partial");
+        let partial_c = path_to_cstring(&partial)?;
+
+        let mut conn = self.pool.acquire().await?;
+        let mut handle = conn.lock_handle().await?;
+        let live = handle.as_raw_handle().as_ptr();
+
+        // SAFETY: `live` stays valid while `handle` holds the connection lock,
+        // and the destination handle is closed on every path below.
+        unsafe {
+            let mut target = std::ptr::null_mut();
+            let rc = ffi::sqlite3_open(partial_c.as_ptr(), &mut target);
+            if rc != ffi::SQLITE_OK {
+                let message = sqlite_error_message(target);
+                ffi::sqlite3_close(target);
+                return Err(anyhow::anyhow!("Failed to create backup file: {}", message));
+            }
+
+            let result = copy_database(live, target);
+            ffi::sqlite3_close(target);
+            result?;
+        }
+
+        drop(handle);
+        drop(conn);
+
+        std::fs::rename(&partial, dest)?;
+        Ok(())
+    }
+
+    /// Check that `source` is a backup this database can be restored from
+    pub async fn check_restore_source(&self, source: &Path) -> Result<()> {
+        if source.canonicalize().ok() == self.path.canonicalize().ok() {
+            return Err(anyhow::anyhow!("Cannot restore the database from itself"));
+        }
+
+        Self::validate_file(source).await?;
+
+        if let Some(cipher) = &self.cipher {
+            let options = SqliteConnectOptions::new()
+                .filename(source)
+                .read_only(true);
+            let mut conn = SqliteConnection::connect_with(&options).await?;
+            let matches = Self::check_key(&mut conn, cipher).await?;
+            conn.close().await?;
+            if !matches {
+                return Err(anyhow::anyhow!("The backup was encrypted with a different key"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the contents of the live database with `source` after
+    /// validating it. A copy of the current data is kept next to the
+    /// database file so a bad restore can be undone by hand.
+    pub async fn restore_from(&self, source: &Path) -> Result<()> {
+        self.check_restore_source(source).await?;
+
+        let safety_copy = self.path.with_extension("pre-restore.db");
+        self.backup_to(&safety_copy).await?;
+
+        let source_c = path_to_cstring(source)?;
+
+        let mut conn = self.pool.acquire().await?;
+        let mut handle = conn.lock_handle().await?;
+        let live = handle.as_raw_handle().as_ptr();
+
+        // SAFETY: as in `backup_to`; the source is opened read-only.
+        unsafe {
+            let mut origin = std::ptr::null_mut();
+            let rc = ffi::sqlite3_open_v2(source_c.as_ptr(), &mut origin, ffi::SQLITE_OPEN_READONLY, std::ptr::null());
+            if rc != ffi::SQLITE_OK {
+                let message = sqlite_error_message(origin);
+                ffi::sqlite3_close(origin);
+                return Err(anyhow::anyhow!("Failed to open backup file: {}", message));
+            }
+
+            let result = copy_database(origin, live);
+            ffi::sqlite3_close(origin);
+            result?;
+        }
+
+        drop(handle);
+        drop(conn);
+
+        // Backups from older versions may predate some tables or encryption
+        self.create_tables().await?;
+        if let Some(cipher) = &self.cipher {
+            if !self.has_key_check().await? {
+                self.write_key_check(cipher, &self.pool).await?;
+            }
+        }
+        self.encrypt_existing_rows().await?;
+        Ok(())
+    }
+
+    /// Check that a file is an intact SQLite database with the NetSnip schema
+    pub async fn validate_file(path: &Path) -> Result<()> {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!("{} does not exist", path.display()));
+        }
+
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .read_only(true);
+        let mut conn = SqliteConnection::connect_with(&options).await
+            .map_err(|e| anyhow::anyhow!("Not a readable SQLite database: {}", e))?;
+
+        let integrity: Vec<String> = sqlx::query_scalar("PRAGMA integrity_check")
+            .fetch_all(&mut conn)
+            .await
+            .map_err(|e| anyhow::anyhow!("Integrity check failed: {}", e))?;
+        if integrity.first().map(String::as_str) != Some("ok") {
+            return Err(anyhow::anyhow!("Integrity check failed: {}", integrity.join("; ")));
+        }
+
+        let tables: Vec<String> = sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table'")
+            .fetch_all(&mut conn)
+            .await?;
+        for required in REQUIRED_TABLES {
+            if !tables.iter().any(|t| t == required) {
+                return Err(anyhow::anyhow!("Missing table {}; not a NetSnip database", required));
+            }
+        }
+
+        conn.close().await?;
+        Ok(())
+    }
+}
+
+/// Weekdays as a bitmask, Monday in the lowest bit
+fn days_to_mask(days: &[Weekday]) -> i64 {
+    days.iter().fold(0, |mask, day| mask | (1 << day.num_days_from_monday()))
+}
+
+fn mask_to_days(mask: i64) -> Vec<Weekday> {
+    (0..7u8)
+        .filter(|bit| mask & (1 << bit) != 0)
+        .filter_map(|bit| Weekday::try_from(bit).ok())
+        .collect()
+}
+
+fn path_to_cstring(path: &Path) -> Result<CString> {
+    CString::new(path.to_string_lossy().as_bytes())
+        .map_err(|_| anyhow::anyhow!("Invalid database path: {}", path.display()))
+}
+
+/// # Safety
+/// `db` must be a valid handle or null.
+unsafe fn sqlite_error_message(db: *mut ffi::sqlite3) -> String {
+    if db.is_null() {
+        return "out of memory".to_string();
+    }
+    CStr::from_ptr(ffi::sqlite3_errmsg(db)).to_string_lossy().into_owned()
+}
+
+/// Copy the main database of `source` into `target` in one backup pass
+///
+/// # Safety
+/// Both handles must be valid open connections for the whole call.
+unsafe fn copy_database(source: *mut ffi::sqlite3, target: *mut ffi::sqlite3) -> Result<()> {
+    let main = c"main";
+    let backup = ffi::sqlite3_backup_init(target, main.as_ptr(), source, main.as_ptr());
+    if backup.is_null() {
+        return Err(anyhow::anyhow!("Failed to start backup: {}", sqlite_error_message(target)));
+    }
+
+    // Retry for up to five seconds while another connection holds a lock
+    let mut rc = ffi::SQLITE_BUSY;
+    for _ in 0..100 {
+        rc = ffi::sqlite3_backup_step(backup, -1);
+        if rc != ffi::SQLITE_BUSY && rc != ffi::SQLITE_LOCKED {
+            break;
+        }
+        ffi::sqlite3_sleep(50);
+    }
+
+    let finish_rc = ffi::sqlite3_backup_finish(backup);
+    if rc != ffi::SQLITE_DONE || finish_rc != ffi::SQLITE_OK {
+        return Err(anyhow::anyhow!("Backup failed: {}", sqlite_error_message(target)));
+    }
+
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE_ID: &str = "02_00_00_00_00_10";
+
+    struct TempDatabase {
+        database: Database,
+        path: PathBuf,
+    }
+
+    impl Drop for TempDatabase {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    async fn temp_database(name: &str) -> TempDatabase {
+        let path = std::env::temp_dir().join(format!("netsnip-database-{}-{}.db", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        let database = Database::new(&path).await.unwrap();
+        TempDatabase { database, path }
+    }
+
+    fn device(custom_name: &str) -> DeviceRecord {
+        DeviceRecord {
+            id: DEVICE_ID.to_string(),
+            mac: DEVICE_ID.replace('_', ":"),
+            ip: "192.168.1.10".to_string(),
+            hostname: None,
+            custom_name: Some(custom_name.to_string()),
+            manufacturer: None,
+            device_type: "unknown".to_string(),
+            first_seen: 0,
+            last_seen: 0,
+            total_bytes: 0,
+            is_blocked: false,
+            bandwidth_limit: None,
+            notes: None,
+            icon: None,
+        }
+    }
+
+    fn audit_record(reason: &str) -> AuditRecord {
+        AuditRecord {
+            actor: "user:test".to_string(),
+            action: "cut".to_string(),
+            target: DEVICE_ID.to_string(),
+            reason: Some(reason.to_string()),
+            outcome: "succeeded".to_string(),
+            details: Some(r#"{"ip":"192.168.1.10"}"#.to_string()),
+        }
+    }
+
+    impl TempDatabase {
+        async fn stored(&self, table: &str, column: &str) -> Vec<String> {
+            let select = format!("SELECT {column} FROM {table} WHERE {column} IS NOT NULL ORDER BY rowid");
+            sqlx::query_scalar(&select).fetch_all(&self.database.pool).await.unwrap()
+        }
+
+        async fn custom_name(&self) -> Option<String> {
+            let device = self.database.get_device_by_mac(&DEVICE_ID.replace('_', ":")).await.unwrap();
+            device.unwrap().custom_name
+        }
+    }
+
+    #[tokio::test]
+    async fn values_are_sealed_once_a_key_is_loaded() {
+        let mut temp = temp_database("sealed").await;
+        temp.database.set_cipher(Cipher::from_master_key(&[1; 64])).await.unwrap();
+
+        temp.database.upsert_device(&device("Kitchen speaker")).await.unwrap();
+
+        let stored = temp.stored("devices", "custom_name").await;
+        assert!(Cipher::is_encrypted(&stored[0]));
+        assert!(!stored[0].contains("Kitchen"));
+        assert_eq!(temp.custom_name().await.as_deref(), Some("Kitchen speaker"));
+    }
+
+    #[tokio::test]
+    async fn plaintext_from_before_encryption_is_read_and_then_migrated() {
+        let mut temp = temp_database("legacy").await;
+        temp.database.upsert_device(&device("Kitchen speaker")).await.unwrap();
+        assert_eq!(temp.stored("devices", "custom_name").await, ["Kitchen speaker"]);
+
+        temp.database.set_cipher(Cipher::from_master_key(&[1; 64])).await.unwrap();
+        assert_eq!(temp.custom_name().await.as_deref(), Some("Kitchen speaker"));
+
+        assert_eq!(temp.database.encrypt_existing_rows().await.unwrap(), 1);
+        assert!(Cipher::is_encrypted(&temp.stored("devices", "custom_name").await[0]));
+        assert_eq!(temp.custom_name().await.as_deref(), Some("Kitchen speaker"));
+    }
+
+    #[tokio::test]
+    async fn rotation_re_encrypts_under_the_new_key() {
+        let mut temp = temp_database("rotation").await;
+        temp.database.set_cipher(Cipher::from_master_key(&[1; 64])).await.unwrap();
+        temp.database.upsert_device(&device("Kitchen speaker")).await.unwrap();
+
+        let new_cipher = Cipher::from_master_key(&[2; 64]);
+        temp.database.rotate_cipher(new_cipher.clone()).await.unwrap();
+
+        assert!(temp.database.verify_cipher(&new_cipher).await.unwrap());
+        assert!(!temp.database.verify_cipher(&Cipher::from_master_key(&[1; 64])).await.unwrap());
+        let stored = temp.stored("devices", "custom_name").await;
+        assert_eq!(new_cipher.decrypt(&stored[0]).unwrap(), "Kitchen speaker");
+        assert_eq!(temp.custom_name().await.as_deref(), Some("Kitchen speaker"));
+    }
+
+    #[tokio::test]
+    async fn audit_entries_are_encrypted_without_breaking_the_chain() {
+        let mut temp = temp_database("audit").await;
+        // One entry from before encryption was enabled
+        temp.database.append_audit_entry(&audit_record("too much streaming")).await.unwrap();
+        temp.database.set_cipher(Cipher::from_master_key(&[1; 64])).await.unwrap();
+        temp.database.append_audit_entry(&audit_record("homework time")).await.unwrap();
+        temp.database.encrypt_existing_rows().await.unwrap();
+
+        for column in ["reason", "details"] {
+            let stored = temp.stored("audit_log", column).await;
+            assert_eq!(stored.len(), 2);
+            assert!(stored.iter().all(|value| Cipher::is_encrypted(value)));
+        }
+        assert!(temp.database.verify_audit_log().await.unwrap().valid);
+
+        temp.database.rotate_cipher(Cipher::from_master_key(&[2; 64])).await.unwrap();
+        assert!(temp.database.verify_audit_log().await.unwrap().valid);
+
+        let page = temp.database.get_audit_log(None, None).await.unwrap();
+        let reasons: Vec<_> = page.entries.iter().map(|entry| entry.reason.as_deref()).collect();
+        assert_eq!(reasons, [Some("homework time"), Some("too much streaming")]);
+    }
+}
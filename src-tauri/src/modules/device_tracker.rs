@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use crate::modules::arp_controller::{ArpController, ArpSpoof};
+use crate::modules::database::Database;
+use crate::modules::transmitter::Transmitter;
+use crate::utils::safety::normalize_mac;
+
+/// Keeps cuts and limits on the device they were made for when it changes
+/// address. Sightings of a MAC at an IP come from scans and from frames the
+/// forwarder sees; a redirected MAC seen at a new IP has its spoof entry
+/// moved there.
+#[derive(Clone)]
+pub struct DeviceTracker {
+    pub spoofs: Arc<Mutex<HashMap<String, ArpSpoof>>>,  // Keyed by MAC
+    pub transmitter: Transmitter,
+    pub journal: Option<Arc<Mutex<Database>>>,
+    /// Addresses a redirected device moved away from. The gateway still
+    /// thinks they are ours until whoever takes them over is seen.
+    pub vacated: Arc<Mutex<HashMap<Ipv4Addr, ArpSpoof>>>,
+    /// Sightings queued by the forwarder, MAC -> IP
+    pub pending: Arc<Mutex<HashMap<String, Ipv4Addr>>>,
+}
+
+impl DeviceTracker {
+    /// Handle the sightings the forwarder has queued since the last call
+    pub async fn process_pending(&self) {
+        let sightings: Vec<(Ipv4Addr, String)> = {
+            let mut pending = self.pending.lock().await;
+            pending.drain().map(|(mac, ip)| (ip, mac)).collect()
+        };
+        if !sightings.is_empty() {
+            self.observe(&sightings).await;
+        }
+    }
+
+    /// Record where devices were seen, following redirected devices to their
+    /// new address and giving back addresses they left behind
+    pub async fn observe(&self, sightings: &[(Ipv4Addr, String)]) {
+        let sightings: Vec<(Ipv4Addr, String)> = sightings
+            .iter()
+            .filter_map(|(ip, mac)| Some((*ip, normalize_mac(mac)?)))
+            .collect();
+
+        for (ip, mac) in &sightings {
+            self.migrate(*ip, mac).await;
+        }
+
+        // Done after every move so an address vacated in this batch can be
+        // handed to the device seen at it in the same batch
+        for (ip, mac) in &sightings {
+            self.reclaim(*ip, mac).await;
+        }
+    }
+
+    async fn migrate(&self, ip: Ipv4Addr, mac: &str) {
+        let moved = {
+            let mut spoofs = self.spoofs.lock().await;
+            match spoofs.get_mut(mac).filter(|s| s.active && s.target_ip != ip) {
+                Some(spoof) => {
+                    let old = spoof.clone();
+                    spoof.target_ip = ip;
+                    Some((old, spoof.clone()))
+                }
+                None => None,
+            }
+        };
+        let Some((old, spoof)) = moved else {
+            return;
+        };
+
+        log::info!("Device {} moved from {} to {}, moving its redirect", mac, old.target_ip, ip);
+
+        if let Some(journal) = &self.journal {
+            let database = journal.lock().await;
+            if let Err(e) = database.add_cut_journal_entry(&spoof.journal_entry()).await {
+                log::warn!("Failed to journal cut of {}: {}", ip, e);
+            }
+        }
+
+        let mut vacated = self.vacated.lock().await;
+        vacated.remove(&ip);
+        vacated.insert(old.target_ip, old);
+    }
+
+    /// If `ip` was left by a redirected device and someone else now has it,
+    /// restore the real mapping between that host and the gateway
+    async fn reclaim(&self, ip: Ipv4Addr, mac: &str) {
+        let Some(old) = self.vacated.lock().await.remove(&ip) else {
+            return;
+        };
+        if old.target_mac == mac {
+            return;
+        }
+
+        log::info!("Address {} left by {} is now used by {}, restoring it", ip, old.target_mac, mac);
+        if let Err(e) = ArpController::send_restore_packets(&self.transmitter, ip, mac, old.gateway_ip, &old.gateway_mac).await {
+            log::warn!("Failed to restore address {} for {}: {}", ip, mac, e);
+            self.vacated.lock().await.insert(ip, old);
+        }
+    }
+}
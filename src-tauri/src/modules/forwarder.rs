@@ -0,0 +1,373 @@
+use anyhow::Result;
+use pnet::datalink::{self, Channel, Config, NetworkInterface};
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::Packet;
+use pnet::util::MacAddr;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::sleep_until;
+use crate::modules::arp_controller::ArpSpoof;
+use crate::modules::bandwidth::{BandwidthController, Direction};
+use crate::modules::gateway::arp_sender;
+
+/// How long a receive may block before the loop checks whether it should stop
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Re-emits frames from ARP-redirected devices toward the real gateway or
+/// device. Each frame is paced through the device's token bucket for its
+/// direction and held in a send queue until it is due. Frames from cut
+/// devices are not in the forwarding set and are dropped.
+#[derive(Clone)]
+pub struct Forwarder {
+    interface: NetworkInterface,
+    our_mac: MacAddr,
+    our_ip: Ipv4Addr,
+    spoofs: Arc<Mutex<HashMap<String, ArpSpoof>>>,
+    bandwidth: Arc<Mutex<BandwidthController>>,
+    gateway_ip: Arc<Mutex<Option<Ipv4Addr>>>,
+    gateway_mac: Arc<Mutex<Option<[u8; 6]>>>,  // The controller's, updated when the gateway changes
+    gateway_suspect: Arc<Mutex<bool>>,
+    sightings: Arc<Mutex<HashMap<String, Ipv4Addr>>>,  // Redirected MACs seen at a new IP
+    running: Arc<Mutex<bool>>,
+}
+
+/// Where a captured frame should go next
+struct Route {
+    device_ip: Ipv4Addr,
+    device_mac: String,  // Normalised, as limits are keyed
+    direction: Direction,
+    next_hop: MacAddr,
+}
+
+/// A rewritten frame waiting for its send time
+struct QueuedFrame {
+    send_at: Instant,
+    seq: u64,  // Keeps frames due at the same instant in arrival order
+    device_ip: Ipv4Addr,
+    device_mac: String,
+    direction: Direction,
+    frame: Vec<u8>,
+}
+
+impl PartialEq for QueuedFrame {
+    fn eq(&self, other: &Self) -> bool {
+        (self.send_at, self.seq) == (other.send_at, other.seq)
+    }
+}
+
+impl Eq for QueuedFrame {}
+
+impl PartialOrd for QueuedFrame {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedFrame {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.send_at, self.seq).cmp(&(other.send_at, other.seq))
+    }
+}
+
+impl Forwarder {
+    pub fn new(
+        interface: NetworkInterface,
+        spoofs: Arc<Mutex<HashMap<String, ArpSpoof>>>,
+        bandwidth: Arc<Mutex<BandwidthController>>,
+        gateway_ip: Option<Ipv4Addr>,
+        gateway_mac: Arc<Mutex<Option<[u8; 6]>>>,
+        gateway_suspect: Arc<Mutex<bool>>,
+        sightings: Arc<Mutex<HashMap<String, Ipv4Addr>>>,
+    ) -> Result<Self> {
+        let our_mac = interface.mac.ok_or_else(|| anyhow::anyhow!("Interface has no MAC address"))?;
+        let our_ip = interface
+            .ips
+            .iter()
+            .find_map(|ip| match ip.ip() {
+                IpAddr::V4(ipv4) => Some(ipv4),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow::anyhow!("No IPv4 address found"))?;
+
+        Ok(Self {
+            interface,
+            our_mac,
+            our_ip,
+            spoofs,
+            bandwidth,
+            gateway_ip: Arc::new(Mutex::new(gateway_ip)),
+            gateway_mac,
+            gateway_suspect,
+            sightings,
+            running: Arc::new(Mutex::new(false)),
+        })
+    }
+
+    /// Follow the controller to a newly configured gateway
+    pub async fn set_gateway_ip(&self, gateway_ip: Ipv4Addr) {
+        *self.gateway_ip.lock().await = Some(gateway_ip);
+    }
+
+    /// Start forwarding if it is not already running. The loop stops by
+    /// itself once no redirected devices are left.
+    pub async fn start(&self) -> Result<()> {
+        let mut running = self.running.lock().await;
+        if *running {
+            return Ok(());
+        }
+
+        let config = Config {
+            read_timeout: Some(READ_TIMEOUT),
+            ..Default::default()
+        };
+        let (tx, rx) = match datalink::channel(&self.interface, config) {
+            Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
+            Ok(_) => return Err(anyhow::anyhow!("Unhandled channel type")),
+            Err(e) => return Err(anyhow::anyhow!("Failed to create channel: {}", e)),
+        };
+        *running = true;
+        drop(running);
+
+        let forwarder = ForwardLoop {
+            our_mac: self.our_mac,
+            our_ip: self.our_ip,
+            spoofs: self.spoofs.clone(),
+            bandwidth: self.bandwidth.clone(),
+            gateway_ip: self.gateway_ip.clone(),
+            gateway_mac: self.gateway_mac.clone(),
+            gateway_suspect: self.gateway_suspect.clone(),
+            sightings: self.sightings.clone(),
+            running: self.running.clone(),
+        };
+
+        // Receiving blocks, so the loop gets its own thread. Frames are
+        // handed to a separate task that sends each one when it is due.
+        let (queue_tx, queue_rx) = mpsc::unbounded_channel();
+        tokio::spawn(send_queued(tx, queue_rx, self.bandwidth.clone()));
+
+        let handle = Handle::current();
+        tokio::task::spawn_blocking(move || handle.block_on(forwarder.run(rx, queue_tx)));
+
+        log::info!("Packet forwarding started on {}", self.interface.name);
+        Ok(())
+    }
+}
+
+struct ForwardLoop {
+    our_mac: MacAddr,
+    our_ip: Ipv4Addr,
+    spoofs: Arc<Mutex<HashMap<String, ArpSpoof>>>,
+    bandwidth: Arc<Mutex<BandwidthController>>,
+    gateway_ip: Arc<Mutex<Option<Ipv4Addr>>>,
+    gateway_mac: Arc<Mutex<Option<[u8; 6]>>>,  // The controller's, updated when the gateway changes
+    gateway_suspect: Arc<Mutex<bool>>,
+    sightings: Arc<Mutex<HashMap<String, Ipv4Addr>>>,  // Redirected MACs seen at a new IP
+    running: Arc<Mutex<bool>>,
+}
+
+impl ForwardLoop {
+    async fn run(self, mut rx: Box<dyn datalink::DataLinkReceiver>, queue: mpsc::UnboundedSender<QueuedFrame>) {
+        let mut queued: u64 = 0;
+        let mut dropped: u64 = 0;
+        let mut seq: u64 = 0;
+        let mut last_check = Instant::now();
+
+        loop {
+            // Stop once nothing is redirected any more. The flag stays locked
+            // while checking so a concurrent start() either has its route
+            // picked up here or starts a fresh loop.
+            if last_check.elapsed() >= CHECK_INTERVAL {
+                let mut running = self.running.lock().await;
+                if !self.has_routes().await {
+                    *running = false;
+                    break;
+                }
+                last_check = Instant::now();
+            }
+
+            let frame = match rx.next() {
+                Ok(frame) => frame.to_vec(),
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => continue,
+                Err(e) => {
+                    log::error!("Error receiving packet to forward: {}", e);
+                    continue;
+                }
+            };
+
+            // ARP frames are only looked at, never forwarded
+            if self.inspect_arp(&frame).await {
+                continue;
+            }
+
+            let Some(route) = self.route(&frame).await else {
+                continue;
+            };
+
+            // Frames are delayed to stay under the limit; only when the
+            // device's queue is full are they dropped, which makes TCP back off
+            let bandwidth = self.bandwidth.lock().await;
+            let delay = bandwidth.pace_packet(&route.device_mac, route.direction, frame.len()).await;
+            drop(bandwidth);
+            let Some(delay) = delay else {
+                dropped += 1;
+                continue;
+            };
+
+            let mut frame = frame;
+            if let Some(mut ethernet) = MutableEthernetPacket::new(&mut frame) {
+                ethernet.set_source(self.our_mac);
+                ethernet.set_destination(route.next_hop);
+            }
+
+            seq += 1;
+            let queued_frame = QueuedFrame {
+                send_at: Instant::now() + delay,
+                seq,
+                device_ip: route.device_ip,
+                device_mac: route.device_mac,
+                direction: route.direction,
+                frame,
+            };
+            if queue.send(queued_frame).is_err() {
+                log::error!("Forwarding send queue closed");
+                break;
+            }
+            queued += 1;
+        }
+
+        log::info!("Packet forwarding stopped ({} queued, {} dropped)", queued, dropped);
+    }
+
+    /// Work out whether a frame belongs to a redirected device and which way
+    /// it is heading
+    async fn route(&self, frame: &[u8]) -> Option<Route> {
+        let ethernet = EthernetPacket::new(frame)?;
+        if ethernet.get_destination() != self.our_mac || ethernet.get_ethertype() != EtherTypes::Ipv4 {
+            return None;
+        }
+
+        let ipv4 = Ipv4Packet::new(ethernet.payload())?;
+        let (source_ip, dest_ip) = (ipv4.get_source(), ipv4.get_destination());
+        if dest_ip == self.our_ip {
+            return None;
+        }
+
+        let source_mac = ethernet.get_source();
+        let spoofs = self.spoofs.lock().await;
+
+        // Upload: device -> gateway. Matched by MAC, so a device that has
+        // changed address is still forwarded while its redirect catches up.
+        if let Some(spoof) = spoofs.get(&source_mac.to_string()).filter(|s| s.active && s.forward) {
+            let route = Route {
+                device_ip: spoof.target_ip,
+                device_mac: spoof.target_mac.clone(),
+                direction: Direction::Upload,
+                next_hop: spoof.gateway_mac.parse().ok()?,
+            };
+            drop(spoofs);
+
+            if route.device_ip != source_ip && !source_ip.is_unspecified() {
+                self.sightings.lock().await.insert(source_mac.to_string(), source_ip);
+            }
+            return Some(route);
+        }
+
+        // Download: gateway -> device
+        if let Some(spoof) = spoofs.values().find(|s| s.active && s.forward && s.target_ip == dest_ip) {
+            let gateway_mac: MacAddr = spoof.gateway_mac.parse().ok()?;
+            if source_mac == gateway_mac {
+                return Some(Route {
+                    device_ip: dest_ip,
+                    device_mac: spoof.target_mac.clone(),
+                    direction: Direction::Download,
+                    next_hop: spoof.target_mac.parse().ok()?,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Check an ARP frame for the gateway announcing a MAC other than the
+    /// one we forward to, or a redirected device announcing a new address.
+    /// Returns false if the frame is not ARP.
+    async fn inspect_arp(&self, frame: &[u8]) -> bool {
+        let Some((sender_ip, sender_mac)) = arp_sender(frame) else {
+            return false;
+        };
+        let sender_mac = MacAddr::from(sender_mac);
+        // Our own poison claims other hosts' addresses
+        if sender_mac == self.our_mac || sender_ip.is_unspecified() {
+            return true;
+        }
+
+        let gateway_contradicted = *self.gateway_ip.lock().await == Some(sender_ip)
+            && self.gateway_mac.lock().await.is_some_and(|known| MacAddr::from(known) != sender_mac);
+        let moved = {
+            let spoofs = self.spoofs.lock().await;
+            spoofs.get(&sender_mac.to_string()).is_some_and(|s| s.active && s.target_ip != sender_ip)
+        };
+
+        // The spoofing loop re-probes the gateway and moves the device
+        if gateway_contradicted {
+            *self.gateway_suspect.lock().await = true;
+        }
+        if moved {
+            self.sightings.lock().await.insert(sender_mac.to_string(), sender_ip);
+        }
+        true
+    }
+
+    async fn has_routes(&self) -> bool {
+        let spoofs = self.spoofs.lock().await;
+        spoofs.values().any(|s| s.active && s.forward)
+    }
+}
+
+/// Send queued frames as they fall due. Runs until the receive loop has
+/// stopped and everything it queued has gone out.
+async fn send_queued(
+    mut tx: Box<dyn datalink::DataLinkSender>,
+    mut queue: mpsc::UnboundedReceiver<QueuedFrame>,
+    bandwidth: Arc<Mutex<BandwidthController>>,
+) {
+    let mut pending: BinaryHeap<Reverse<QueuedFrame>> = BinaryHeap::new();
+    let mut closed = false;
+
+    loop {
+        let next_due = pending.peek().map(|Reverse(f)| f.send_at);
+        if closed && next_due.is_none() {
+            break;
+        }
+
+        tokio::select! {
+            received = queue.recv(), if !closed => match received {
+                Some(frame) => pending.push(Reverse(frame)),
+                None => closed = true,
+            },
+            _ = sleep_until(next_due.unwrap_or_else(Instant::now).into()), if next_due.is_some() => {
+                let now = Instant::now();
+                while pending.peek().is_some_and(|Reverse(f)| f.send_at <= now) {
+                    let Some(Reverse(queued)) = pending.pop() else { break };
+
+                    match tx.send_to(&queued.frame, None) {
+                        Some(Ok(())) => {}
+                        Some(Err(e)) => log::warn!("Failed to forward packet for {}: {}", queued.device_ip, e),
+                        None => log::warn!("Failed to forward packet for {}", queued.device_ip),
+                    }
+
+                    let bandwidth = bandwidth.lock().await;
+                    bandwidth.packet_sent(&queued.device_mac, queued.direction, queued.frame.len()).await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use anyhow::Result;
+use pnet::datalink::{self, Channel, Config, NetworkInterface};
+use pnet::packet::arp::{ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket};
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::Packet;
+use pnet::util::MacAddr;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use crate::utils::safety::device_id_for_mac;
+use crate::modules::arp_controller::ArpSpoof;
+use crate::modules::database::{Database, EventType};
+use crate::modules::transmitter::Transmitter;
+
+/// How often the gateway is re-probed while devices are redirected
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// How long to wait for the gateway to answer a probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+const PROBE_READ_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Ask the gateway for its MAC address with an ARP request and wait for the
+/// reply. Unlike the system ARP cache this cannot return a stale entry.
+pub async fn probe_gateway_mac(
+    interface: &NetworkInterface,
+    transmitter: &Transmitter,
+    our_mac: [u8; 6],
+    our_ip: Ipv4Addr,
+    gateway_ip: Ipv4Addr,
+) -> Result<[u8; 6]> {
+    // Listen before asking so the reply cannot be missed
+    let config = Config {
+        read_timeout: Some(PROBE_READ_TIMEOUT),
+        ..Default::default()
+    };
+    let mut rx = match datalink::channel(interface, config) {
+        Ok(Channel::Ethernet(_, rx)) => rx,
+        Ok(_) => return Err(anyhow::anyhow!("Unhandled channel type")),
+        Err(e) => return Err(anyhow::anyhow!("Failed to create channel: {}", e)),
+    };
+
+    transmitter.send(vec![create_arp_request(our_mac, our_ip, gateway_ip)]).await?;
+
+    tokio::task::spawn_blocking(move || {
+        let deadline = Instant::now() + PROBE_TIMEOUT;
+        while Instant::now() < deadline {
+            match rx.next() {
+                Ok(frame) => {
+                    if let Some(mac) = arp_sender_mac(frame, gateway_ip) {
+                        return Ok(mac);
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {}
+                Err(e) => return Err(anyhow::anyhow!("Error receiving ARP reply: {}", e)),
+            }
+        }
+        Err(anyhow::anyhow!("Gateway {} did not answer an ARP request", gateway_ip))
+    })
+    .await?
+}
+
+/// What is needed to probe the gateway, taken from the controller so the
+/// probe can run without holding it
+#[derive(Clone)]
+pub struct GatewayProbe {
+    pub interface: NetworkInterface,
+    pub transmitter: Transmitter,
+    pub our_mac: [u8; 6],
+    pub our_ip: Ipv4Addr,
+    pub gateway_ip: Ipv4Addr,
+}
+
+impl GatewayProbe {
+    pub async fn run(&self) -> Result<[u8; 6]> {
+        probe_gateway_mac(&self.interface, &self.transmitter, self.our_mac, self.our_ip, self.gateway_ip).await
+    }
+}
+
+/// The hardware address `ip` claims in an ARP frame, if the frame is an
+/// ARP packet sent by `ip`
+pub fn arp_sender_mac(frame: &[u8], ip: Ipv4Addr) -> Option<[u8; 6]> {
+    arp_sender(frame).filter(|(sender_ip, _)| *sender_ip == ip).map(|(_, mac)| mac)
+}
+
+/// The address pair the sender of an ARP frame announces
+pub fn arp_sender(frame: &[u8]) -> Option<(Ipv4Addr, [u8; 6])> {
+    let ethernet = EthernetPacket::new(frame)?;
+    if ethernet.get_ethertype() != EtherTypes::Arp {
+        return None;
+    }
+
+    let arp = ArpPacket::new(ethernet.payload())?;
+    Some((arp.get_sender_proto_addr(), arp.get_sender_hw_addr().octets()))
+}
+
+pub(crate) fn create_arp_request(our_mac: [u8; 6], our_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Vec<u8> {
+    let mut buffer = vec![0u8; 42];
+    let mut ethernet_packet = MutableEthernetPacket::new(&mut buffer).unwrap();
+
+    ethernet_packet.set_destination([0xff; 6].into());
+    ethernet_packet.set_source(our_mac.into());
+    ethernet_packet.set_ethertype(EtherTypes::Arp);
+
+    let mut arp_buffer = vec![0u8; 28];
+    let mut arp_packet = MutableArpPacket::new(&mut arp_buffer).unwrap();
+
+    arp_packet.set_hardware_type(ArpHardwareTypes::Ethernet);
+    arp_packet.set_protocol_type(EtherTypes::Ipv4);
+    arp_packet.set_hw_addr_len(6);
+    arp_packet.set_proto_addr_len(4);
+    arp_packet.set_operation(ArpOperations::Request);
+    arp_packet.set_sender_hw_addr(our_mac.into());
+    arp_packet.set_sender_proto_addr(our_ip);
+    arp_packet.set_target_hw_addr([0; 6].into());
+    arp_packet.set_target_proto_addr(target_ip);
+
+    ethernet_packet.set_payload(arp_packet.packet());
+    buffer
+}
+
+/// Re-checks the gateway's MAC address while devices are redirected and
+/// moves every spoof entry over when it changes, e.g. after the router was
+/// replaced or failed over to a standby.
+pub struct GatewayMonitor {
+    pub interface: NetworkInterface,
+    pub transmitter: Transmitter,
+    pub our_mac: [u8; 6],
+    pub our_ip: Ipv4Addr,
+    pub gateway_ip: Ipv4Addr,
+    pub gateway_mac: Arc<Mutex<Option<[u8; 6]>>>,
+    pub suspect: Arc<Mutex<bool>>,  // Set when traffic contradicts the known MAC
+    pub spoofs: Arc<Mutex<HashMap<String, ArpSpoof>>>,
+    pub journal: Option<Arc<Mutex<Database>>>,
+    pub last_check: Option<Instant>,
+    pub pending: Option<PendingProbe>,
+}
+
+/// A probe running in the background, so waiting for the gateway's reply
+/// never holds up the spoofing loop
+pub struct PendingProbe {
+    handle: JoinHandle<Result<[u8; 6]>>,
+    reason: &'static str,
+}
+
+impl GatewayMonitor {
+    /// Start a probe of the gateway if the check interval has passed or the
+    /// current MAC has been contradicted since the last probe. The probe's
+    /// result is applied on a later check, once it has finished.
+    pub async fn check(&mut self) {
+        match self.pending.take() {
+            Some(probe) if !probe.handle.is_finished() => {
+                self.pending = Some(probe);
+                return;
+            }
+            Some(probe) => {
+                self.finish(probe).await;
+                return;
+            }
+            None => {}
+        }
+
+        let suspect = std::mem::take(&mut *self.suspect.lock().await);
+        let due = self.last_check.is_none_or(|t| t.elapsed() >= CHECK_INTERVAL);
+        if !suspect && !due {
+            return;
+        }
+        self.last_check = Some(Instant::now());
+
+        if self.spoofs.lock().await.is_empty() || self.gateway_mac.lock().await.is_none() {
+            return;
+        }
+
+        let probe = GatewayProbe {
+            interface: self.interface.clone(),
+            transmitter: self.transmitter.clone(),
+            our_mac: self.our_mac,
+            our_ip: self.our_ip,
+            gateway_ip: self.gateway_ip,
+        };
+        self.pending = Some(PendingProbe {
+            handle: tokio::spawn(async move { probe.run().await }),
+            reason: if suspect { "contradicted" } else { "periodic" },
+        });
+    }
+
+    /// Move the spoofs over if a finished probe found a new gateway MAC
+    async fn finish(&self, probe: PendingProbe) {
+        let mac = match probe.handle.await {
+            Ok(Ok(mac)) => MacAddr::from(mac).to_string(),
+            Ok(Err(e)) => {
+                log::warn!("Could not verify gateway MAC: {}", e);
+                return;
+            }
+            Err(e) => {
+                log::warn!("Gateway probe failed: {}", e);
+                return;
+            }
+        };
+        let Some(old_mac) = *self.gateway_mac.lock().await else {
+            return;
+        };
+        let old_mac = MacAddr::from(old_mac).to_string();
+        if mac == old_mac {
+            return;
+        }
+
+        let gateway_ip = self.gateway_ip;
+        log::warn!("Gateway {} changed MAC from {} to {}", gateway_ip, old_mac, mac);
+        self.apply_change(gateway_ip, &old_mac, &mac, probe.reason).await;
+    }
+
+    async fn apply_change(&self, gateway_ip: Ipv4Addr, old_mac: &str, new_mac: &str, reason: &str) {
+        if let Ok(octets) = new_mac.parse::<MacAddr>().map(|m| m.octets()) {
+            *self.gateway_mac.lock().await = Some(octets);
+        }
+
+        let updated: Vec<ArpSpoof> = {
+            let mut spoofs = self.spoofs.lock().await;
+            spoofs.values_mut()
+                .filter(|s| s.gateway_ip == gateway_ip)
+                .map(|s| {
+                    s.gateway_mac = new_mac.to_string();
+                    s.clone()
+                })
+                .collect()
+        };
+
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        let database = journal.lock().await;
+        for spoof in &updated {
+            if let Err(e) = database.add_cut_journal_entry(&spoof.journal_entry()).await {
+                log::warn!("Failed to update journal entry for {}: {}", spoof.target_ip, e);
+            }
+        }
+
+        // The old gateway is in the inventory from earlier scans; the new one
+        // usually is not yet, and events must belong to a known device
+        let device_id = device_id_for_mac(old_mac);
+        let details = serde_json::json!({
+            "ip": gateway_ip.to_string(),
+            "oldMac": old_mac,
+            "newMac": new_mac,
+            "reason": reason,
+            "devices": updated.len(),
+        });
+        if let Err(e) = database.record_event(EventType::GatewayChanged, &device_id, Some(details.to_string())).await {
+            log::warn!("Failed to record gateway_changed event: {}", e);
+        }
+    }
+}
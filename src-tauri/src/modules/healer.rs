@@ -0,0 +1,159 @@
+use pnet::packet::ethernet::{EtherTypes, MutableEthernetPacket};
+use pnet::packet::icmp::echo_request::{IcmpCodes, MutableEchoRequestPacket};
+use pnet::packet::icmp::{self, IcmpPacket, IcmpTypes};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ipv4::{self, MutableIpv4Packet};
+use pnet::packet::Packet;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+use crate::utils::safety::device_id_for_mac;
+use crate::modules::arp_controller::{ArpController, ArpSpoof};
+use crate::modules::database::{Database, EventType};
+use crate::modules::gateway::create_arp_request;
+use crate::modules::transmitter::Transmitter;
+use crate::modules::verifier::{VerificationStatus, VerifiedAction, Verifier};
+
+/// How long a restored device has to show it is back on the gateway
+const HEAL_TIMEOUT: Duration = Duration::from_secs(15);
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(4);
+
+/// Follows up a restore until the device's traffic is seen going to the
+/// real gateway. The device is pinged from the gateway's address, so its
+/// reply takes the same path as its internet traffic and the verifier can
+/// tell which MAC it is sent to. While the reply still comes to us the
+/// restore packets are sent again.
+#[derive(Clone)]
+pub struct Healer {
+    pub transmitter: Transmitter,
+    pub verifier: Verifier,
+    pub our_mac: [u8; 6],
+    pub our_ip: Ipv4Addr,
+    pub journal: Option<Arc<Mutex<Database>>>,
+}
+
+impl Healer {
+    /// Start watching a device that was just sent restore packets
+    pub async fn heal(&self, spoof: &ArpSpoof) {
+        // The restore was only logged in dry-run mode; probing would add
+        // frames for a device that was never cut
+        if self.transmitter.is_dry_run().await {
+            return;
+        }
+        if let Err(e) = self.verifier.watch(&spoof.target_mac, &spoof.gateway_mac, VerifiedAction::Restore).await {
+            log::warn!("Cannot verify restore of {}: {}", spoof.target_ip, e);
+            return;
+        }
+        tokio::spawn(self.clone().run(spoof.clone()));
+    }
+
+    async fn run(self, spoof: ArpSpoof) {
+        let Ok(target_mac) = spoof.target_mac.parse::<pnet::util::MacAddr>() else {
+            return;
+        };
+        let target_mac = target_mac.octets();
+
+        let started = Instant::now();
+        let mut backoff = INITIAL_BACKOFF;
+        let mut sequence = 0u16;
+
+        loop {
+            sequence = sequence.wrapping_add(1);
+            let probes = vec![
+                create_arp_request(self.our_mac, self.our_ip, spoof.target_ip),
+                create_echo_request(self.our_mac, target_mac, spoof.gateway_ip, spoof.target_ip, sequence),
+            ];
+            if let Err(e) = self.transmitter.send(probes).await {
+                log::debug!("Failed to probe {} after restore: {}", spoof.target_ip, e);
+            }
+
+            sleep(backoff).await;
+
+            let verification = self.verifier.get_verifications().await
+                .remove(&spoof.target_mac);
+            match verification {
+                // Cut again, or the watch was replaced some other way
+                Some(v) if v.action != VerifiedAction::Restore => return,
+                None => return,
+                Some(v) if v.status == VerificationStatus::Confirmed => {
+                    log::info!("Restore of {} confirmed after {:?}", spoof.target_ip, started.elapsed());
+                    return;
+                }
+                Some(v) if v.status == VerificationStatus::Failing => {
+                    log::info!("{} still sends to us after restore, correcting again", spoof.target_ip);
+                    if let Err(e) = ArpController::send_restore_packets(
+                        &self.transmitter, spoof.target_ip, &spoof.target_mac, spoof.gateway_ip, &spoof.gateway_mac,
+                    ).await {
+                        log::warn!("Failed to resend restore packets to {}: {}", spoof.target_ip, e);
+                    }
+                }
+                Some(_) => {}
+            }
+
+            if started.elapsed() >= HEAL_TIMEOUT {
+                break;
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+
+        log::warn!("Could not confirm that {} ({}) was restored", spoof.target_ip, spoof.target_mac);
+        self.verifier.time_out_restore(&spoof.target_mac).await;
+
+        if let Some(journal) = &self.journal {
+            let device_id = device_id_for_mac(&spoof.target_mac);
+            let details = serde_json::json!({
+                "ip": spoof.target_ip.to_string(),
+                "timeoutSecs": HEAL_TIMEOUT.as_secs(),
+            });
+            let database = journal.lock().await;
+            if let Err(e) = database.record_event(EventType::RestoreUnconfirmed, &device_id, Some(details.to_string())).await {
+                log::warn!("Failed to record restore_unconfirmed event for {}: {}", device_id, e);
+            }
+        }
+    }
+}
+
+/// An ICMP echo request to `target_ip` that appears to come from `source_ip`
+fn create_echo_request(
+    our_mac: [u8; 6],
+    target_mac: [u8; 6],
+    source_ip: Ipv4Addr,
+    target_ip: Ipv4Addr,
+    sequence: u16,
+) -> Vec<u8> {
+    const ICMP_LEN: usize = 8;
+    const IPV4_LEN: usize = 20 + ICMP_LEN;
+    let mut buffer = vec![0u8; 14 + IPV4_LEN];
+
+    let mut icmp_buffer = [0u8; ICMP_LEN];
+    let mut echo = MutableEchoRequestPacket::new(&mut icmp_buffer).unwrap();
+    echo.set_icmp_type(IcmpTypes::EchoRequest);
+    echo.set_icmp_code(IcmpCodes::NoCode);
+    echo.set_identifier(0x4e53);
+    echo.set_sequence_number(sequence);
+    let checksum = icmp::checksum(&IcmpPacket::new(echo.packet()).unwrap());
+    echo.set_checksum(checksum);
+
+    let mut ipv4_buffer = [0u8; IPV4_LEN];
+    let mut ipv4_packet = MutableIpv4Packet::new(&mut ipv4_buffer).unwrap();
+    ipv4_packet.set_version(4);
+    ipv4_packet.set_header_length(5);
+    ipv4_packet.set_total_length(IPV4_LEN as u16);
+    ipv4_packet.set_ttl(64);
+    ipv4_packet.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
+    ipv4_packet.set_source(source_ip);
+    ipv4_packet.set_destination(target_ip);
+    ipv4_packet.set_payload(&icmp_buffer);
+    let checksum = ipv4::checksum(&ipv4_packet.to_immutable());
+    ipv4_packet.set_checksum(checksum);
+
+    let mut ethernet_packet = MutableEthernetPacket::new(&mut buffer).unwrap();
+    ethernet_packet.set_destination(target_mac.into());
+    ethernet_packet.set_source(our_mac.into());
+    ethernet_packet.set_ethertype(EtherTypes::Ipv4);
+    ethernet_packet.set_payload(&ipv4_buffer);
+    buffer
+}
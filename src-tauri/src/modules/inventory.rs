@@ -0,0 +1,1025 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use crate::modules::database::{
+    is_sensitive_setting, Database, DeviceQuota, DeviceRecord, GroupImport, ImportChanges, ProtectedDevice,
+    ProtectionLevel, QuotaAction, QuotaPeriod, Schedule, ScheduleAction, ScheduleImport, ScheduleTarget,
+};
+use crate::utils::safety::{device_id_for_mac, normalize_mac};
+
+/// Version of the export format written by this build. Imports of newer
+/// versions are refused; older versions are upgraded on read.
+pub const EXPORT_FORMAT_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    /// Guess the format from a file name, defaulting to JSON
+    pub fn from_path(path: &str) -> Self {
+        if path.to_lowercase().ends_with(".csv") {
+            ExportFormat::Csv
+        } else {
+            ExportFormat::Json
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictStrategy {
+    #[default]
+    KeepLocal,
+    UseImported,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedDevice {
+    pub mac: String,
+    pub ip: String,
+    pub hostname: Option<String>,
+    pub custom_name: Option<String>,
+    pub manufacturer: Option<String>,
+    pub device_type: String,
+    pub first_seen: i64,
+    pub last_seen: i64,
+    pub bandwidth_limit: Option<f64>,
+    pub notes: Option<String>,
+    pub icon: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedQuota {
+    pub mac: String,
+    pub period: QuotaPeriod,
+    pub limit_bytes: i64,
+    pub action: QuotaAction,
+    pub limit_mbps: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedGroup {
+    pub name: String,
+    pub color: Option<String>,
+    pub protected: bool,
+    pub members: Vec<String>,  // MACs
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedProtectedDevice {
+    pub mac: String,
+    pub label: Option<String>,
+    pub level: ProtectionLevel,
+}
+
+/// A schedule with its target named by MAC or group name, as device and
+/// group IDs differ between installs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedSchedule {
+    pub name: String,
+    pub target: ScheduleTarget,
+    pub mac: Option<String>,    // Device schedules
+    pub group: Option<String>,  // Group schedules
+    pub action: ScheduleAction,
+    pub limit_mbps: Option<f64>,
+    pub days: Vec<Weekday>,
+    pub start_minute: u32,
+    pub end_minute: u32,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryExport {
+    pub format_version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub devices: Vec<ExportedDevice>,
+    pub settings: BTreeMap<String, String>,
+    pub quotas: Vec<ExportedQuota>,
+    // Added in version 2
+    #[serde(default)]
+    pub groups: Vec<ExportedGroup>,
+    #[serde(default)]
+    pub protected_devices: Vec<ExportedProtectedDevice>,
+    #[serde(default)]
+    pub schedules: Vec<ExportedSchedule>,
+}
+
+/// One line of the CSV export. The `record` column says which of the other
+/// columns are filled: `meta` and `setting` rows use key/value, `device`
+/// rows use the device and quota columns, and `group`, `protected` and
+/// `schedule` rows hold the entry as JSON in value.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct CsvRow {
+    record: String,
+    mac: Option<String>,
+    ip: Option<String>,
+    hostname: Option<String>,
+    custom_name: Option<String>,
+    manufacturer: Option<String>,
+    device_type: Option<String>,
+    first_seen: Option<i64>,
+    last_seen: Option<i64>,
+    bandwidth_limit: Option<f64>,
+    notes: Option<String>,
+    icon: Option<String>,
+    quota_period: Option<QuotaPeriod>,
+    quota_limit_bytes: Option<i64>,
+    quota_action: Option<QuotaAction>,
+    quota_limit_mbps: Option<f64>,
+    key: Option<String>,
+    value: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportConflict {
+    pub mac: Option<String>,
+    pub field: String,
+    pub local_value: String,
+    pub imported_value: String,
+    pub kept: ConflictStrategy,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub format_version: u32,
+    pub devices_added: usize,
+    pub devices_updated: usize,
+    pub settings_applied: usize,
+    pub quotas_applied: usize,
+    pub groups_applied: usize,
+    pub protected_applied: usize,
+    pub schedules_applied: usize,
+    pub conflicts: Vec<ImportConflict>,
+    pub skipped: Vec<String>,
+}
+
+/// Collect the device table, settings, quotas, groups, protected devices
+/// and schedules into an export
+pub async fn build_export(database: &Database) -> Result<InventoryExport> {
+    let records = database.get_all_devices().await?;
+    let quotas = database.get_quotas().await?;
+    let settings = database.get_all_settings().await?;
+    let groups = database.get_groups().await?;
+    let protected_devices = database.get_protected_devices().await?;
+    let schedules = database.get_schedules().await?;
+
+    let mac_of = |device_id: &str| records.iter().find(|r| r.id == device_id).map(|r| r.mac.clone());
+
+    let devices = records
+        .iter()
+        .map(|record| ExportedDevice {
+            mac: record.mac.clone(),
+            ip: record.ip.clone(),
+            hostname: record.hostname.clone(),
+            custom_name: record.custom_name.clone(),
+            manufacturer: record.manufacturer.clone(),
+            device_type: record.device_type.clone(),
+            first_seen: record.first_seen,
+            last_seen: record.last_seen,
+            bandwidth_limit: record.bandwidth_limit,
+            notes: record.notes.clone(),
+            icon: record.icon.clone(),
+        })
+        .collect();
+
+    let quotas = quotas
+        .into_iter()
+        .filter_map(|quota| {
+            let mac = mac_of(&quota.device_id)?;
+            Some(ExportedQuota {
+                mac,
+                period: quota.period,
+                limit_bytes: quota.limit_bytes,
+                action: quota.action,
+                limit_mbps: quota.limit_mbps,
+            })
+        })
+        .collect();
+
+    let schedules = schedules
+        .into_iter()
+        .filter_map(|schedule| {
+            let (mac, group) = match schedule.target {
+                ScheduleTarget::Device => (Some(mac_of(&schedule.target_id)?), None),
+                ScheduleTarget::Group => {
+                    let group = groups.iter().find(|g| g.id.to_string() == schedule.target_id)?;
+                    (None, Some(group.name.clone()))
+                }
+            };
+            Some(ExportedSchedule {
+                name: schedule.name,
+                target: schedule.target,
+                mac,
+                group,
+                action: schedule.action,
+                limit_mbps: schedule.limit_mbps,
+                days: schedule.days,
+                start_minute: schedule.start_minute,
+                end_minute: schedule.end_minute,
+                enabled: schedule.enabled,
+            })
+        })
+        .collect();
+
+    let groups = groups
+        .into_iter()
+        .map(|group| ExportedGroup {
+            members: group.device_ids.iter().filter_map(|id| mac_of(id)).collect(),
+            name: group.name,
+            color: group.color,
+            protected: group.protected,
+        })
+        .collect();
+
+    let protected_devices = protected_devices
+        .into_iter()
+        .map(|device| ExportedProtectedDevice {
+            mac: device.mac,
+            label: device.label,
+            level: device.level,
+        })
+        .collect();
+
+    Ok(InventoryExport {
+        format_version: EXPORT_FORMAT_VERSION,
+        exported_at: Utc::now(),
+        devices,
+        // Credentials stay on this machine
+        settings: settings
+            .into_iter()
+            .filter(|(key, _)| !is_sensitive_setting(key))
+            .collect(),
+        quotas,
+        groups,
+        protected_devices,
+        schedules,
+    })
+}
+
+pub fn to_json(export: &InventoryExport) -> Result<String> {
+    Ok(serde_json::to_string_pretty(export)?)
+}
+
+pub fn to_csv(export: &InventoryExport) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    writer.serialize(CsvRow {
+        record: "meta".to_string(),
+        key: Some("format_version".to_string()),
+        value: Some(export.format_version.to_string()),
+        ..Default::default()
+    })?;
+    writer.serialize(CsvRow {
+        record: "meta".to_string(),
+        key: Some("exported_at".to_string()),
+        value: Some(export.exported_at.to_rfc3339()),
+        ..Default::default()
+    })?;
+
+    for device in &export.devices {
+        let quota = export.quotas.iter().find(|q| q.mac == device.mac);
+        writer.serialize(CsvRow {
+            record: "device".to_string(),
+            mac: Some(device.mac.clone()),
+            ip: Some(device.ip.clone()),
+            hostname: device.hostname.clone(),
+            custom_name: device.custom_name.clone(),
+            manufacturer: device.manufacturer.clone(),
+            device_type: Some(device.device_type.clone()),
+            first_seen: Some(device.first_seen),
+            last_seen: Some(device.last_seen),
+            bandwidth_limit: device.bandwidth_limit,
+            notes: device.notes.clone(),
+            icon: device.icon.clone(),
+            quota_period: quota.map(|q| q.period),
+            quota_limit_bytes: quota.map(|q| q.limit_bytes),
+            quota_action: quota.map(|q| q.action),
+            quota_limit_mbps: quota.and_then(|q| q.limit_mbps),
+            ..Default::default()
+        })?;
+    }
+
+    for (key, value) in &export.settings {
+        writer.serialize(CsvRow {
+            record: "setting".to_string(),
+            key: Some(key.clone()),
+            value: Some(value.clone()),
+            ..Default::default()
+        })?;
+    }
+
+    for group in &export.groups {
+        writer.serialize(json_row("group", group)?)?;
+    }
+    for device in &export.protected_devices {
+        writer.serialize(json_row("protected", device)?)?;
+    }
+    for schedule in &export.schedules {
+        writer.serialize(json_row("schedule", schedule)?)?;
+    }
+
+    let bytes = writer.into_inner().map_err(|e| anyhow::anyhow!("Failed to write CSV: {}", e))?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+fn json_row<T: Serialize>(record: &str, entry: &T) -> Result<CsvRow> {
+    Ok(CsvRow {
+        record: record.to_string(),
+        value: Some(serde_json::to_string(entry)?),
+        ..Default::default()
+    })
+}
+
+fn from_json_row<T: serde::de::DeserializeOwned>(row: CsvRow) -> Result<T> {
+    let value = row.value.ok_or_else(|| anyhow::anyhow!("{} row without a value", row.record))?;
+    Ok(serde_json::from_str(&value)?)
+}
+
+pub fn parse(contents: &str, format: ExportFormat) -> Result<InventoryExport> {
+    let export = match format {
+        ExportFormat::Json => serde_json::from_str::<InventoryExport>(contents)?,
+        ExportFormat::Csv => from_csv(contents)?,
+    };
+
+    if export.format_version == 0 || export.format_version > EXPORT_FORMAT_VERSION {
+        return Err(anyhow::anyhow!(
+            "Unsupported export format version {} (this build reads up to {})",
+            export.format_version, EXPORT_FORMAT_VERSION
+        ));
+    }
+
+    Ok(export)
+}
+
+fn from_csv(contents: &str) -> Result<InventoryExport> {
+    let mut reader = csv::Reader::from_reader(contents.as_bytes());
+    let mut export = InventoryExport {
+        format_version: 0,
+        exported_at: Utc::now(),
+        devices: Vec::new(),
+        settings: BTreeMap::new(),
+        quotas: Vec::new(),
+        groups: Vec::new(),
+        protected_devices: Vec::new(),
+        schedules: Vec::new(),
+    };
+
+    for row in reader.deserialize::<CsvRow>() {
+        let row = row?;
+        match row.record.as_str() {
+            "meta" => match (row.key.as_deref(), row.value) {
+                (Some("format_version"), Some(value)) => export.format_version = value.parse()?,
+                (Some("exported_at"), Some(value)) => {
+                    export.exported_at = DateTime::parse_from_rfc3339(&value)?.with_timezone(&Utc);
+                }
+                _ => {}
+            },
+            "setting" => {
+                if let (Some(key), Some(value)) = (row.key, row.value) {
+                    export.settings.insert(key, value);
+                }
+            }
+            "device" => {
+                let mac = row.mac.ok_or_else(|| anyhow::anyhow!("Device row without a MAC address"))?;
+
+                if let (Some(period), Some(limit_bytes), Some(action)) =
+                    (row.quota_period, row.quota_limit_bytes, row.quota_action) {
+                    export.quotas.push(ExportedQuota {
+                        mac: mac.clone(),
+                        period,
+                        limit_bytes,
+                        action,
+                        limit_mbps: row.quota_limit_mbps,
+                    });
+                }
+
+                export.devices.push(ExportedDevice {
+                    mac,
+                    ip: row.ip.unwrap_or_default(),
+                    hostname: row.hostname,
+                    custom_name: row.custom_name,
+                    manufacturer: row.manufacturer,
+                    device_type: row.device_type.unwrap_or_else(|| "unknown".to_string()),
+                    first_seen: row.first_seen.unwrap_or_default(),
+                    last_seen: row.last_seen.unwrap_or_default(),
+                    bandwidth_limit: row.bandwidth_limit,
+                    notes: row.notes,
+                    icon: row.icon,
+                });
+            }
+            "group" => export.groups.push(from_json_row(row)?),
+            "protected" => export.protected_devices.push(from_json_row(row)?),
+            "schedule" => export.schedules.push(from_json_row(row)?),
+            other => return Err(anyhow::anyhow!("Unknown CSV record type: {}", other)),
+        }
+    }
+
+    Ok(export)
+}
+
+/// Merge an import into the database by MAC address. Nothing is written
+/// until the whole import has been merged, and then in one transaction.
+/// Returns the report and any enforced quotas that were replaced, so the
+/// caller can lift their actions.
+pub async fn merge_into(
+    database: &Database,
+    export: InventoryExport,
+    strategy: ConflictStrategy,
+) -> Result<(ImportReport, Vec<DeviceQuota>)> {
+    let mut report = ImportReport {
+        format_version: export.format_version,
+        ..Default::default()
+    };
+    let mut changes = ImportChanges::default();
+    let mut replaced_quotas = Vec::new();
+    let use_imported = strategy == ConflictStrategy::UseImported;
+    // Device IDs of the imported devices by MAC, as new ones are not stored yet
+    let mut known = HashMap::new();
+
+    for device in export.devices {
+        let Some(mac) = normalize_mac(&device.mac) else {
+            report.skipped.push(format!("Invalid MAC address: {}", device.mac));
+            continue;
+        };
+
+        let record = match database.get_device_by_mac(&mac).await? {
+            None => {
+                report.devices_added += 1;
+                DeviceRecord {
+                    id: device_id_for_mac(&mac),
+                    // Stored the way the scanner reports MACs
+                    mac: mac.to_uppercase(),
+                    ip: device.ip,
+                    hostname: device.hostname,
+                    custom_name: device.custom_name,
+                    manufacturer: device.manufacturer,
+                    device_type: device.device_type,
+                    first_seen: device.first_seen,
+                    last_seen: device.last_seen,
+                    total_bytes: 0,
+                    is_blocked: false,
+                    bandwidth_limit: device.bandwidth_limit,
+                    notes: device.notes,
+                    icon: device.icon,
+                }
+            }
+            Some(local) => {
+                report.devices_updated += 1;
+                let mut merged = local.clone();
+
+                merged.hostname = local.hostname.or(device.hostname);
+                merged.manufacturer = local.manufacturer.or(device.manufacturer);
+                merged.first_seen = local.first_seen.min(device.first_seen);
+                merged.last_seen = local.last_seen.max(device.last_seen);
+
+                merged.custom_name = merge_text(&mut report, Some(&mac), "customName", local.custom_name, device.custom_name, strategy);
+                merged.notes = merge_text(&mut report, Some(&mac), "notes", local.notes, device.notes, strategy);
+                merged.icon = merge_text(&mut report, Some(&mac), "icon", local.icon, device.icon, strategy);
+
+                match (local.bandwidth_limit, device.bandwidth_limit) {
+                    (None, imported) => merged.bandwidth_limit = imported,
+                    (Some(local_limit), Some(imported)) if local_limit != imported => {
+                        report.conflicts.push(ImportConflict {
+                            mac: Some(mac.clone()),
+                            field: "bandwidthLimit".to_string(),
+                            local_value: local_limit.to_string(),
+                            imported_value: imported.to_string(),
+                            kept: strategy,
+                        });
+                        if use_imported {
+                            merged.bandwidth_limit = Some(imported);
+                        }
+                    }
+                    _ => {}
+                }
+
+                merged
+            }
+        };
+
+        known.insert(mac, record.id.clone());
+        changes.devices.push(record);
+    }
+
+    for quota in export.quotas {
+        let Some(mac) = normalize_mac(&quota.mac) else {
+            report.skipped.push(format!("Invalid MAC address: {}", quota.mac));
+            continue;
+        };
+        let Some(device_id) = device_id_of(database, &known, &mac).await? else {
+            report.skipped.push(format!("Quota for unknown device: {}", quota.mac));
+            continue;
+        };
+
+        let imported = DeviceQuota {
+            device_id: device_id.clone(),
+            period: quota.period,
+            limit_bytes: quota.limit_bytes,
+            action: quota.action,
+            limit_mbps: quota.limit_mbps,
+            cycle_start: quota.period.cycle_start(chrono::Local::now()),
+            used_bytes: 0,
+            enforced: false,
+            enforced_ip: None,
+        };
+
+        match database.get_quota(&device_id).await? {
+            None => {
+                changes.quotas.push(imported);
+                report.quotas_applied += 1;
+            }
+            Some(local) => {
+                let same = local.period == imported.period
+                    && local.limit_bytes == imported.limit_bytes
+                    && local.action == imported.action
+                    && local.limit_mbps == imported.limit_mbps;
+                if same {
+                    continue;
+                }
+
+                report.conflicts.push(ImportConflict {
+                    mac: Some(mac),
+                    field: "quota".to_string(),
+                    local_value: format!("{} bytes {} ({})", local.limit_bytes, local.period.as_str(), local.action.as_str()),
+                    imported_value: format!("{} bytes {} ({})", imported.limit_bytes, imported.period.as_str(), imported.action.as_str()),
+                    kept: strategy,
+                });
+
+                if use_imported {
+                    changes.quotas.push(imported);
+                    report.quotas_applied += 1;
+                    if local.enforced {
+                        replaced_quotas.push(local);
+                    }
+                }
+            }
+        }
+    }
+
+    for (key, value) in export.settings {
+        match database.get_setting(&key).await? {
+            None => {
+                changes.settings.push((key, value));
+                report.settings_applied += 1;
+            }
+            Some(local) if local == value => {}
+            Some(local) => {
+                report.conflicts.push(ImportConflict {
+                    mac: None,
+                    field: key.clone(),
+                    local_value: local,
+                    imported_value: value.clone(),
+                    kept: strategy,
+                });
+                if use_imported {
+                    changes.settings.push((key, value));
+                    report.settings_applied += 1;
+                }
+            }
+        }
+    }
+
+    let local_protected = database.get_protected_devices().await?;
+    for device in export.protected_devices {
+        let Some(mac) = normalize_mac(&device.mac) else {
+            report.skipped.push(format!("Invalid MAC address: {}", device.mac));
+            continue;
+        };
+        let imported = ProtectedDevice {
+            mac: mac.clone(),
+            label: device.label,
+            level: device.level,
+            created_at: Utc::now().timestamp(),
+        };
+
+        match local_protected.iter().find(|p| normalize_mac(&p.mac).as_deref() == Some(mac.as_str())) {
+            None => {
+                changes.protected.push(imported);
+                report.protected_applied += 1;
+            }
+            Some(local) if local.label == imported.label && local.level == imported.level => {}
+            Some(local) => {
+                report.conflicts.push(ImportConflict {
+                    mac: Some(mac),
+                    field: "protection".to_string(),
+                    local_value: describe_protection(local),
+                    imported_value: describe_protection(&imported),
+                    kept: strategy,
+                });
+                if use_imported {
+                    changes.protected.push(imported);
+                    report.protected_applied += 1;
+                }
+            }
+        }
+    }
+
+    let local_groups = database.get_groups().await?;
+    let imported_groups: Vec<String> = export.groups.iter().map(|g| g.name.clone()).collect();
+    for group in export.groups {
+        let mut device_ids = Vec::new();
+        for member in &group.members {
+            let device_id = match normalize_mac(member) {
+                Some(mac) => device_id_of(database, &known, &mac).await?,
+                None => None,
+            };
+            match device_id {
+                Some(device_id) => device_ids.push(device_id),
+                None => report.skipped.push(format!("Unknown member {} of group {}", member, group.name)),
+            }
+        }
+
+        let Some(local) = local_groups.iter().find(|g| g.name.eq_ignore_ascii_case(&group.name)) else {
+            changes.groups.push(GroupImport {
+                id: None,
+                name: group.name,
+                color: group.color,
+                protected: group.protected,
+                device_ids,
+            });
+            report.groups_applied += 1;
+            continue;
+        };
+
+        // Members are added, never removed
+        device_ids.retain(|id| !local.device_ids.contains(id));
+
+        let field = format!("groups.{}.color", local.name);
+        let color = merge_text(&mut report, None, &field, local.color.clone(), group.color, strategy);
+
+        let mut protected = local.protected;
+        if group.protected != local.protected {
+            report.conflicts.push(ImportConflict {
+                mac: None,
+                field: format!("groups.{}.protected", local.name),
+                local_value: local.protected.to_string(),
+                imported_value: group.protected.to_string(),
+                kept: strategy,
+            });
+            if use_imported {
+                protected = group.protected;
+            }
+        }
+
+        if device_ids.is_empty() && color == local.color && protected == local.protected {
+            continue;
+        }
+
+        changes.groups.push(GroupImport {
+            id: Some(local.id),
+            name: local.name.clone(),
+            color,
+            protected,
+            device_ids,
+        });
+        report.groups_applied += 1;
+    }
+
+    let local_schedules = database.get_schedules().await?;
+    for schedule in export.schedules {
+        let (target_id, group) = match (schedule.target, &schedule.mac, &schedule.group) {
+            (ScheduleTarget::Device, Some(mac), _) => {
+                let device_id = match normalize_mac(mac) {
+                    Some(mac) => device_id_of(database, &known, &mac).await?,
+                    None => None,
+                };
+                let Some(device_id) = device_id else {
+                    report.skipped.push(format!("Schedule {} for unknown device: {}", schedule.name, mac));
+                    continue;
+                };
+                (device_id, None)
+            }
+            (ScheduleTarget::Group, _, Some(group)) => {
+                let exists = imported_groups.iter().chain(local_groups.iter().map(|g| &g.name))
+                    .any(|name| name.eq_ignore_ascii_case(group));
+                if !exists {
+                    report.skipped.push(format!("Schedule {} for unknown group: {}", schedule.name, group));
+                    continue;
+                }
+                (String::new(), Some(group.clone()))
+            }
+            _ => {
+                report.skipped.push(format!("Schedule {} without a target", schedule.name));
+                continue;
+            }
+        };
+
+        let mut imported = Schedule {
+            id: 0,
+            name: schedule.name,
+            target: schedule.target,
+            target_id,
+            action: schedule.action,
+            limit_mbps: schedule.limit_mbps,
+            days: schedule.days,
+            start_minute: schedule.start_minute,
+            end_minute: schedule.end_minute,
+            enabled: schedule.enabled,
+            created_at: Utc::now().timestamp(),
+        };
+
+        if let Some(local) = local_schedules.iter().find(|s| s.name.eq_ignore_ascii_case(&imported.name)) {
+            let same_target = match &group {
+                Some(group) => local.target == ScheduleTarget::Group && local_groups
+                    .iter()
+                    .any(|g| g.name.eq_ignore_ascii_case(group) && g.id.to_string() == local.target_id),
+                None => local.target == ScheduleTarget::Device && local.target_id == imported.target_id,
+            };
+            let same = same_target
+                && local.action == imported.action
+                && local.limit_mbps == imported.limit_mbps
+                && local.days == imported.days
+                && local.start_minute == imported.start_minute
+                && local.end_minute == imported.end_minute
+                && local.enabled == imported.enabled;
+            if same {
+                continue;
+            }
+
+            report.conflicts.push(ImportConflict {
+                mac: None,
+                field: format!("schedules.{}", local.name),
+                local_value: describe_schedule(local),
+                imported_value: describe_schedule(&imported),
+                kept: strategy,
+            });
+            if !use_imported {
+                continue;
+            }
+            imported.id = local.id;
+            imported.created_at = local.created_at;
+        }
+
+        changes.schedules.push(ScheduleImport { schedule: imported, group });
+        report.schedules_applied += 1;
+    }
+
+    database.apply_import(&changes).await?;
+
+    Ok((report, replaced_quotas))
+}
+
+/// The device ID for `mac`, from the devices this import writes or else
+/// from the database
+async fn device_id_of(database: &Database, known: &HashMap<String, String>, mac: &str) -> Result<Option<String>> {
+    if let Some(device_id) = known.get(mac) {
+        return Ok(Some(device_id.clone()));
+    }
+    Ok(database.get_device_by_mac(mac).await?.map(|record| record.id))
+}
+
+fn describe_protection(device: &ProtectedDevice) -> String {
+    match &device.label {
+        Some(label) => format!("{} ({})", device.level.as_str(), label),
+        None => device.level.as_str().to_string(),
+    }
+}
+
+fn describe_schedule(schedule: &Schedule) -> String {
+    let days: Vec<String> = schedule.days.iter().map(|day| day.to_string()).collect();
+    format!(
+        "{} {} {:02}:{:02}-{:02}:{:02}{}",
+        schedule.action.as_str(), days.join(","),
+        schedule.start_minute / 60, schedule.start_minute % 60,
+        schedule.end_minute / 60, schedule.end_minute % 60,
+        if schedule.enabled { "" } else { " (disabled)" },
+    )
+}
+
+/// Merge an optional text field, recording a conflict when both sides
+/// hold different values
+fn merge_text(
+    report: &mut ImportReport,
+    mac: Option<&str>,
+    field: &str,
+    local: Option<String>,
+    imported: Option<String>,
+    strategy: ConflictStrategy,
+) -> Option<String> {
+    match (local, imported) {
+        (None, imported) => imported,
+        (Some(local), Some(imported)) if local != imported => {
+            report.conflicts.push(ImportConflict {
+                mac: mac.map(str::to_string),
+                field: field.to_string(),
+                local_value: local.clone(),
+                imported_value: imported.clone(),
+                kept: strategy,
+            });
+            match strategy {
+                ConflictStrategy::UseImported => Some(imported),
+                ConflictStrategy::KeepLocal => Some(local),
+            }
+        }
+        (local, _) => local,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const MAC: &str = "02:00:00:00:00:10";
+    const NAME: &str = "Kitchen speaker, \"left\"";
+    const NOTES: &str = "Bought 2023, \"refurbished\"\nSecond line";
+
+    struct TempDatabase {
+        database: Database,
+        path: PathBuf,
+    }
+
+    impl Drop for TempDatabase {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    async fn temp_database(name: &str) -> TempDatabase {
+        let path = std::env::temp_dir().join(format!("netsnip-inventory-{}-{}.db", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        let database = Database::new(&path).await.unwrap();
+        TempDatabase { database, path }
+    }
+
+    fn device(custom_name: &str, notes: &str) -> DeviceRecord {
+        DeviceRecord {
+            id: device_id_for_mac(MAC),
+            mac: MAC.to_uppercase(),
+            ip: "192.168.1.10".to_string(),
+            hostname: Some("speaker.lan".to_string()),
+            custom_name: Some(custom_name.to_string()),
+            manufacturer: None,
+            device_type: "speaker".to_string(),
+            first_seen: 1_700_000_000,
+            last_seen: 1_700_000_500,
+            total_bytes: 0,
+            is_blocked: false,
+            bandwidth_limit: None,
+            notes: Some(notes.to_string()),
+            icon: None,
+        }
+    }
+
+    fn quota(limit_bytes: i64, action: QuotaAction) -> DeviceQuota {
+        DeviceQuota {
+            device_id: device_id_for_mac(MAC),
+            period: QuotaPeriod::Daily,
+            limit_bytes,
+            action,
+            limit_mbps: None,
+            cycle_start: QuotaPeriod::Daily.cycle_start(Utc::now()),
+            used_bytes: 0,
+            enforced: false,
+            enforced_ip: None,
+        }
+    }
+
+    /// The exporting install: one named device with a quota, a setting,
+    /// a group and a protection entry
+    async fn source() -> TempDatabase {
+        let temp = temp_database("source").await;
+        let database = &temp.database;
+        database.upsert_device(&device(NAME, NOTES)).await.unwrap();
+        database.upsert_quota(&quota(5_000_000, QuotaAction::Cut)).await.unwrap();
+        database.set_setting("theme", "dark").await.unwrap();
+        let group = database.create_group("Kids, upstairs", Some("#ff0000")).await.unwrap();
+        database.add_group_members(group.id, &[device_id_for_mac(MAC)]).await.unwrap();
+        database.add_protected_device(MAC, Some("Office \"main\" printer"), ProtectionLevel::Confirm).await.unwrap();
+        temp
+    }
+
+    /// The importing install, which already knows the device by other
+    /// names and has its own quota and setting
+    async fn target(name: &str) -> TempDatabase {
+        let temp = temp_database(name).await;
+        let database = &temp.database;
+        database.upsert_device(&device("Living room", "Local notes")).await.unwrap();
+        database.upsert_quota(&quota(1_000_000, QuotaAction::Notify)).await.unwrap();
+        database.set_setting("theme", "light").await.unwrap();
+        temp
+    }
+
+    async fn round_trip(export: &InventoryExport, format: ExportFormat) -> InventoryExport {
+        let contents = match format {
+            ExportFormat::Json => to_json(export).unwrap(),
+            ExportFormat::Csv => to_csv(export).unwrap(),
+        };
+        parse(&contents, format).unwrap()
+    }
+
+    #[tokio::test]
+    async fn export_imports_into_an_empty_database_in_both_formats() {
+        let source = source().await;
+        let export = build_export(&source.database).await.unwrap();
+
+        for format in [ExportFormat::Json, ExportFormat::Csv] {
+            let name = format!("empty-{:?}", format);
+            let target = temp_database(&name).await;
+            let imported = round_trip(&export, format).await;
+            let (report, replaced) = merge_into(&target.database, imported, ConflictStrategy::KeepLocal).await.unwrap();
+
+            assert_eq!(report.devices_added, 1, "{:?}", format);
+            assert!(report.conflicts.is_empty(), "{:?}", format);
+            assert!(report.skipped.is_empty(), "{:?}", format);
+            assert!(replaced.is_empty());
+
+            let device = target.database.get_device_by_mac(MAC).await.unwrap().unwrap();
+            assert_eq!(device.custom_name.as_deref(), Some(NAME));
+            assert_eq!(device.notes.as_deref(), Some(NOTES));
+            assert_eq!(device.hostname.as_deref(), Some("speaker.lan"));
+
+            let quota = target.database.get_quota(&device.id).await.unwrap().unwrap();
+            assert_eq!((quota.limit_bytes, quota.action), (5_000_000, QuotaAction::Cut));
+            assert_eq!(target.database.get_setting("theme").await.unwrap().as_deref(), Some("dark"));
+
+            let groups = target.database.get_groups().await.unwrap();
+            assert_eq!(groups[0].name, "Kids, upstairs");
+            assert_eq!(groups[0].device_ids, vec![device.id.clone()]);
+            let protected = target.database.get_protected_devices().await.unwrap();
+            assert_eq!(protected[0].label.as_deref(), Some("Office \"main\" printer"));
+        }
+    }
+
+    #[tokio::test]
+    async fn keep_local_reports_conflicts_and_changes_nothing() {
+        let source = source().await;
+        let export = build_export(&source.database).await.unwrap();
+        let target = target("keep-local").await;
+
+        let (report, _) = merge_into(&target.database, export, ConflictStrategy::KeepLocal).await.unwrap();
+
+        let mut fields: Vec<_> = report.conflicts.iter().map(|c| c.field.as_str()).collect();
+        fields.sort_unstable();
+        assert_eq!(fields, ["customName", "notes", "quota", "theme"]);
+        assert!(report.conflicts.iter().all(|c| c.kept == ConflictStrategy::KeepLocal));
+        assert_eq!(report.devices_updated, 1);
+
+        let device = target.database.get_device_by_mac(MAC).await.unwrap().unwrap();
+        assert_eq!(device.custom_name.as_deref(), Some("Living room"));
+        assert_eq!(device.notes.as_deref(), Some("Local notes"));
+        let quota = target.database.get_quota(&device.id).await.unwrap().unwrap();
+        assert_eq!((quota.limit_bytes, quota.action), (1_000_000, QuotaAction::Notify));
+        assert_eq!(target.database.get_setting("theme").await.unwrap().as_deref(), Some("light"));
+        // Entries the target did not have are still added
+        assert_eq!(target.database.get_groups().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn use_imported_overwrites_conflicts_and_returns_replaced_quotas() {
+        let source = source().await;
+        let export = build_export(&source.database).await.unwrap();
+        let target = target("use-imported").await;
+        let mut enforced = quota(1_000_000, QuotaAction::Notify);
+        enforced.enforced = true;
+        target.database.upsert_quota(&enforced).await.unwrap();
+
+        let (report, replaced) = merge_into(&target.database, export, ConflictStrategy::UseImported).await.unwrap();
+
+        assert_eq!(report.conflicts.len(), 4);
+        assert!(report.conflicts.iter().all(|c| c.kept == ConflictStrategy::UseImported));
+        assert_eq!(replaced.len(), 1);
+        assert_eq!(replaced[0].limit_bytes, 1_000_000);
+
+        let device = target.database.get_device_by_mac(MAC).await.unwrap().unwrap();
+        assert_eq!(device.custom_name.as_deref(), Some(NAME));
+        assert_eq!(device.notes.as_deref(), Some(NOTES));
+        let quota = target.database.get_quota(&device.id).await.unwrap().unwrap();
+        assert_eq!((quota.limit_bytes, quota.action), (5_000_000, QuotaAction::Cut));
+        assert!(!quota.enforced);
+        assert_eq!(target.database.get_setting("theme").await.unwrap().as_deref(), Some("dark"));
+    }
+
+    #[tokio::test]
+    async fn csv_quotes_commas_quotes_and_newlines() {
+        let source = source().await;
+        let export = build_export(&source.database).await.unwrap();
+        let csv = to_csv(&export).unwrap();
+
+        // Fields with special characters are quoted, with quotes doubled
+        assert!(csv.contains("\"Kitchen speaker, \"\"left\"\"\""));
+        assert!(csv.contains("\"Bought 2023, \"\"refurbished\"\"\nSecond line\""));
+
+        let parsed = parse(&csv, ExportFormat::Csv).unwrap();
+        assert_eq!(parsed.devices.len(), 1);
+        assert_eq!(parsed.devices[0].custom_name.as_deref(), Some(NAME));
+        assert_eq!(parsed.devices[0].notes.as_deref(), Some(NOTES));
+        assert_eq!(parsed.groups, export.groups);
+        assert_eq!(parsed.protected_devices, export.protected_devices);
+        assert_eq!(parsed.quotas, export.quotas);
+    }
+}
@@ -1,7 +1,22 @@
 pub mod scanner;
 pub mod arp_controller;
+pub mod device_tracker;
 pub mod bandwidth;
+pub mod forwarder;
+pub mod gateway;
+pub mod transmitter;
+pub mod verifier;
+pub mod healer;
 pub mod database;
 pub mod vendor;
 pub mod packet_monitor;
-pub mod network_stats;
\ No newline at end of file
+pub mod network_stats;
+pub mod bandwidth_history;
+pub mod quota;
+pub mod inventory;
+pub mod crypto;
+pub mod scheduler;
+pub mod policy;
+pub mod audit;
+pub mod watchdog;
+pub mod arp_watch;
\ No newline at end of file
@@ -3,11 +3,22 @@ use pnet::datalink::{self, Channel, NetworkInterface};
 use pnet::packet::ethernet::{EthernetPacket, EtherTypes};
 use pnet::packet::ipv4::Ipv4Packet;
 use pnet::packet::Packet;
+use pnet::util::MacAddr;
 use std::collections::HashMap;
 use std::net::Ipv4Addr;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use tokio::sync::Mutex;
+use crate::modules::arp_watch::ArpWatch;
+use crate::modules::database::Database;
+use crate::modules::scanner::MIN_FRONTED_HOSTS;
+
+/// How long a source IP seen behind a MAC counts towards bridging. Short
+/// enough that a device renewing its lease is not mistaken for a bridge.
+const BRIDGING_WINDOW: Duration = Duration::from_secs(300);
+
+/// Local source IPs seen in frames from each MAC, with when each was last seen
+type HostsByMac = Arc<Mutex<HashMap<MacAddr, HashMap<Ipv4Addr, SystemTime>>>>;
 
 #[derive(Debug, Clone)]
 pub struct DeviceTraffic {
@@ -22,6 +33,8 @@ pub struct DeviceTraffic {
 pub struct PacketMonitor {
     interface: NetworkInterface,
     traffic_stats: Arc<Mutex<HashMap<Ipv4Addr, DeviceTraffic>>>,
+    hosts_by_mac: HostsByMac,
+    arp_watch: ArpWatch,
     local_ip: Ipv4Addr,
     running: Arc<Mutex<bool>>,
 }
@@ -62,14 +75,27 @@ impl PacketMonitor {
         log::info!("Packet monitor initialized for interface: {} ({})",
                    interface.name, local_ip);
 
+        let arp_watch = ArpWatch::new(interface.mac);
+
         Ok(Self {
             interface,
             traffic_stats: Arc::new(Mutex::new(HashMap::new())),
+            hosts_by_mac: Arc::new(Mutex::new(HashMap::new())),
+            arp_watch,
             local_ip,
             running: Arc::new(Mutex::new(false)),
         })
     }
 
+    /// Record ARP spoofing alerts in the event log of `database`
+    pub fn set_journal(&mut self, database: Arc<Mutex<Database>>) {
+        self.arp_watch.set_journal(database);
+    }
+
+    pub fn arp_watch(&self) -> &ArpWatch {
+        &self.arp_watch
+    }
+
     /// Start monitoring network packets
     pub async fn start_monitoring(&self) -> Result<()> {
         let mut running = self.running.lock().await;
@@ -81,12 +107,14 @@ impl PacketMonitor {
 
         let interface = self.interface.clone();
         let traffic_stats = self.traffic_stats.clone();
+        let hosts_by_mac = self.hosts_by_mac.clone();
+        let arp_watch = self.arp_watch.clone();
         let local_ip = self.local_ip;
         let running = self.running.clone();
 
         // Spawn monitoring task
         tokio::spawn(async move {
-            if let Err(e) = Self::monitor_loop(interface, traffic_stats, local_ip, running).await {
+            if let Err(e) = Self::monitor_loop(interface, traffic_stats, hosts_by_mac, arp_watch, local_ip, running).await {
                 log::error!("Packet monitoring error: {}", e);
             }
         });
@@ -107,6 +135,8 @@ impl PacketMonitor {
     async fn monitor_loop(
         interface: NetworkInterface,
         traffic_stats: Arc<Mutex<HashMap<Ipv4Addr, DeviceTraffic>>>,
+        hosts_by_mac: HostsByMac,
+        arp_watch: ArpWatch,
         local_ip: Ipv4Addr,
         running: Arc<Mutex<bool>>,
     ) -> Result<()> {
@@ -141,6 +171,8 @@ impl PacketMonitor {
                     // Process the packet
                     if let Some(ethernet) = EthernetPacket::new(packet) {
                         Self::process_packet(&ethernet, &traffic_stats, local_ip).await;
+                        Self::record_source(&ethernet, &hosts_by_mac, interface.mac, local_ip).await;
+                        arp_watch.inspect(&ethernet).await;
                     }
                 }
                 Err(e) => {
@@ -212,6 +244,59 @@ impl PacketMonitor {
         }
     }
 
+    /// Note which local IP sent a frame from which MAC. Ordinary hosts only
+    /// ever send from their own address; a bridge or repeater that rewrites
+    /// source MACs carries the traffic of every host behind it.
+    async fn record_source(
+        ethernet: &EthernetPacket<'_>,
+        hosts_by_mac: &HostsByMac,
+        our_mac: Option<MacAddr>,
+        local_ip: Ipv4Addr,
+    ) {
+        if ethernet.get_ethertype() != EtherTypes::Ipv4 {
+            return;
+        }
+
+        // Frames we forward for spoofed devices carry their IPs under our MAC
+        let source_mac = ethernet.get_source();
+        if Some(source_mac) == our_mac || source_mac.is_broadcast() || source_mac.is_multicast() {
+            return;
+        }
+
+        let Some(ipv4) = Ipv4Packet::new(ethernet.payload()) else {
+            return;
+        };
+        let source_ip = ipv4.get_source();
+        if source_ip == local_ip || !Self::is_local_network(source_ip) {
+            return;
+        }
+
+        let mut hosts_by_mac = hosts_by_mac.lock().await;
+        hosts_by_mac.entry(source_mac).or_default().insert(source_ip, SystemTime::now());
+    }
+
+    /// MACs (lowercase) that recently sent traffic for several local IPs,
+    /// with the IPs behind each
+    pub async fn get_bridging_macs(&self) -> HashMap<String, Vec<Ipv4Addr>> {
+        let mut hosts_by_mac = self.hosts_by_mac.lock().await;
+        let now = SystemTime::now();
+
+        hosts_by_mac.retain(|_, hosts| {
+            hosts.retain(|_, seen| now.duration_since(*seen).unwrap_or_default() < BRIDGING_WINDOW);
+            !hosts.is_empty()
+        });
+
+        hosts_by_mac
+            .iter()
+            .filter(|(_, hosts)| hosts.len() >= MIN_FRONTED_HOSTS)
+            .map(|(mac, hosts)| {
+                let mut ips: Vec<Ipv4Addr> = hosts.keys().copied().collect();
+                ips.sort();
+                (mac.to_string(), ips)
+            })
+            .collect()
+    }
+
     /// Check if an IP is in the local network (not a public IP)
     fn is_local_network(ip: Ipv4Addr) -> bool {
         let octets = ip.octets();
@@ -290,6 +375,7 @@ impl PacketMonitor {
         use std::process::Command;
 
         log::info!("Using network statistics monitoring as fallback");
+        log::warn!("ARP spoofing detection needs packet capture and is not running");
 
         loop {
             // Check if we should stop
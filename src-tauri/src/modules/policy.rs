@@ -0,0 +1,183 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::Ipv4Addr;
+use tokio::sync::Mutex;
+use crate::modules::database::{Database, InfrastructureProtection, ProtectionLevel};
+use crate::utils::safety::{check_gateway_cut, check_self_cut, device_id_for_mac, normalize_mac};
+
+/// Settings, stored by the settings command, that switch the built-in checks
+const SELF_PROTECTION_KEY: &str = "self_protection";
+const GATEWAY_PROTECTION_KEY: &str = "gateway_protection";
+
+/// Something that takes connectivity away from a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlAction {
+    Cut,
+    Limit,
+}
+
+impl ControlAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ControlAction::Cut => "cut",
+            ControlAction::Limit => "limit",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefusalReason {
+    OwnDevice,
+    Gateway,
+    ProtectedDevice,
+    Infrastructure,
+    InvalidMac,
+}
+
+/// Why the policy would not let an action through
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Refusal {
+    pub reason: RefusalReason,
+    pub action: ControlAction,
+    pub message: String,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Refusal {}
+
+/// The checks every cut and limit goes through, whether it comes from a
+/// command, a group action, a schedule or a quota
+pub struct ControlPolicy {
+    self_protection: bool,
+    gateway_protection: bool,
+    gateway_ip: Option<Ipv4Addr>,
+    protected: HashMap<String, Option<String>>,  // MAC -> label
+    sensitive: HashMap<String, Option<String>>,  // MAC -> label, for confirm-level entries
+    protected_groups: HashMap<String, String>,   // Device ID -> name of a protected group it is in
+    infrastructure_protection: InfrastructureProtection,
+    infrastructure: HashMap<String, usize>,      // MAC -> number of hosts behind it
+}
+
+impl ControlPolicy {
+    pub async fn load(database: &Database, gateway_ip: Option<Ipv4Addr>) -> Result<Self> {
+        let (protected, sensitive) = database.get_protected_devices().await?
+            .into_iter()
+            .partition::<Vec<_>, _>(|device| device.level == ProtectionLevel::Refuse);
+
+        let mut protected_groups = HashMap::new();
+        for group in database.get_groups().await?.into_iter().filter(|g| g.protected) {
+            for device_id in group.device_ids {
+                protected_groups.entry(device_id).or_insert_with(|| group.name.clone());
+            }
+        }
+
+        let infrastructure = database.get_infrastructure_devices().await?
+            .into_iter()
+            .filter(|device| !device.dismissed)
+            .filter_map(|device| Some((normalize_mac(&device.mac)?, device.hosts.len())))
+            .collect();
+
+        Ok(Self {
+            self_protection: Self::flag(database, SELF_PROTECTION_KEY).await?,
+            gateway_protection: Self::flag(database, GATEWAY_PROTECTION_KEY).await?,
+            gateway_ip,
+            protected: protected.into_iter().filter_map(|d| Some((normalize_mac(&d.mac)?, d.label))).collect(),
+            sensitive: sensitive.into_iter().filter_map(|d| Some((normalize_mac(&d.mac)?, d.label))).collect(),
+            protected_groups,
+            infrastructure_protection: database.get_infrastructure_protection().await?,
+            infrastructure,
+        })
+    }
+
+    /// Protection settings default to on
+    async fn flag(database: &Database, key: &str) -> Result<bool> {
+        Ok(database.get_setting(key).await?
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(true))
+    }
+
+    /// Check an action nobody is there to confirm, as schedules and quotas
+    /// take, against the policy as stored right now
+    pub async fn check_unattended(
+        database: &Mutex<Database>,
+        gateway_ip: Option<Ipv4Addr>,
+        action: ControlAction,
+        ip: Ipv4Addr,
+        mac: &str,
+    ) -> Result<()> {
+        let policy = Self::load(&*database.lock().await, gateway_ip).await?;
+        policy.check(action, ip, mac)?;
+        Ok(())
+    }
+
+    pub fn check(&self, action: ControlAction, ip: Ipv4Addr, mac: &str) -> Result<(), Refusal> {
+        let refuse = |reason, message: String| Err(Refusal { reason, action, message });
+        let verb = action.as_str();
+
+        let Some(mac) = normalize_mac(mac) else {
+            return refuse(RefusalReason::InvalidMac, format!("Cannot {} a device without a valid MAC address", verb));
+        };
+
+        if self.self_protection && !check_self_cut(&ip.to_string()).is_safe {
+            return refuse(RefusalReason::OwnDevice, format!("Cannot {} own device - this would disconnect NetSnip", verb));
+        }
+
+        if let Some(gateway_ip) = self.gateway_ip.filter(|_| self.gateway_protection) {
+            if !check_gateway_cut(&ip.to_string(), &gateway_ip.to_string()).is_safe {
+                return refuse(RefusalReason::Gateway, format!("Cannot {} the gateway - this would affect every device on the network", verb));
+            }
+        }
+
+        if let Some(label) = self.protected.get(&mac) {
+            let name = label.as_deref().unwrap_or(&mac);
+            return refuse(RefusalReason::ProtectedDevice, format!("{} is protected and cannot be {}", name, past_tense(action)));
+        }
+
+        if self.infrastructure_protection == InfrastructureProtection::Refuse {
+            if let Some(hosts) = self.infrastructure.get(&mac) {
+                return refuse(RefusalReason::Infrastructure, format!(
+                    "Cannot {} {} - it looks like a repeater or bridge serving {} hosts", verb, mac, hosts,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Why an action on `mac` has to be confirmed by the user first, if it does
+    pub fn confirmation_reason(&self, mac: &str) -> Option<String> {
+        let mac = normalize_mac(mac)?;
+        if let Some(label) = self.sensitive.get(&mac) {
+            return Some(format!("{} is marked as sensitive", label.as_deref().unwrap_or(&mac)));
+        }
+
+        if self.infrastructure_protection == InfrastructureProtection::Confirm {
+            if let Some(hosts) = self.infrastructure.get(&mac) {
+                return Some(format!("{} looks like a repeater or bridge serving {} hosts, which would all be affected", mac, hosts));
+            }
+        }
+
+        let device_id = device_id_for_mac(&mac);
+        self.protected_groups
+            .get(&device_id)
+            .map(|group| format!("{} is in protected group {}", mac, group))
+    }
+}
+
+fn past_tense(action: ControlAction) -> &'static str {
+    match action {
+        ControlAction::Cut => "cut",
+        ControlAction::Limit => "limited",
+    }
+}
+
@@ -0,0 +1,589 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::interval;
+use crate::utils::safety::{device_id_for_mac, mac_for_device_id};
+use crate::modules::arp_controller::ArpController;
+use crate::modules::audit::{self, AuditOutcome};
+use crate::modules::bandwidth::BandwidthLimit;
+use crate::modules::database::{AuditRecord, CutMode, Database, DeviceQuota, EventType, QuotaAction};
+use crate::modules::packet_monitor::PacketMonitor;
+use crate::modules::policy::{ControlAction, ControlPolicy, Refusal};
+use crate::modules::scanner::{NetworkDevice, NetworkScanner};
+
+const QUOTA_CHECK_INTERVAL_SECS: u64 = 10;
+
+/// Tracks per-device data usage against configured quotas and applies the
+/// quota action when a device goes over its allowance.
+#[derive(Clone)]
+pub struct QuotaManager {
+    packet_monitor: Arc<Mutex<Option<PacketMonitor>>>,
+    scanner: Arc<Mutex<NetworkScanner>>,
+    database: Arc<Mutex<Database>>,
+    arp_controller: Arc<Mutex<ArpController>>,
+    enforcing: Arc<Mutex<()>>,  // Held for each accounting pass
+    running: Arc<Mutex<bool>>,
+}
+
+impl QuotaManager {
+    pub fn new(
+        packet_monitor: Arc<Mutex<Option<PacketMonitor>>>,
+        scanner: Arc<Mutex<NetworkScanner>>,
+        database: Arc<Mutex<Database>>,
+        arp_controller: Arc<Mutex<ArpController>>,
+    ) -> Self {
+        Self {
+            packet_monitor,
+            scanner,
+            database,
+            arp_controller,
+            enforcing: Arc::new(Mutex::new(())),
+            running: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    /// Start the background quota accounting task
+    pub async fn start_enforcement(&self) -> Result<()> {
+        let mut running = self.running.lock().await;
+        if *running {
+            return Ok(());
+        }
+        *running = true;
+        drop(running);
+
+        let manager = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = interval(Duration::from_secs(QUOTA_CHECK_INTERVAL_SECS));
+            let mut last_counters: HashMap<Ipv4Addr, u64> = HashMap::new();
+            let mut devices: Vec<NetworkDevice> = Vec::new();
+            // Usage not yet added to a stored quota, kept until it is
+            let mut unsaved: HashMap<String, u64> = HashMap::new();
+
+            loop {
+                ticker.tick().await;
+
+                // Checked under the pass lock, so no pass starts once
+                // `stop_enforcement` has returned
+                let _enforcing = manager.enforcing.lock().await;
+                if !*manager.running.lock().await {
+                    break;
+                }
+
+                // Refresh the device list unless a scan currently holds the scanner
+                if let Ok(scanner) = manager.scanner.try_lock() {
+                    devices = scanner.get_discovered_devices().await;
+                }
+
+                for (device_id, bytes) in manager.collect_usage(&devices, &mut last_counters).await {
+                    *unsaved.entry(device_id).or_insert(0) += bytes;
+                }
+
+                let quotas = {
+                    let database = manager.database.lock().await;
+                    match database.get_quotas().await {
+                        Ok(quotas) => quotas,
+                        Err(e) => {
+                            log::warn!("Failed to load quotas: {}", e);
+                            continue;
+                        }
+                    }
+                };
+
+                // Usage of devices without a quota is not needed
+                let usage = std::mem::take(&mut unsaved);
+                for quota in quotas {
+                    let used = usage.get(&quota.device_id).copied().unwrap_or(0);
+                    let device_id = quota.device_id.clone();
+                    let quota = match manager.record_usage(quota, used).await {
+                        Ok(quota) => quota,
+                        Err(e) => {
+                            log::warn!("Failed to record quota usage of {}: {}", device_id, e);
+                            *unsaved.entry(device_id).or_insert(0) += used;
+                            continue;
+                        }
+                    };
+                    // Failed actions are retried on the next pass
+                    if let Err(e) = manager.enforce(quota, &devices).await {
+                        log::warn!("Failed to enforce quota of {}: {}", device_id, e);
+                    }
+                }
+            }
+
+            log::info!("Quota enforcement stopped");
+        });
+
+        log::info!("Quota enforcement started");
+        Ok(())
+    }
+
+    /// Stop the background quota accounting task, waiting for a pass in
+    /// progress
+    pub async fn stop_enforcement(&self) -> Result<()> {
+        let _enforcing = self.enforcing.lock().await;
+        let mut running = self.running.lock().await;
+        *running = false;
+        Ok(())
+    }
+
+    /// Bytes transferred per device ID since the previous call
+    async fn collect_usage(
+        &self,
+        devices: &[NetworkDevice],
+        last_counters: &mut HashMap<Ipv4Addr, u64>,
+    ) -> HashMap<String, u64> {
+        let traffic = {
+            let packet_monitor = self.packet_monitor.lock().await;
+            match packet_monitor.as_ref() {
+                Some(monitor) if monitor.is_running().await => monitor.get_traffic_stats().await,
+                _ => HashMap::new(),
+            }
+        };
+
+        let mut usage = HashMap::new();
+        for (ip, stats) in traffic {
+            let total = stats.bytes_sent + stats.bytes_received;
+            let previous = last_counters.insert(ip, total).unwrap_or(0);
+            // Counters restart from zero when the monitor clears its stats
+            let delta = total.checked_sub(previous).unwrap_or(total);
+
+            if let Some(device) = devices.iter().find(|d| d.ip == ip) {
+                let device_id = device_id_for_mac(&device.mac);
+                *usage.entry(device_id).or_insert(0) += delta;
+            }
+        }
+
+        usage
+    }
+
+    /// Roll the quota over at cycle boundaries and add new usage. The usage
+    /// is stored before any action is taken, so a failed action loses none.
+    async fn record_usage(&self, mut quota: DeviceQuota, used: u64) -> Result<DeviceQuota> {
+        let cycle_start = quota.period.cycle_start(chrono::Local::now());
+
+        // The previous cycle's action stays enforced until `enforce` lifts it
+        if quota.cycle_start != cycle_start {
+            let details = serde_json::json!({
+                "period": quota.period.as_str(),
+                "usedBytes": quota.used_bytes,
+            });
+            quota.cycle_start = cycle_start;
+            quota.used_bytes = 0;
+            self.record_event(EventType::QuotaReset, &quota.device_id, details).await;
+        }
+
+        quota.used_bytes += used as i64;
+
+        let database = self.database.lock().await;
+        database.upsert_quota(&quota).await?;
+        Ok(quota)
+    }
+
+    /// Apply the quota action once usage reaches the limit, and lift it
+    /// once usage is below the limit again after a new cycle or a raised
+    /// limit
+    async fn enforce(&self, mut quota: DeviceQuota, devices: &[NetworkDevice]) -> Result<()> {
+        if !quota.enforced && quota.used_bytes >= quota.limit_bytes {
+            let device = devices
+                .iter()
+                .find(|d| device_id_for_mac(&d.mac) == quota.device_id);
+
+            if let Some(device) = device {
+                // A protected device still counts as having reached its
+                // quota, but nothing is applied that would need lifting
+                let refusal = match self.apply_action(&quota, device).await {
+                    Ok(()) => {
+                        quota.enforced_ip = Some(device.ip.to_string());
+                        None
+                    }
+                    Err(e) => Some(e.downcast::<Refusal>()?),
+                };
+                quota.enforced = true;
+
+                if quota.action != QuotaAction::Notify {
+                    let outcome = match refusal {
+                        Some(_) => AuditOutcome::Refused,
+                        None => AuditOutcome::Succeeded,
+                    };
+                    self.audit(&quota, quota.action.as_str(), outcome).await;
+                }
+
+                log::warn!(
+                    "Device {} reached its {} quota ({} of {} bytes), action: {}",
+                    quota.device_id, quota.period.as_str(), quota.used_bytes,
+                    quota.limit_bytes, quota.action.as_str()
+                );
+
+                self.record_event(EventType::QuotaReached, &quota.device_id, serde_json::json!({
+                    "period": quota.period.as_str(),
+                    "usedBytes": quota.used_bytes,
+                    "limitBytes": quota.limit_bytes,
+                    "action": quota.action.as_str(),
+                    "refusal": refusal,
+                })).await;
+            }
+        } else if quota.enforced && quota.used_bytes < quota.limit_bytes {
+            // A new cycle started or the limit was raised above current usage
+            self.lift_action(&quota).await?;
+            quota.enforced = false;
+            quota.enforced_ip = None;
+        } else {
+            // Usage was stored by `record_usage`
+            return Ok(());
+        }
+
+        let database = self.database.lock().await;
+        database.upsert_quota(&quota).await
+    }
+
+    async fn apply_action(&self, quota: &DeviceQuota, device: &NetworkDevice) -> Result<()> {
+        if quota.action == QuotaAction::Notify {
+            return Ok(());
+        }
+
+        // Both cutting and limiting redirect the device through us
+        let mut arp = self.arp_controller.lock().await;
+        let gateway_ip = arp.ensure_gateway(&self.scanner).await?;
+
+        let action = match quota.action {
+            QuotaAction::Cut => ControlAction::Cut,
+            _ => ControlAction::Limit,
+        };
+        ControlPolicy::check_unattended(&self.database, Some(gateway_ip), action, device.ip, &device.mac).await?;
+
+        match quota.action {
+            QuotaAction::Notify => {}
+            QuotaAction::Limit => {
+                let limit_mbps = quota.limit_mbps
+                    .ok_or_else(|| anyhow::anyhow!("Quota for {} has no bandwidth limit", quota.device_id))?;
+                arp.limit_device(device.ip, device.mac.clone(), BandwidthLimit::symmetric(limit_mbps)).await?;
+            }
+            QuotaAction::Cut => {
+                arp.cut_device(device.ip, device.mac.clone(), CutMode::InternetOnly).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Undo the action applied when the quota was reached
+    pub async fn lift_action(&self, quota: &DeviceQuota) -> Result<()> {
+        if quota.enforced_ip.is_none() {
+            return Ok(());
+        }
+        // Redirects are keyed by MAC, which follows the device if its address changed
+        let mac = mac_for_device_id(&quota.device_id);
+
+        let (event_type, action) = match quota.action {
+            QuotaAction::Notify => return Ok(()),
+            QuotaAction::Limit => {
+                let arp = self.arp_controller.lock().await;
+                arp.remove_limit(&mac).await?;
+                (EventType::LimitRemoved, "unlimit")
+            }
+            QuotaAction::Cut => {
+                let arp = self.arp_controller.lock().await;
+                arp.restore_device(&mac).await?;
+                (EventType::DeviceRestored, "restore")
+            }
+        };
+
+        self.audit(quota, action, AuditOutcome::Succeeded).await;
+        self.record_event(event_type, &quota.device_id, serde_json::json!({
+            "ip": quota.enforced_ip,
+            "reason": "quota",
+            "period": quota.period.as_str(),
+        })).await;
+
+        log::info!("Lifted {} quota action for device {}", quota.action.as_str(), quota.device_id);
+        Ok(())
+    }
+
+    async fn audit(&self, quota: &DeviceQuota, action: &str, outcome: AuditOutcome) {
+        let details = serde_json::json!({
+            "period": quota.period.as_str(),
+            "usedBytes": quota.used_bytes,
+            "limitBytes": quota.limit_bytes,
+        });
+        let database = self.database.lock().await;
+        audit::record(&database, AuditRecord {
+            actor: "quota".to_string(),
+            action: action.to_string(),
+            target: quota.device_id.clone(),
+            reason: Some(format!("{} data quota", quota.period.as_str())),
+            outcome: outcome.as_str().to_string(),
+            details: Some(details.to_string()),
+        }).await;
+    }
+
+    async fn record_event(&self, event_type: EventType, device_id: &str, details: serde_json::Value) {
+        let database = self.database.lock().await;
+        if let Err(e) = database.record_event(event_type, device_id, Some(details.to_string())).await {
+            log::warn!("Failed to record {} event for {}: {}", event_type.as_str(), device_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, NaiveDate, Utc};
+    use pnet::datalink::NetworkInterface;
+    use pnet::ipnetwork::{IpNetwork, Ipv4Network};
+    use std::path::PathBuf;
+    use crate::modules::bandwidth::BandwidthController;
+    use crate::modules::database::{DeviceRecord, EventQuery, QuotaPeriod};
+
+    const DEVICE_MAC: &str = "02:00:00:00:00:10";
+    const DEVICE_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+
+    struct Harness {
+        manager: QuotaManager,
+        path: PathBuf,
+    }
+
+    impl Drop for Harness {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    /// A manager on a fresh database, with a dry-run controller that has
+    /// bandwidth limiting attached but no interface to forward on
+    async fn harness(name: &str) -> Harness {
+        let path = std::env::temp_dir().join(format!("netsnip-quota-{}-{}.db", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        let database = Database::new(&path).await.unwrap();
+        let now = Utc::now().timestamp();
+        database.upsert_device(&DeviceRecord {
+            id: device_id_for_mac(DEVICE_MAC),
+            mac: DEVICE_MAC.to_string(),
+            ip: DEVICE_IP.to_string(),
+            hostname: None,
+            custom_name: None,
+            manufacturer: None,
+            device_type: "unknown".to_string(),
+            first_seen: now,
+            last_seen: now,
+            total_bytes: 0,
+            is_blocked: false,
+            bandwidth_limit: None,
+            notes: None,
+            icon: None,
+        }).await.unwrap();
+
+        let interface = NetworkInterface {
+            name: "test0".to_string(),
+            description: String::new(),
+            index: 0,
+            mac: Some("02:00:00:00:00:01".parse().unwrap()),
+            ips: vec![IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 1, 2), 24).unwrap())],
+            flags: 0,
+        };
+        let mut controller = ArpController::new(interface.clone()).unwrap();
+        controller.set_gateway(Ipv4Addr::new(192, 168, 1, 1), "02:00:00:00:00:fe".to_string()).await.unwrap();
+        controller.set_bandwidth(Arc::new(Mutex::new(BandwidthController::new()))).unwrap();
+        controller.set_dry_run(true).await.unwrap();
+
+        let manager = QuotaManager::new(
+            Arc::new(Mutex::new(None)),
+            Arc::new(Mutex::new(NetworkScanner::with_interface(interface))),
+            Arc::new(Mutex::new(database)),
+            Arc::new(Mutex::new(controller)),
+        );
+        Harness { manager, path }
+    }
+
+    fn quota(period: QuotaPeriod, action: QuotaAction, used_bytes: i64) -> DeviceQuota {
+        DeviceQuota {
+            device_id: device_id_for_mac(DEVICE_MAC),
+            period,
+            limit_bytes: 1000,
+            action,
+            limit_mbps: (action == QuotaAction::Limit).then_some(5.0),
+            cycle_start: period.cycle_start(chrono::Local::now()),
+            used_bytes,
+            enforced: false,
+            enforced_ip: None,
+        }
+    }
+
+    fn devices() -> Vec<NetworkDevice> {
+        vec![NetworkDevice {
+            ip: DEVICE_IP,
+            mac: DEVICE_MAC.to_string(),
+            hostname: None,
+            manufacturer: None,
+            device_type: "unknown".to_string(),
+            is_gateway: false,
+        }]
+    }
+
+    impl Harness {
+        async fn stored(&self) -> DeviceQuota {
+            let database = self.manager.database.lock().await;
+            database.get_quota(&device_id_for_mac(DEVICE_MAC)).await.unwrap().unwrap()
+        }
+
+        async fn events(&self, event_type: EventType) -> usize {
+            let database = self.manager.database.lock().await;
+            let query = EventQuery { event_types: vec![event_type], ..Default::default() };
+            database.get_events(&query).await.unwrap().events.len()
+        }
+    }
+
+    fn timestamp(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+            .and_hms_opt(hour, minute, 0).unwrap()
+            .and_utc()
+            .timestamp()
+    }
+
+    fn cycle_start(period: QuotaPeriod, now: i64) -> i64 {
+        period.cycle_start(DateTime::from_timestamp(now, 0).unwrap())
+    }
+
+    #[test]
+    fn daily_cycles_start_at_midnight() {
+        let start = timestamp(2024, 1, 3, 0, 0);
+        assert_eq!(cycle_start(QuotaPeriod::Daily, start), start);
+        assert_eq!(cycle_start(QuotaPeriod::Daily, timestamp(2024, 1, 3, 23, 59)), start);
+        assert_eq!(cycle_start(QuotaPeriod::Daily, timestamp(2024, 1, 4, 0, 0)), timestamp(2024, 1, 4, 0, 0));
+    }
+
+    #[test]
+    fn weekly_cycles_start_on_monday() {
+        // 2024-01-01 is a Monday
+        let start = timestamp(2024, 1, 1, 0, 0);
+        assert_eq!(cycle_start(QuotaPeriod::Weekly, start), start);
+        assert_eq!(cycle_start(QuotaPeriod::Weekly, timestamp(2024, 1, 3, 12, 0)), start);
+        assert_eq!(cycle_start(QuotaPeriod::Weekly, timestamp(2024, 1, 7, 23, 59)), start);
+        assert_eq!(cycle_start(QuotaPeriod::Weekly, timestamp(2024, 1, 8, 0, 0)), timestamp(2024, 1, 8, 0, 0));
+        // Weeks run across the end of the year
+        assert_eq!(cycle_start(QuotaPeriod::Weekly, timestamp(2025, 1, 1, 9, 0)), timestamp(2024, 12, 30, 0, 0));
+    }
+
+    #[test]
+    fn monthly_cycles_start_on_the_first() {
+        let start = timestamp(2024, 2, 1, 0, 0);
+        assert_eq!(cycle_start(QuotaPeriod::Monthly, start), start);
+        assert_eq!(cycle_start(QuotaPeriod::Monthly, timestamp(2024, 2, 29, 23, 59)), start);
+        assert_eq!(cycle_start(QuotaPeriod::Monthly, timestamp(2024, 3, 1, 0, 0)), timestamp(2024, 3, 1, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn usage_accumulates_within_a_cycle() {
+        let harness = harness("accumulate").await;
+        let quota = quota(QuotaPeriod::Daily, QuotaAction::Notify, 100);
+        let cycle_start = quota.cycle_start;
+
+        let quota = harness.manager.record_usage(quota, 50).await.unwrap();
+        let quota = harness.manager.record_usage(quota, 25).await.unwrap();
+
+        assert_eq!(quota.used_bytes, 175);
+        assert_eq!(quota.cycle_start, cycle_start);
+        assert_eq!(harness.stored().await.used_bytes, 175);
+        assert_eq!(harness.events(EventType::QuotaReset).await, 0);
+    }
+
+    #[tokio::test]
+    async fn usage_resets_at_a_new_cycle() {
+        let harness = harness("reset").await;
+        let mut quota = quota(QuotaPeriod::Daily, QuotaAction::Cut, 1500);
+        let cycle_start = quota.cycle_start;
+        quota.cycle_start -= 86_400;
+        quota.enforced = true;
+
+        let quota = harness.manager.record_usage(quota, 50).await.unwrap();
+
+        // Only the new usage counts, and the action waits for `enforce`
+        assert_eq!(quota.used_bytes, 50);
+        assert_eq!(quota.cycle_start, cycle_start);
+        assert!(quota.enforced);
+        assert_eq!(harness.stored().await.used_bytes, 50);
+        assert_eq!(harness.events(EventType::QuotaReset).await, 1);
+    }
+
+    #[tokio::test]
+    async fn notify_quota_is_recorded_and_lifted_without_touching_the_device() {
+        let harness = harness("notify").await;
+        let quota = quota(QuotaPeriod::Daily, QuotaAction::Notify, 1000);
+
+        harness.manager.enforce(quota, &devices()).await.unwrap();
+        let mut reached = harness.stored().await;
+        assert!(reached.enforced);
+        assert_eq!(reached.enforced_ip, Some(DEVICE_IP.to_string()));
+        assert!(harness.manager.arp_controller.lock().await.get_cut(DEVICE_MAC).await.is_none());
+        assert_eq!(harness.events(EventType::QuotaReached).await, 1);
+
+        reached.used_bytes = 0;
+        harness.manager.enforce(reached, &devices()).await.unwrap();
+        assert!(!harness.stored().await.enforced);
+    }
+
+    #[tokio::test]
+    async fn cut_quota_is_applied_and_lifted() {
+        let harness = harness("cut").await;
+        let quota = quota(QuotaPeriod::Weekly, QuotaAction::Cut, 1000);
+
+        harness.manager.enforce(quota, &devices()).await.unwrap();
+        let mut reached = harness.stored().await;
+        assert!(reached.enforced);
+        assert_eq!(reached.enforced_ip, Some(DEVICE_IP.to_string()));
+        assert!(harness.manager.arp_controller.lock().await.get_cut(DEVICE_MAC).await.is_some());
+
+        // Raising the limit above current usage lifts the cut
+        reached.limit_bytes = 2000;
+        harness.manager.enforce(reached, &devices()).await.unwrap();
+        let lifted = harness.stored().await;
+        assert!(!lifted.enforced);
+        assert_eq!(lifted.enforced_ip, None);
+        assert!(harness.manager.arp_controller.lock().await.get_cut(DEVICE_MAC).await.is_none());
+        assert_eq!(harness.events(EventType::DeviceRestored).await, 1);
+    }
+
+    #[tokio::test]
+    async fn limit_quota_is_retried_when_the_limit_cannot_be_applied() {
+        let harness = harness("limit-retry").await;
+        let quota = quota(QuotaPeriod::Monthly, QuotaAction::Limit, 1000);
+        harness.manager.database.lock().await.upsert_quota(&quota).await.unwrap();
+
+        // The test interface cannot be opened, so forwarding never starts
+        assert!(harness.manager.enforce(quota, &devices()).await.is_err());
+        let arp = harness.manager.arp_controller.lock().await;
+        assert_eq!(arp.get_limit(DEVICE_MAC).await, None);
+        assert!(arp.get_forwarded_devices().await.is_empty());
+        drop(arp);
+        assert!(!harness.stored().await.enforced);
+    }
+
+    #[tokio::test]
+    async fn limit_quota_is_applied_and_lifted() {
+        let harness = harness("limit").await;
+        // A cut device is already redirected, so no forwarding has to start
+        harness.manager.arp_controller.lock().await
+            .cut_device(DEVICE_IP, DEVICE_MAC.to_string(), CutMode::InternetOnly).await.unwrap();
+        let quota = quota(QuotaPeriod::Monthly, QuotaAction::Limit, 1000);
+
+        harness.manager.enforce(quota, &devices()).await.unwrap();
+        let mut reached = harness.stored().await;
+        assert!(reached.enforced);
+        assert_eq!(
+            harness.manager.arp_controller.lock().await.get_limit(DEVICE_MAC).await,
+            Some(BandwidthLimit::symmetric(5.0))
+        );
+
+        // Lifting the limit leaves the device's own cut in place
+        reached.used_bytes = 0;
+        harness.manager.enforce(reached, &devices()).await.unwrap();
+        assert!(!harness.stored().await.enforced);
+        let arp = harness.manager.arp_controller.lock().await;
+        assert_eq!(arp.get_limit(DEVICE_MAC).await, None);
+        assert!(arp.get_cut(DEVICE_MAC).await.is_some());
+        drop(arp);
+        assert_eq!(harness.events(EventType::LimitRemoved).await, 1);
+    }
+}
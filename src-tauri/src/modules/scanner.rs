@@ -13,6 +13,10 @@ use tokio::sync::Mutex;
 use tokio::time::{sleep, timeout};
 use crate::modules::vendor::VendorLookup;
 
+/// Distinct IPs behind one MAC before it is taken for a repeater, mesh
+/// node or bridge rather than an ordinary host
+pub const MIN_FRONTED_HOSTS: usize = 2;
+
 #[derive(Clone, Debug)]
 pub struct NetworkDevice {
     pub ip: Ipv4Addr,
@@ -42,11 +46,16 @@ impl NetworkScanner {
 
         log::info!("Using network interface: {}", interface.name);
 
-        Ok(Self {
+        Ok(Self::with_interface(interface))
+    }
+
+    /// Scan on a given interface
+    pub fn with_interface(interface: NetworkInterface) -> Self {
+        Self {
             interface,
             discovered_devices: Arc::new(Mutex::new(HashMap::new())),
             vendor_lookup: VendorLookup::new(),
-        })
+        }
     }
 
     /// Get the default gateway using macOS route command
@@ -274,6 +283,9 @@ impl NetworkScanner {
         *discovered = devices_map.clone();
 
         let result: Vec<NetworkDevice> = devices_map.into_values().collect();
+        for (mac, ips) in find_shared_macs(&result) {
+            log::info!("{} answers for {} addresses, likely infrastructure", mac, ips.len());
+        }
         log::info!("Network scan complete. Found {} devices", result.len());
         println!("Returning {} devices to frontend", result.len());
 
@@ -460,4 +472,19 @@ impl NetworkScanner {
         ethernet_packet.set_payload(arp_packet.packet());
         ethernet_buffer
     }
+}
+
+/// MACs (lowercase) that scanned devices share, with the IPs behind each.
+/// A repeater or bridge that proxies ARP for the hosts behind it answers
+/// for all of their addresses with its own MAC.
+pub fn find_shared_macs(devices: &[NetworkDevice]) -> HashMap<String, Vec<Ipv4Addr>> {
+    let mut ips_by_mac: HashMap<String, Vec<Ipv4Addr>> = HashMap::new();
+    for device in devices {
+        ips_by_mac.entry(device.mac.to_lowercase()).or_default().push(device.ip);
+    }
+    ips_by_mac.retain(|_, ips| ips.len() >= MIN_FRONTED_HOSTS);
+    for ips in ips_by_mac.values_mut() {
+        ips.sort();
+    }
+    ips_by_mac
 }
\ No newline at end of file
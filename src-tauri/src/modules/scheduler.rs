@@ -0,0 +1,610 @@
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::interval;
+use crate::utils::safety::{device_id_for_mac, mac_for_device_id};
+use crate::modules::arp_controller::ArpController;
+use crate::modules::audit::{self, AuditOutcome};
+use crate::modules::bandwidth::BandwidthLimit;
+use crate::modules::database::{
+    AuditRecord, CutMode, Database, DeviceGroup, EventType, Schedule, ScheduleAction, ScheduleApplication, ScheduleTarget,
+};
+use crate::modules::policy::{ControlAction, ControlPolicy, Refusal};
+use crate::modules::scanner::{NetworkDevice, NetworkScanner};
+
+const SCHEDULE_CHECK_INTERVAL_SECS: u64 = 20;
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// The next point at which a schedule affecting a device starts or ends
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleTransition {
+    pub at: DateTime<Utc>,
+    pub schedule_id: i64,
+    pub schedule_name: String,
+    pub action: ScheduleAction,
+    pub starts: bool,  // False when the window ends at `at`
+}
+
+/// Applies and lifts scheduled cuts and bandwidth limits at window
+/// boundaries. What has been applied is stored in the database, so windows
+/// that ended while the app was closed are lifted on the next start.
+#[derive(Clone)]
+pub struct Scheduler {
+    scanner: Arc<Mutex<NetworkScanner>>,
+    database: Arc<Mutex<Database>>,
+    arp_controller: Arc<Mutex<ArpController>>,
+    devices: Arc<Mutex<Vec<NetworkDevice>>>,
+    evaluating: Arc<Mutex<()>>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl Scheduler {
+    pub fn new(
+        scanner: Arc<Mutex<NetworkScanner>>,
+        database: Arc<Mutex<Database>>,
+        arp_controller: Arc<Mutex<ArpController>>,
+    ) -> Self {
+        Self {
+            scanner,
+            database,
+            arp_controller,
+            devices: Arc::new(Mutex::new(Vec::new())),
+            evaluating: Arc::new(Mutex::new(())),
+            running: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    /// Start the background schedule task
+    pub async fn start(&self) -> Result<()> {
+        let mut running = self.running.lock().await;
+        if *running {
+            return Ok(());
+        }
+        *running = true;
+        drop(running);
+
+        let scheduler = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = interval(Duration::from_secs(SCHEDULE_CHECK_INTERVAL_SECS));
+
+            loop {
+                ticker.tick().await;
+
+                // Checked under the evaluation lock, so no pass starts
+                // once `stop` has returned
+                let _evaluating = scheduler.evaluating.lock().await;
+                if !*scheduler.running.lock().await {
+                    break;
+                }
+
+                if let Err(e) = scheduler.evaluate_locked().await {
+                    log::warn!("Failed to evaluate schedules: {}", e);
+                }
+            }
+
+            log::info!("Scheduler stopped");
+        });
+
+        log::info!("Scheduler started");
+        Ok(())
+    }
+
+    /// Stop the background schedule task, waiting for a pass in progress
+    pub async fn stop(&self) -> Result<()> {
+        let _evaluating = self.evaluating.lock().await;
+        let mut running = self.running.lock().await;
+        *running = false;
+        Ok(())
+    }
+
+    /// Bring every device in line with the schedules active right now
+    pub async fn evaluate(&self) -> Result<()> {
+        let _evaluating = self.evaluating.lock().await;
+        self.evaluate_locked().await
+    }
+
+    /// `evaluate` for a caller already holding the evaluation lock
+    async fn evaluate_locked(&self) -> Result<()> {
+        let now = Local::now();
+
+        // Refresh the device list unless a scan currently holds the scanner
+        if let Ok(scanner) = self.scanner.try_lock() {
+            *self.devices.lock().await = scanner.get_discovered_devices().await;
+        }
+        let devices = self.devices.lock().await.clone();
+
+        let (schedules, groups, applied) = {
+            let database = self.database.lock().await;
+            (
+                database.get_schedules().await?,
+                database.get_groups().await?,
+                database.get_schedule_applications().await?,
+            )
+        };
+
+        let desired = desired_state(&schedules, &groups, &now);
+
+        // Lift actions whose window has ended or been overridden
+        let mut kept = HashMap::new();
+        for mut application in applied {
+            let still_wanted = desired
+                .get(&application.device_id)
+                .filter(|schedule| same_effect(schedule, &application));
+
+            if let Some(schedule) = still_wanted {
+                // Another schedule with the same effect took over
+                if schedule.id != application.schedule_id {
+                    application.schedule_id = schedule.id;
+                    let database = self.database.lock().await;
+                    database.set_schedule_application(&application).await?;
+                }
+                kept.insert(application.device_id.clone(), application);
+                continue;
+            }
+
+            match self.lift(&application).await {
+                Ok(()) => {
+                    let database = self.database.lock().await;
+                    database.remove_schedule_application(&application.device_id).await?;
+                }
+                Err(e) => log::warn!("Failed to lift schedule {} for {}: {}", application.schedule_id, application.device_id, e),
+            }
+        }
+
+        // Apply actions for windows that have started
+        for (device_id, schedule) in desired {
+            if kept.contains_key(&device_id) {
+                continue;
+            }
+
+            // Offline devices are picked up on a later pass once scanned
+            let Some(device) = devices.iter().find(|d| device_id_for_mac(&d.mac) == device_id) else {
+                continue;
+            };
+
+            match self.apply(schedule, &device_id, device).await {
+                Ok(redirected_at) => {
+                    let database = self.database.lock().await;
+                    database.set_schedule_application(&ScheduleApplication {
+                        device_id,
+                        schedule_id: schedule.id,
+                        action: schedule.action,
+                        limit_mbps: schedule.limit_mbps,
+                        ip: device.ip.to_string(),
+                        redirected_at,
+                    }).await?;
+                }
+                // Retried every pass, so refusals are not worth a warning each time
+                Err(e) if e.is::<Refusal>() => log::debug!("Schedule {} not applied to {}: {}", schedule.id, device_id, e),
+                Err(e) => log::warn!("Failed to apply schedule {} to {}: {}", schedule.id, device_id, e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply a schedule's action, returning when the redirect it made was made
+    async fn apply(&self, schedule: &Schedule, device_id: &str, device: &NetworkDevice) -> Result<Option<i64>> {
+        // Both cutting and limiting redirect the device through us
+        let mut arp = self.arp_controller.lock().await;
+        let gateway_ip = arp.ensure_gateway(&self.scanner).await?;
+
+        let action = match schedule.action {
+            ScheduleAction::Cut => ControlAction::Cut,
+            ScheduleAction::Limit => ControlAction::Limit,
+        };
+        ControlPolicy::check_unattended(&self.database, Some(gateway_ip), action, device.ip, &device.mac).await?;
+
+        let event_type = match schedule.action {
+            ScheduleAction::Cut => {
+                arp.cut_device(device.ip, device.mac.clone(), CutMode::InternetOnly).await?;
+                EventType::DeviceCut
+            }
+            ScheduleAction::Limit => {
+                let limit_mbps = schedule.limit_mbps
+                    .ok_or_else(|| anyhow::anyhow!("Schedule {} has no bandwidth limit", schedule.id))?;
+                arp.limit_device(device.ip, device.mac.clone(), BandwidthLimit::symmetric(limit_mbps)).await?;
+                EventType::LimitSet
+            }
+        };
+        let redirected_at = arp.redirected_at(&device.mac).await;
+        drop(arp);
+        self.record_event(event_type, device_id, schedule).await;
+
+        log::info!("Schedule {} ({}) applied {} to {}", schedule.id, schedule.name, schedule.action.as_str(), device_id);
+        Ok(redirected_at)
+    }
+
+    /// Whether what the schedule applied is still in place, rather than
+    /// undone or replaced by a cut or limit made since
+    async fn still_applied(&self, application: &ScheduleApplication, mac: &str) -> bool {
+        let arp = self.arp_controller.lock().await;
+        match application.action {
+            ScheduleAction::Cut => {
+                // Applications recorded before redirect times were kept are
+                // taken to still be in place
+                let redirected_at = arp.redirected_at(mac).await;
+                arp.is_device_cut(mac).await
+                    && application.redirected_at.is_none_or(|at| redirected_at == Some(at))
+            }
+            ScheduleAction::Limit => {
+                let limit = application.limit_mbps.map(BandwidthLimit::symmetric);
+                limit.is_some() && arp.get_limit(mac).await == limit
+            }
+        }
+    }
+
+    async fn lift(&self, application: &ScheduleApplication) -> Result<()> {
+        // Looked up by MAC; the device may have a different address by now
+        let mac = mac_for_device_id(&application.device_id);
+
+        if !self.still_applied(application, &mac).await {
+            log::info!(
+                "Schedule {} left {} for {} alone, it was changed while the window was open",
+                application.schedule_id, application.action.as_str(), application.device_id
+            );
+            return Ok(());
+        }
+
+        let (event_type, action) = match application.action {
+            ScheduleAction::Cut => {
+                let arp = self.arp_controller.lock().await;
+                arp.restore_device(&mac).await?;
+                (EventType::DeviceRestored, "restore")
+            }
+            ScheduleAction::Limit => {
+                let arp = self.arp_controller.lock().await;
+                arp.remove_limit(&mac).await?;
+                (EventType::LimitRemoved, "unlimit")
+            }
+        };
+
+        let details = serde_json::json!({ "scheduleId": application.schedule_id });
+        let database = self.database.lock().await;
+        if let Err(e) = database.record_event(event_type, &application.device_id, Some(details.to_string())).await {
+            log::warn!("Failed to record {} event for {}: {}", event_type.as_str(), application.device_id, e);
+        }
+        audit::record(&database, AuditRecord {
+            actor: format!("schedule:{}", application.schedule_id),
+            action: action.to_string(),
+            target: application.device_id.clone(),
+            reason: Some("Schedule window ended".to_string()),
+            outcome: AuditOutcome::Succeeded.as_str().to_string(),
+            details: Some(details.to_string()),
+        }).await;
+
+        log::info!("Schedule {} lifted {} for {}", application.schedule_id, application.action.as_str(), application.device_id);
+        Ok(())
+    }
+
+    async fn record_event(&self, event_type: EventType, device_id: &str, schedule: &Schedule) {
+        let details = serde_json::json!({
+            "scheduleId": schedule.id,
+            "schedule": schedule.name,
+            "limitMbps": schedule.limit_mbps,
+        });
+        let database = self.database.lock().await;
+        if let Err(e) = database.record_event(event_type, device_id, Some(details.to_string())).await {
+            log::warn!("Failed to record {} event for {}: {}", event_type.as_str(), device_id, e);
+        }
+        // Refused and failed attempts are retried every pass, so only
+        // actions that took effect are audited
+        audit::record(&database, AuditRecord {
+            actor: format!("schedule:{}", schedule.id),
+            action: schedule.action.as_str().to_string(),
+            target: device_id.to_string(),
+            reason: Some(format!("Schedule {}", schedule.name)),
+            outcome: AuditOutcome::Succeeded.as_str().to_string(),
+            details: Some(details.to_string()),
+        }).await;
+    }
+}
+
+/// The schedule that should be in force for each device right now
+fn desired_state<'a, Tz: TimeZone>(
+    schedules: &'a [Schedule],
+    groups: &[DeviceGroup],
+    now: &DateTime<Tz>,
+) -> HashMap<String, &'a Schedule> {
+    let mut desired: HashMap<String, &Schedule> = HashMap::new();
+
+    for schedule in schedules.iter().filter(|s| s.enabled && is_active(s, now)) {
+        for device_id in schedule_devices(schedule, groups) {
+            let wins = desired
+                .get(&device_id)
+                .is_none_or(|current| takes_precedence(schedule, current));
+            if wins {
+                desired.insert(device_id, schedule);
+            }
+        }
+    }
+
+    desired
+}
+
+/// Overlapping schedules resolve the same way every time: a cut beats a
+/// limit, a lower limit beats a higher one, and the older schedule wins ties.
+fn takes_precedence(candidate: &Schedule, current: &Schedule) -> bool {
+    let rank = |s: &Schedule| {
+        let action = match s.action {
+            ScheduleAction::Cut => 0,
+            ScheduleAction::Limit => 1,
+        };
+        (action, s.limit_mbps.unwrap_or(0.0), s.id)
+    };
+
+    let (a, b) = (rank(candidate), rank(current));
+    a.0.cmp(&b.0)
+        .then(a.1.total_cmp(&b.1))
+        .then(a.2.cmp(&b.2))
+        .is_lt()
+}
+
+fn same_effect(schedule: &Schedule, application: &ScheduleApplication) -> bool {
+    match schedule.action {
+        ScheduleAction::Cut => application.action == ScheduleAction::Cut,
+        ScheduleAction::Limit => application.action == ScheduleAction::Limit && schedule.limit_mbps == application.limit_mbps,
+    }
+}
+
+/// Device IDs a schedule applies to
+pub fn schedule_devices(schedule: &Schedule, groups: &[DeviceGroup]) -> Vec<String> {
+    match schedule.target {
+        ScheduleTarget::Device => vec![schedule.target_id.clone()],
+        ScheduleTarget::Group => groups
+            .iter()
+            .find(|g| g.id.to_string() == schedule.target_id)
+            .map(|g| g.device_ids.clone())
+            .unwrap_or_default(),
+    }
+}
+
+/// Start and end of the window that begins on `date` in `tz`, if the
+/// schedule runs that day
+fn window_on<Tz: TimeZone>(schedule: &Schedule, date: NaiveDate, tz: &Tz) -> Option<(DateTime<Tz>, DateTime<Tz>)> {
+    if !schedule.days.contains(&date.weekday()) {
+        return None;
+    }
+
+    let midnight = date.and_hms_opt(0, 0, 0)?;
+    let start = midnight + ChronoDuration::minutes(schedule.start_minute as i64);
+    // An end at or before the start means the window runs past midnight
+    let end_offset = if schedule.end_minute > schedule.start_minute {
+        schedule.end_minute
+    } else {
+        schedule.end_minute + MINUTES_PER_DAY
+    };
+    let end = midnight + ChronoDuration::minutes(end_offset as i64);
+
+    Some((to_local(tz, start)?, to_local(tz, end)?))
+}
+
+/// Resolve a wall-clock time, moving times skipped by a DST change forward
+fn to_local<Tz: TimeZone>(tz: &Tz, naive: NaiveDateTime) -> Option<DateTime<Tz>> {
+    tz.from_local_datetime(&naive).earliest()
+        .or_else(|| tz.from_local_datetime(&(naive + ChronoDuration::hours(1))).earliest())
+}
+
+pub fn is_active<Tz: TimeZone>(schedule: &Schedule, now: &DateTime<Tz>) -> bool {
+    let today = now.date_naive();
+    // Yesterday's window may still be running past midnight
+    [today.pred_opt(), Some(today)]
+        .into_iter()
+        .flatten()
+        .filter_map(|date| window_on(schedule, date, &now.timezone()))
+        .any(|(start, end)| start <= *now && *now < end)
+}
+
+/// The next start or end of a schedule's window after `now`
+fn next_boundary<Tz: TimeZone>(schedule: &Schedule, now: &DateTime<Tz>) -> Option<(DateTime<Tz>, bool)> {
+    let today = now.date_naive();
+
+    (-1..=7)
+        .filter_map(|offset| today.checked_add_signed(ChronoDuration::days(offset)))
+        .filter_map(|date| window_on(schedule, date, &now.timezone()))
+        .flat_map(|(start, end)| [(start, true), (end, false)])
+        .filter(|(at, _)| at > now)
+        .min_by_key(|(at, _)| at.clone())
+}
+
+/// The next transition of any enabled schedule covering `device_id`
+pub fn next_transition(
+    schedules: &[Schedule],
+    groups: &[DeviceGroup],
+    device_id: &str,
+    now: DateTime<Local>,
+) -> Option<ScheduleTransition> {
+    schedules
+        .iter()
+        .filter(|s| s.enabled && schedule_devices(s, groups).iter().any(|id| id == device_id))
+        .filter_map(|s| next_boundary(s, &now).map(|(at, starts)| (s, at, starts)))
+        .min_by_key(|(s, at, _)| (*at, s.id))
+        .map(|(s, at, starts)| ScheduleTransition {
+            at: at.with_timezone(&Utc),
+            schedule_id: s.id,
+            schedule_name: s.name.clone(),
+            action: s.action,
+            starts,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, MappedLocalTime, NaiveTime, Weekday};
+
+    /// US Eastern around the 2024 spring change: clocks jump from 02:00 to
+    /// 03:00 local on Sunday 10 March, so 02:00-02:59 that day never happens
+    #[derive(Debug, Clone, Copy)]
+    struct SpringForward;
+
+    impl SpringForward {
+        fn gap() -> (NaiveDateTime, NaiveDateTime) {
+            let day = NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+            (day.and_hms_opt(2, 0, 0).unwrap(), day.and_hms_opt(3, 0, 0).unwrap())
+        }
+
+        fn standard() -> FixedOffset {
+            FixedOffset::west_opt(5 * 3600).unwrap()
+        }
+
+        fn daylight() -> FixedOffset {
+            FixedOffset::west_opt(4 * 3600).unwrap()
+        }
+    }
+
+    impl TimeZone for SpringForward {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            SpringForward
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> MappedLocalTime<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> MappedLocalTime<FixedOffset> {
+            let (gap_start, gap_end) = Self::gap();
+            if *local < gap_start {
+                MappedLocalTime::Single(Self::standard())
+            } else if *local >= gap_end {
+                MappedLocalTime::Single(Self::daylight())
+            } else {
+                MappedLocalTime::None
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            let (gap_start, _) = Self::gap();
+            if *utc < gap_start + ChronoDuration::hours(5) {
+                Self::standard()
+            } else {
+                Self::daylight()
+            }
+        }
+    }
+
+    fn schedule(id: i64, action: ScheduleAction, limit_mbps: Option<f64>, days: &[Weekday], start: u32, end: u32) -> Schedule {
+        Schedule {
+            id,
+            name: format!("schedule {}", id),
+            target: ScheduleTarget::Device,
+            target_id: "aa_bb_cc_00_00_01".to_string(),
+            action,
+            limit_mbps,
+            days: days.to_vec(),
+            start_minute: start,
+            end_minute: end,
+            enabled: true,
+            created_at: 0,
+        }
+    }
+
+    fn hours(hours: u32) -> u32 {
+        hours * 60
+    }
+
+    // 1 January 2024 is a Monday
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn window_past_midnight_runs_into_the_next_day() {
+        let night = schedule(1, ScheduleAction::Cut, None, &[Weekday::Mon], hours(22), hours(6));
+
+        assert!(!is_active(&night, &at(1, 21, 59)));
+        assert!(is_active(&night, &at(1, 22, 0)));
+        assert!(is_active(&night, &at(2, 5, 59)));
+        assert!(!is_active(&night, &at(2, 6, 0)));
+    }
+
+    #[test]
+    fn end_equal_to_start_is_a_full_day() {
+        let day = schedule(1, ScheduleAction::Cut, None, &[Weekday::Mon], hours(8), hours(8));
+
+        assert!(!is_active(&day, &at(1, 7, 59)));
+        assert!(is_active(&day, &at(1, 8, 0)));
+        assert!(is_active(&day, &at(2, 7, 59)));
+        assert!(!is_active(&day, &at(2, 8, 0)));
+    }
+
+    #[test]
+    fn overnight_window_only_continues_from_a_scheduled_day() {
+        let night = schedule(1, ScheduleAction::Cut, None, &[Weekday::Mon], hours(22), hours(6));
+
+        // Sunday night's window would run into Monday, but Sunday is not scheduled
+        assert!(!is_active(&night, &at(1, 1, 0)));
+        // Monday night's does run into Tuesday
+        assert!(is_active(&night, &at(2, 1, 0)));
+        // And it does not carry on into Wednesday
+        assert!(!is_active(&night, &at(3, 1, 0)));
+    }
+
+    #[test]
+    fn next_boundary_inside_an_overnight_window_is_its_end() {
+        let night = schedule(1, ScheduleAction::Cut, None, &[Weekday::Mon], hours(22), hours(6));
+
+        assert_eq!(next_boundary(&night, &at(2, 1, 0)), Some((at(2, 6, 0), false)));
+        assert_eq!(next_boundary(&night, &at(2, 6, 0)), Some((at(8, 22, 0), true)));
+    }
+
+    #[test]
+    fn start_skipped_by_dst_moves_forward_an_hour() {
+        let sunday = NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+        let early = schedule(1, ScheduleAction::Cut, None, &[Weekday::Sun], hours(2) + 30, hours(5));
+
+        let (start, end) = window_on(&early, sunday, &SpringForward).unwrap();
+        assert_eq!(start.naive_local(), sunday.and_hms_opt(3, 30, 0).unwrap());
+        assert_eq!(end.naive_local(), sunday.and_hms_opt(5, 0, 0).unwrap());
+
+        let local = |hour, minute| to_local(&SpringForward, sunday.and_hms_opt(hour, minute, 0).unwrap()).unwrap();
+        assert!(!is_active(&early, &local(3, 15)));
+        assert!(is_active(&early, &local(3, 30)));
+        assert_eq!(next_boundary(&early, &local(1, 0)), Some((local(3, 30), true)));
+    }
+
+    #[test]
+    fn overlapping_schedules_resolve_by_action_limit_and_age() {
+        let device = "aa_bb_cc_00_00_01";
+        let now = at(1, 12, 0);
+        let all_day = [Weekday::Mon];
+        let resolve = |schedules: &[Schedule]| desired_state(schedules, &[], &now)[device].id;
+
+        // A cut beats a limit, whatever the order
+        let limit = schedule(1, ScheduleAction::Limit, Some(5.0), &all_day, 0, 0);
+        let cut = schedule(2, ScheduleAction::Cut, None, &all_day, 0, 0);
+        assert_eq!(resolve(&[limit.clone(), cut.clone()]), 2);
+        assert_eq!(resolve(&[cut, limit.clone()]), 2);
+
+        // A lower limit beats a higher one
+        let lower = schedule(3, ScheduleAction::Limit, Some(1.0), &all_day, 0, 0);
+        assert_eq!(resolve(&[limit.clone(), lower.clone()]), 3);
+        assert_eq!(resolve(&[lower, limit.clone()]), 3);
+
+        // The older schedule wins a tie
+        let same = schedule(4, ScheduleAction::Limit, Some(5.0), &all_day, 0, 0);
+        assert_eq!(resolve(&[same.clone(), limit.clone()]), 1);
+        assert_eq!(resolve(&[limit, same]), 1);
+    }
+
+    #[test]
+    fn disabled_and_inactive_schedules_are_ignored() {
+        let mut disabled = schedule(1, ScheduleAction::Cut, None, &[Weekday::Mon], 0, 0);
+        disabled.enabled = false;
+        let evening = schedule(2, ScheduleAction::Cut, None, &[Weekday::Mon], hours(18), hours(20));
+
+        assert!(desired_state(&[disabled, evening], &[], &at(1, 12, 0)).is_empty());
+    }
+}
@@ -0,0 +1,332 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use pnet::datalink::{self, Channel, DataLinkSender, NetworkInterface};
+use pnet::packet::arp::{ArpOperations, ArpPacket};
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
+use pnet::packet::Packet;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+/// Requests waiting to be sent; callers wait for room once this is full
+const QUEUE_CAPACITY: usize = 256;
+/// Most requests handled in one pass of the worker
+const MAX_BATCH: usize = 64;
+/// Frames kept in the dry-run log; the oldest are dropped first
+const MAX_DRY_RUN_FRAMES: usize = 2000;
+
+/// Source of the time recorded on dry-run frames
+pub(crate) type Clock = fn() -> DateTime<Utc>;
+
+struct TxRequest {
+    frames: Vec<Vec<u8>>,
+    reply: oneshot::Sender<Result<()>>,
+}
+
+/// Decoded fields of an ARP frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArpFields {
+    pub operation: String,  // "request" or "reply"
+    pub sender_mac: String,
+    pub sender_ip: String,
+    pub target_mac: String,
+    pub target_ip: String,
+}
+
+/// A frame that would have been sent
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRunFrame {
+    pub seq: u64,     // Position in the log since dry-run was enabled
+    pub batch: u64,   // Frames handed over in one call share a batch
+    pub destination: String,
+    pub source: String,
+    pub arp: Option<ArpFields>,
+    pub length: usize,
+    pub offset_ms: u64,  // Time since dry-run was enabled
+    pub timestamp: DateTime<Utc>,
+}
+
+struct DryRunLog {
+    clock: Clock,
+    started: DateTime<Utc>,
+    next_seq: u64,
+    next_batch: u64,
+    frames: VecDeque<DryRunFrame>,
+}
+
+/// Handle to the worker that owns the interface's transmit channel. Every
+/// ARP frame we send goes through it, so the channel is opened once rather
+/// than per packet. In dry-run mode frames are logged instead of sent.
+#[derive(Clone)]
+pub struct Transmitter {
+    queue: mpsc::Sender<TxRequest>,
+    dry_run: Arc<Mutex<Option<DryRunLog>>>,
+}
+
+impl Transmitter {
+    pub fn new(interface: NetworkInterface) -> Self {
+        let (queue, requests) = mpsc::channel(QUEUE_CAPACITY);
+
+        std::thread::Builder::new()
+            .name("arp-transmit".to_string())
+            .spawn(move || run_worker(interface, requests))
+            .expect("failed to spawn transmit worker");
+
+        Self {
+            queue,
+            dry_run: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Send frames in order, returning once they have been handed to the
+    /// interface or the first one failed
+    pub async fn send(&self, frames: Vec<Vec<u8>>) -> Result<()> {
+        if let Some(log) = self.dry_run.lock().await.as_mut() {
+            log.record(&frames);
+            return Ok(());
+        }
+
+        let (reply, result) = oneshot::channel();
+        self.queue.send(TxRequest { frames, reply }).await
+            .map_err(|_| anyhow::anyhow!("Transmit worker has stopped"))?;
+
+        result.await
+            .map_err(|_| anyhow::anyhow!("Transmit worker dropped the request"))?
+    }
+
+    /// Switch dry-run mode. Enabling it starts a fresh log.
+    pub async fn set_dry_run(&self, enabled: bool) {
+        self.switch_dry_run(enabled, Utc::now).await
+    }
+
+    /// Switch dry-run mode, timing logged frames with `clock`
+    pub(crate) async fn switch_dry_run(&self, enabled: bool, clock: Clock) {
+        let mut dry_run = self.dry_run.lock().await;
+        if enabled == dry_run.is_some() {
+            return;
+        }
+        *dry_run = enabled.then(|| DryRunLog {
+            clock,
+            started: clock(),
+            next_seq: 0,
+            next_batch: 0,
+            frames: VecDeque::new(),
+        });
+    }
+
+    pub async fn is_dry_run(&self) -> bool {
+        self.dry_run.lock().await.is_some()
+    }
+
+    /// Frames logged in dry-run mode, oldest first
+    pub async fn dry_run_frames(&self) -> Vec<DryRunFrame> {
+        match self.dry_run.lock().await.as_ref() {
+            Some(log) => log.frames.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub async fn clear_dry_run_frames(&self) {
+        if let Some(log) = self.dry_run.lock().await.as_mut() {
+            log.frames.clear();
+        }
+    }
+}
+
+impl DryRunLog {
+    fn record(&mut self, frames: &[Vec<u8>]) {
+        let batch = self.next_batch;
+        self.next_batch += 1;
+        let now = (self.clock)();
+
+        for frame in frames {
+            let (destination, source) = EthernetPacket::new(frame)
+                .map(|e| (e.get_destination().to_string(), e.get_source().to_string()))
+                .unwrap_or_default();
+
+            self.frames.push_back(DryRunFrame {
+                seq: self.next_seq,
+                batch,
+                destination,
+                source,
+                arp: decode_arp(frame),
+                length: frame.len(),
+                offset_ms: (now - self.started).num_milliseconds().max(0) as u64,
+                timestamp: now,
+            });
+            self.next_seq += 1;
+
+            if self.frames.len() > MAX_DRY_RUN_FRAMES {
+                self.frames.pop_front();
+            }
+        }
+    }
+}
+
+fn decode_arp(frame: &[u8]) -> Option<ArpFields> {
+    let ethernet = EthernetPacket::new(frame)?;
+    if ethernet.get_ethertype() != EtherTypes::Arp {
+        return None;
+    }
+    let arp = ArpPacket::new(ethernet.payload())?;
+
+    let operation = match arp.get_operation() {
+        ArpOperations::Request => "request".to_string(),
+        ArpOperations::Reply => "reply".to_string(),
+        other => other.0.to_string(),
+    };
+    Some(ArpFields {
+        operation,
+        sender_mac: arp.get_sender_hw_addr().to_string(),
+        sender_ip: arp.get_sender_proto_addr().to_string(),
+        target_mac: arp.get_target_hw_addr().to_string(),
+        target_ip: arp.get_target_proto_addr().to_string(),
+    })
+}
+
+/// Runs until every `Transmitter` handle is dropped. A channel that fails is
+/// closed and opened again for the next batch.
+fn run_worker(interface: NetworkInterface, mut requests: mpsc::Receiver<TxRequest>) {
+    let mut channel: Option<Box<dyn DataLinkSender>> = None;
+
+    while let Some(first) = requests.blocking_recv() {
+        let mut batch = vec![first];
+        while batch.len() < MAX_BATCH {
+            match requests.try_recv() {
+                Ok(request) => batch.push(request),
+                Err(_) => break,
+            }
+        }
+
+        if channel.is_none() {
+            match open_channel(&interface) {
+                Ok(tx) => channel = Some(tx),
+                Err(e) => {
+                    log::warn!("Could not open transmit channel on {}: {}", interface.name, e);
+                    for request in batch {
+                        // The caller may have given up waiting; nothing to report to then
+                        let _ = request.reply.send(Err(anyhow::anyhow!("Failed to create channel: {}", e)));
+                    }
+                    continue;
+                }
+            }
+        }
+
+        for request in batch {
+            let result = match channel.as_mut() {
+                Some(tx) => send_frames(tx.as_mut(), &request.frames),
+                None => Err(anyhow::anyhow!("Transmit channel is closed")),
+            };
+            if result.is_err() {
+                channel = None;
+            }
+            let _ = request.reply.send(result);
+        }
+    }
+
+    log::info!("Transmit worker for {} stopped", interface.name);
+}
+
+fn open_channel(interface: &NetworkInterface) -> Result<Box<dyn DataLinkSender>> {
+    match datalink::channel(interface, Default::default()) {
+        Ok(Channel::Ethernet(tx, _)) => Ok(tx),
+        Ok(_) => Err(anyhow::anyhow!("Unhandled channel type")),
+        Err(e) => Err(anyhow::anyhow!("{}", e)),
+    }
+}
+
+fn send_frames(tx: &mut dyn DataLinkSender, frames: &[Vec<u8>]) -> Result<()> {
+    for frame in frames {
+        match tx.send_to(frame, None) {
+            Some(Ok(())) => {}
+            Some(Err(e)) => return Err(anyhow::anyhow!("Failed to send packet: {}", e)),
+            None => return Err(anyhow::anyhow!("Failed to send packet")),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::ipnetwork::{IpNetwork, Ipv4Network};
+    use pnet::util::MacAddr;
+    use std::net::Ipv4Addr;
+
+    fn interface() -> NetworkInterface {
+        NetworkInterface {
+            name: "test0".to_string(),
+            description: String::new(),
+            index: 0,
+            mac: Some(MacAddr(0x02, 0, 0, 0, 0, 0x01)),
+            ips: vec![IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 1, 2), 24).unwrap())],
+            flags: 0,
+        }
+    }
+
+    fn epoch() -> DateTime<Utc> {
+        DateTime::UNIX_EPOCH
+    }
+
+    fn arp_frame(operation: u16, sender: [u8; 6], sender_ip: [u8; 4], target: [u8; 6], target_ip: [u8; 4]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(42);
+        frame.extend_from_slice(&target);
+        frame.extend_from_slice(&sender);
+        frame.extend_from_slice(&[0x08, 0x06, 0x00, 0x01, 0x08, 0x00, 6, 4]);
+        frame.extend_from_slice(&operation.to_be_bytes());
+        frame.extend_from_slice(&sender);
+        frame.extend_from_slice(&sender_ip);
+        frame.extend_from_slice(&target);
+        frame.extend_from_slice(&target_ip);
+        frame
+    }
+
+    #[tokio::test]
+    async fn dry_run_logs_frames_in_order() {
+        let transmitter = Transmitter::new(interface());
+        transmitter.switch_dry_run(true, epoch).await;
+
+        let ours = [0x02, 0, 0, 0, 0, 0x01];
+        let device = [0x02, 0, 0, 0, 0, 0x10];
+        transmitter.send(vec![
+            arp_frame(2, ours, [192, 168, 1, 1], device, [192, 168, 1, 10]),
+            arp_frame(1, ours, [192, 168, 1, 2], [0xff; 6], [192, 168, 1, 1]),
+        ]).await.unwrap();
+        transmitter.send(vec![vec![0u8; 14]]).await.unwrap();
+
+        let frames = transmitter.dry_run_frames().await;
+        let order: Vec<(u64, u64)> = frames.iter().map(|f| (f.seq, f.batch)).collect();
+        assert_eq!(order, vec![(0, 0), (1, 0), (2, 1)]);
+        assert!(frames.iter().all(|f| f.offset_ms == 0 && f.timestamp == epoch()));
+
+        assert_eq!(frames[0].destination, "02:00:00:00:00:10");
+        assert_eq!(frames[0].arp, Some(ArpFields {
+            operation: "reply".to_string(),
+            sender_mac: "02:00:00:00:00:01".to_string(),
+            sender_ip: "192.168.1.1".to_string(),
+            target_mac: "02:00:00:00:00:10".to_string(),
+            target_ip: "192.168.1.10".to_string(),
+        }));
+        assert_eq!(frames[1].arp.as_ref().map(|a| a.operation.as_str()), Some("request"));
+        assert_eq!(frames[2].arp, None);
+    }
+
+    #[tokio::test]
+    async fn enabling_dry_run_starts_a_fresh_log() {
+        let transmitter = Transmitter::new(interface());
+        transmitter.switch_dry_run(true, epoch).await;
+        transmitter.send(vec![vec![0u8; 14]]).await.unwrap();
+
+        transmitter.set_dry_run(false).await;
+        assert!(transmitter.dry_run_frames().await.is_empty());
+
+        transmitter.switch_dry_run(true, epoch).await;
+        transmitter.send(vec![vec![0u8; 14]]).await.unwrap();
+        let frames = transmitter.dry_run_frames().await;
+        assert_eq!(frames.len(), 1);
+        assert_eq!((frames[0].seq, frames[0].batch), (0, 0));
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::Result;
+use pnet::datalink::{self, Channel, Config, DataLinkReceiver, NetworkInterface};
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::Packet;
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::sync::Mutex;
+
+/// How long traffic is watched after a cut or restore
+const WATCH_WINDOW: Duration = Duration::from_secs(20);
+/// Time for the target's ARP cache to take the new entries before traffic
+/// still going the old way counts against the action
+const SETTLE_TIME: Duration = Duration::from_secs(3);
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifiedAction {
+    Cut,
+    Restore,
+}
+
+impl VerifiedAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VerifiedAction::Cut => "cut",
+            VerifiedAction::Restore => "restore",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationStatus {
+    Confirmed,    // The device's traffic goes where the action sent it
+    Unconfirmed,  // No traffic from the device seen yet
+    Failing,      // The device's traffic still goes the old way
+    TimedOut,     // Nothing confirmed a restore in time; the device may still be cut
+}
+
+impl VerificationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VerificationStatus::Confirmed => "confirmed",
+            VerificationStatus::Unconfirmed => "unconfirmed",
+            VerificationStatus::Failing => "failing",
+            VerificationStatus::TimedOut => "timed_out",
+        }
+    }
+}
+
+/// Outcome of the last cut or restore of a device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Verification {
+    pub action: VerifiedAction,
+    pub status: VerificationStatus,
+}
+
+struct Watch {
+    verification: Verification,
+    gateway_mac: MacAddr,
+    started: Instant,
+}
+
+/// Confirms cuts and restores from captured traffic. After a cut the
+/// target's gateway-bound frames should be addressed to our MAC; after a
+/// restore they should not be any more.
+#[derive(Clone)]
+pub struct Verifier {
+    interface: NetworkInterface,
+    our_mac: MacAddr,
+    our_ip: Ipv4Addr,
+    watches: Arc<Mutex<HashMap<String, Watch>>>,  // Keyed by device MAC
+    running: Arc<Mutex<bool>>,
+}
+
+impl Verifier {
+    pub fn new(interface: NetworkInterface) -> Result<Self> {
+        let our_mac = interface.mac.ok_or_else(|| anyhow::anyhow!("Interface has no MAC address"))?;
+        let our_ip = interface
+            .ips
+            .iter()
+            .find_map(|ip| match ip.ip() {
+                IpAddr::V4(ipv4) => Some(ipv4),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow::anyhow!("No IPv4 address found"))?;
+
+        Ok(Self {
+            interface,
+            our_mac,
+            our_ip,
+            watches: Arc::new(Mutex::new(HashMap::new())),
+            running: Arc::new(Mutex::new(false)),
+        })
+    }
+
+    /// Start watching a device after `action`, replacing the outcome of
+    /// any earlier action
+    pub async fn watch(&self, target_mac: &str, gateway_mac: &str, action: VerifiedAction) -> Result<()> {
+        let target: MacAddr = target_mac.parse()
+            .map_err(|_| anyhow::anyhow!("Invalid MAC address {}", target_mac))?;
+        let gateway_mac = gateway_mac.parse()
+            .map_err(|_| anyhow::anyhow!("Invalid gateway MAC address {}", gateway_mac))?;
+
+        let mut watches = self.watches.lock().await;
+        watches.insert(target.to_string(), Watch {
+            verification: Verification { action, status: VerificationStatus::Unconfirmed },
+            gateway_mac,
+            started: Instant::now(),
+        });
+        drop(watches);
+
+        self.start().await
+    }
+
+    /// Give up on confirming a restore that nothing has confirmed yet, so
+    /// the device shows a warning. Traffic seen later still confirms it.
+    pub async fn time_out_restore(&self, target_mac: &str) {
+        let mut watches = self.watches.lock().await;
+        let Some(watch) = watches.get_mut(target_mac) else {
+            return;
+        };
+        let verification = &mut watch.verification;
+        if verification.action == VerifiedAction::Restore && verification.status == VerificationStatus::Unconfirmed {
+            verification.status = VerificationStatus::TimedOut;
+        }
+    }
+
+    pub async fn get_verifications(&self) -> HashMap<String, Verification> {
+        let watches = self.watches.lock().await;
+        watches.iter().map(|(mac, w)| (mac.clone(), w.verification.clone())).collect()
+    }
+
+    /// Start capturing if it is not already running. The loop stops by
+    /// itself once every watch window has closed.
+    async fn start(&self) -> Result<()> {
+        let mut running = self.running.lock().await;
+        if *running {
+            return Ok(());
+        }
+
+        let config = Config {
+            read_timeout: Some(READ_TIMEOUT),
+            ..Default::default()
+        };
+        let rx = match datalink::channel(&self.interface, config) {
+            Ok(Channel::Ethernet(_, rx)) => rx,
+            Ok(_) => return Err(anyhow::anyhow!("Unhandled channel type")),
+            Err(e) => return Err(anyhow::anyhow!("Failed to create channel: {}", e)),
+        };
+        *running = true;
+        drop(running);
+
+        // Receiving blocks, so the loop gets its own thread
+        let verifier = self.clone();
+        let handle = Handle::current();
+        tokio::task::spawn_blocking(move || handle.block_on(verifier.run(rx)));
+        Ok(())
+    }
+
+    async fn run(self, mut rx: Box<dyn DataLinkReceiver>) {
+        let mut last_check = Instant::now();
+
+        loop {
+            if last_check.elapsed() >= CHECK_INTERVAL {
+                let mut running = self.running.lock().await;
+                let watching = self.watches.lock().await.values().any(|w| w.started.elapsed() < WATCH_WINDOW);
+                if !watching {
+                    *running = false;
+                    break;
+                }
+                last_check = Instant::now();
+            }
+
+            match rx.next() {
+                Ok(frame) => self.inspect(frame).await,
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {}
+                Err(e) => log::error!("Error receiving packet to verify: {}", e),
+            }
+        }
+
+        log::debug!("Cut verification capture stopped");
+    }
+
+    async fn inspect(&self, frame: &[u8]) {
+        let Some(ethernet) = EthernetPacket::new(frame) else {
+            return;
+        };
+        if ethernet.get_ethertype() != EtherTypes::Ipv4 {
+            return;
+        }
+        let Some(ipv4) = Ipv4Packet::new(ethernet.payload()) else {
+            return;
+        };
+
+        // Only traffic that would go through the gateway tells us anything
+        let dest_ip = ipv4.get_destination();
+        if dest_ip == self.our_ip || dest_ip.is_broadcast() || dest_ip.is_multicast() {
+            return;
+        }
+
+        let mut watches = self.watches.lock().await;
+        let Some(watch) = watches.get_mut(&ethernet.get_source().to_string()) else {
+            return;
+        };
+        if watch.started.elapsed() >= WATCH_WINDOW {
+            return;
+        }
+
+        let to_us = ethernet.get_destination() == self.our_mac;
+        let to_gateway = ethernet.get_destination() == watch.gateway_mac;
+        let settled = watch.started.elapsed() >= SETTLE_TIME;
+
+        let status = match watch.verification.action {
+            VerifiedAction::Cut if to_us => VerificationStatus::Confirmed,
+            VerifiedAction::Cut if to_gateway && settled => VerificationStatus::Failing,
+            VerifiedAction::Restore if to_gateway => VerificationStatus::Confirmed,
+            VerifiedAction::Restore if to_us && settled => VerificationStatus::Failing,
+            _ => return,
+        };
+        if watch.verification.status != status {
+            log::info!("{} of {} is {}", watch.verification.action.as_str(), ethernet.get_source(), status.as_str());
+            watch.verification.status = status;
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::Result;
+use pnet::datalink;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::interval;
+use crate::utils::safety::device_id_for_mac;
+use crate::modules::arp_controller::ArpController;
+use crate::modules::audit::{self, AuditOutcome};
+use crate::modules::database::{AuditRecord, Database, EventType};
+
+const WATCHDOG_INTERVAL_SECS: u64 = 5;
+/// The spoofing loop ticks every second; this much silence means it is stuck
+const MAX_TICK_AGE: Duration = Duration::from_secs(15);
+/// Consecutive unanswered gateway probes before giving up on spoofing
+const MAX_GATEWAY_FAILURES: u32 = 3;
+
+/// Restores every device when spoofing seems to be hurting us: our own
+/// interface went down, the gateway stopped answering us, or the spoofing
+/// loop stopped keeping up. Only checks while something is redirected.
+#[derive(Clone)]
+pub struct Watchdog {
+    arp_controller: Arc<Mutex<ArpController>>,
+    database: Arc<Mutex<Database>>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl Watchdog {
+    pub fn new(arp_controller: Arc<Mutex<ArpController>>, database: Arc<Mutex<Database>>) -> Self {
+        Self {
+            arp_controller,
+            database,
+            running: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut running = self.running.lock().await;
+        if *running {
+            return Ok(());
+        }
+        *running = true;
+        drop(running);
+
+        let watchdog = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = interval(Duration::from_secs(WATCHDOG_INTERVAL_SECS));
+            let mut gateway_failures = 0;
+
+            loop {
+                ticker.tick().await;
+
+                // Check if we should stop
+                {
+                    let running = watchdog.running.lock().await;
+                    if !*running {
+                        break;
+                    }
+                }
+
+                if let Some(reason) = watchdog.check(&mut gateway_failures).await {
+                    watchdog.trip(&reason).await;
+                    gateway_failures = 0;
+                }
+            }
+
+            log::info!("Connectivity watchdog stopped");
+        });
+
+        log::info!("Connectivity watchdog started");
+        Ok(())
+    }
+
+    pub async fn stop(&self) -> Result<()> {
+        let mut running = self.running.lock().await;
+        *running = false;
+        Ok(())
+    }
+
+    /// Why every device should be restored, if it should
+    async fn check(&self, gateway_failures: &mut u32) -> Option<String> {
+        let health = self.arp_controller.lock().await.health().await;
+        if health.redirected == 0 {
+            *gateway_failures = 0;
+            return None;
+        }
+
+        if health.loop_running && health.last_tick.is_some_and(|t| t.elapsed() > MAX_TICK_AGE) {
+            return Some(format!("spoofing loop has not run for over {} seconds", MAX_TICK_AGE.as_secs()));
+        }
+
+        // Nothing reaches the gateway in dry-run mode, so there is no
+        // connectivity to check
+        let probe = health.probe.filter(|_| !health.dry_run)?;
+
+        let interface_up = datalink::interfaces()
+            .into_iter()
+            .find(|i| i.name == probe.interface.name)
+            .is_some_and(|i| i.is_up() && i.ips.iter().any(|ip| ip.is_ipv4()));
+        if !interface_up {
+            return Some(format!("interface {} lost its connection", probe.interface.name));
+        }
+
+        match probe.run().await {
+            Ok(_) => *gateway_failures = 0,
+            Err(e) => {
+                *gateway_failures += 1;
+                log::warn!("Watchdog gateway check failed ({} of {}): {}", gateway_failures, MAX_GATEWAY_FAILURES, e);
+                if *gateway_failures >= MAX_GATEWAY_FAILURES {
+                    return Some(format!("gateway {} stopped answering", probe.gateway_ip));
+                }
+            }
+        }
+
+        None
+    }
+
+    async fn trip(&self, reason: &str) {
+        log::error!("Watchdog restoring every device: {}", reason);
+
+        let restored = {
+            let arp = self.arp_controller.lock().await;
+            arp.restore_all().await
+        };
+
+        let database = self.database.lock().await;
+        let (outcome, count) = match &restored {
+            Ok(spoofs) => {
+                for spoof in spoofs {
+                    let device_id = device_id_for_mac(&spoof.target_mac);
+                    let details = serde_json::json!({ "ip": spoof.target_ip.to_string(), "reason": "watchdog" });
+                    if let Err(e) = database.record_event(EventType::DeviceRestored, &device_id, Some(details.to_string())).await {
+                        log::warn!("Failed to record device_restored event for {}: {}", device_id, e);
+                    }
+                }
+                (AuditOutcome::Succeeded, spoofs.len())
+            }
+            Err(e) => {
+                log::error!("Watchdog could not restore devices: {}", e);
+                (AuditOutcome::Failed, 0)
+            }
+        };
+
+        // Events belong to devices, so the trip itself is only audited
+        let details = serde_json::json!({ "restored": count });
+        audit::record(&database, AuditRecord {
+            actor: "watchdog".to_string(),
+            action: "restore_all".to_string(),
+            target: "all".to_string(),
+            reason: Some(reason.to_string()),
+            outcome: outcome.as_str().to_string(),
+            details: Some(details.to_string()),
+        }).await;
+    }
+}
@@ -8,7 +8,6 @@ pub struct SafetyCheck {
     pub reason: Option<String>,
 }
 
-#[allow(dead_code)]
 pub fn check_self_cut(target_ip: &str) -> SafetyCheck {
     // Get current machine's IPs
     let interfaces = datalink::interfaces();
@@ -30,7 +29,6 @@ pub fn check_self_cut(target_ip: &str) -> SafetyCheck {
     }
 }
 
-#[allow(dead_code)]
 pub fn check_gateway_cut(target_ip: &str, gateway_ip: &str) -> SafetyCheck {
     if target_ip == gateway_ip {
         SafetyCheck {
@@ -72,7 +70,6 @@ pub fn is_private_ip(ip: &str) -> bool {
     }
 }
 
-#[allow(dead_code)]
 pub fn validate_mac_address(mac: &str) -> bool {
     // MAC address format: XX:XX:XX:XX:XX:XX
     let parts: Vec<&str> = mac.split(':').collect();
@@ -91,13 +88,30 @@ pub fn validate_mac_address(mac: &str) -> bool {
     }
 
     // Check for invalid MACs
-    if mac == "00:00:00:00:00:00" || mac == "FF:FF:FF:FF:FF:FF" {
+    if mac == "00:00:00:00:00:00" || mac.eq_ignore_ascii_case("FF:FF:FF:FF:FF:FF") {
         return false;
     }
 
     true
 }
 
+/// Normalise a MAC address to the lowercase, colon-separated form MACs are
+/// compared in, or `None` if it is not a valid address
+pub fn normalize_mac(mac: &str) -> Option<String> {
+    let mac = mac.trim().replace('-', ":").to_lowercase();
+    validate_mac_address(&mac).then_some(mac)
+}
+
+/// The ID a device is stored and shown under, derived from its MAC
+pub fn device_id_for_mac(mac: &str) -> String {
+    mac.replace(':', "_").to_lowercase()
+}
+
+/// The MAC a device ID was derived from, in the form MACs are compared in
+pub fn mac_for_device_id(device_id: &str) -> String {
+    device_id.replace('_', ":")
+}
+
 #[allow(dead_code)]
 pub fn is_multicast_mac(mac: &str) -> bool {
     if let Some(first_octet) = mac.split(':').next() {